@@ -72,6 +72,7 @@ pub fn setup(
     )));
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn update(
     keys: EventReader<CrosstermKeyEventWrapper>,
     state: ResMut<State<GameState>>,
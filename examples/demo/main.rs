@@ -69,31 +69,46 @@ pub fn main() {
             Update,
             just_wait_and_advance.run_if(in_state(GameState::Title)),
         )
-        .add_systems(OnExit(GameState::Title), simple_teardown)
+        .add_systems(
+            OnExit(GameState::Title),
+            (simple_teardown, request_full_redraw),
+        )
         .add_systems(OnEnter(GameState::Sprites), sprites::setup)
         .add_systems(
             Update,
             just_wait_and_advance.run_if(in_state(GameState::Sprites)),
         )
-        .add_systems(OnExit(GameState::Sprites), simple_teardown)
+        .add_systems(
+            OnExit(GameState::Sprites),
+            (simple_teardown, request_full_redraw),
+        )
         .add_systems(OnEnter(GameState::Colors), colors::setup)
         .add_systems(
             Update,
             just_wait_and_advance.run_if(in_state(GameState::Colors)),
         )
-        .add_systems(OnExit(GameState::Colors), simple_teardown)
+        .add_systems(
+            OnExit(GameState::Colors),
+            (simple_teardown, request_full_redraw),
+        )
         .add_systems(OnEnter(GameState::Animation), animation::setup)
         .add_systems(
             Update,
             animation::update.run_if(in_state(GameState::Animation)),
         )
-        .add_systems(OnExit(GameState::Animation), simple_teardown)
+        .add_systems(
+            OnExit(GameState::Animation),
+            (simple_teardown, request_full_redraw),
+        )
         .add_systems(OnEnter(GameState::Finale), finale::setup)
         .add_systems(
             Update,
             just_wait_and_advance.run_if(in_state(GameState::Finale)),
         )
-        .add_systems(OnExit(GameState::Finale), simple_teardown)
+        .add_systems(
+            OnExit(GameState::Finale),
+            (simple_teardown, request_full_redraw),
+        )
         .run();
 }
 
@@ -27,13 +27,13 @@ pub fn setup(
     let big_box_sprite = sprites.get(&big_box_handle).unwrap();
     let big_box_pos = Position::with_xy(
         window.width() as i32 / 4 * 3 - big_box_sprite.width() as i32 - MARGIN,
-        window.height() as i32 / 10 * 1,
+        window.height() as i32 / 10,
     );
 
     let small_box_handle: Handle<Sprite> = asset_server.get_handle("demo/small_box.txt").unwrap();
     let small_box_pos = Position::with_xy(
         window.width() as i32 / 4 * 3 + MARGIN,
-        window.height() as i32 / 10 * 1 + 1,
+        window.height() as i32 / 10 + 1,
     );
 
     let big_combo_pos = Position::with_xy(
@@ -14,7 +14,7 @@ pub fn setup(
     let title_sprite = sprites.get(&title_handle).unwrap();
     let title_pos = Position::with_xy(
         window.x_center() as i32 - title_sprite.x_center() as i32,
-        window.height() as i32 / 10 * 1,
+        window.height() as i32 / 10,
     );
 
     let welcome_sprite = Sprite::new("Welcome to the bevy_crossterm demo!");
@@ -48,12 +48,20 @@ impl AssetLoader for StyleMapLoader {
         &'a self,
         reader: &'a mut Reader,
         _settings: &'a Self::Settings,
-        _load_context: &'a mut LoadContext,
+        load_context: &'a mut LoadContext,
     ) -> BoxedFuture<'a, Result<Self::Asset, anyhow::Error>> {
         Box::pin(async move {
             let mut bytes = Vec::new();
             reader.read_to_end(&mut bytes).await?;
-            let stylemap = ron::de::from_bytes::<StyleMap>(&bytes)?;
+            let stylemap = ron::de::from_bytes::<StyleMap>(&bytes).map_err(|error| {
+                anyhow::anyhow!(
+                    "{}, line {}, column {}: {}",
+                    load_context.path().display(),
+                    error.position.line,
+                    error.position.col,
+                    error.code
+                )
+            })?;
             Ok(stylemap)
         })
     }
@@ -62,3 +70,281 @@ impl AssetLoader for StyleMapLoader {
         &["stylemap"]
     }
 }
+
+/// How `ImageLoader` turns an image into a sprite
+#[cfg(feature = "image")]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum ImageMode {
+    /// Each cell is a character picked by how bright that part of the image is. The sprite isn't
+    /// colored.
+    #[default]
+    Ascii,
+    /// Each cell is a `▀` which is colored to show two pixels, one above the other. The colors are
+    /// available as a labeled `StyleMap` asset, by loading `<path>#stylemap`. The terminal has to
+    /// support 24-bit color.
+    HalfBlock,
+}
+
+/// Settings for loading an image as a sprite, which can be changed with
+/// `AssetServer::load_with_settings`
+#[cfg(feature = "image")]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ImageLoaderSettings {
+    /// The width of the sprite, in cells
+    pub width: u16,
+    /// The height of the sprite, in cells. If this is `None`, it's picked to keep the image's aspect
+    /// ratio, taking into account that cells are about twice as tall as they are wide.
+    pub height: Option<u16>,
+    pub mode: ImageMode,
+    /// Characters used by `ImageMode::Ascii`, from darkest to brightest
+    pub ramp: String,
+}
+
+#[cfg(feature = "image")]
+impl Default for ImageLoaderSettings {
+    fn default() -> Self {
+        ImageLoaderSettings {
+            width: 40,
+            height: None,
+            mode: ImageMode::Ascii,
+            ramp: " .:-=+*#%@".to_string(),
+        }
+    }
+}
+
+/// Loads png, jpeg, gif and bmp files as sprites
+#[cfg(feature = "image")]
+#[derive(Default)]
+pub struct ImageLoader;
+
+#[cfg(feature = "image")]
+impl AssetLoader for ImageLoader {
+    type Asset = Sprite;
+    type Settings = ImageLoaderSettings;
+    type Error = anyhow::Error;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        settings: &'a Self::Settings,
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, anyhow::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            let image = image::load_from_memory(&bytes)?;
+
+            let width = std::cmp::max(settings.width, 1) as u32;
+            let height = match settings.height {
+                Some(height) => std::cmp::max(height, 1) as u32,
+                None => {
+                    let aspect = image.height() as f32 / std::cmp::max(image.width(), 1) as f32;
+                    std::cmp::max((width as f32 * aspect / 2.0).round() as u32, 1)
+                }
+            };
+
+            let (sprite, stylemap) = match settings.mode {
+                ImageMode::Ascii => (image_to_ascii(&image, width, height, &settings.ramp), None),
+                ImageMode::HalfBlock => {
+                    let (sprite, stylemap) = image_to_half_blocks(&image, width, height);
+                    (sprite, Some(stylemap))
+                }
+            };
+
+            if let Some(stylemap) = stylemap {
+                load_context.add_labeled_asset("stylemap".to_string(), stylemap);
+            }
+            Ok(sprite)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["png", "jpg", "jpeg", "gif", "bmp"]
+    }
+}
+
+#[cfg(feature = "image")]
+fn image_to_ascii(image: &image::DynamicImage, width: u32, height: u32, ramp: &str) -> Sprite {
+    let ramp: Vec<char> = ramp.chars().collect();
+    let ramp = if ramp.is_empty() { vec![' '] } else { ramp };
+
+    let scaled = image
+        .resize_exact(width, height, image::imageops::FilterType::Triangle)
+        .to_luma_alpha8();
+
+    let mut data = String::new();
+    for y in 0..height {
+        if y > 0 {
+            data.push('\n');
+        }
+        for x in 0..width {
+            let image::LumaA([luma, alpha]) = *scaled.get_pixel(x, y);
+            // Treat transparent parts of the image as black, so they end up as blank space
+            let brightness = luma as f32 * alpha as f32 / (255.0 * 255.0);
+            let idx = (brightness * (ramp.len() - 1) as f32).round() as usize;
+            data.push(ramp[idx]);
+        }
+    }
+
+    Sprite::new(data)
+}
+
+#[cfg(feature = "image")]
+fn image_to_half_blocks(
+    image: &image::DynamicImage,
+    width: u32,
+    height: u32,
+) -> (Sprite, StyleMap) {
+    use crate::components::{Color, Colors, Style};
+
+    let scaled = image
+        .resize_exact(width, height * 2, image::imageops::FilterType::Triangle)
+        .to_rgba8();
+    let color = |x, y| {
+        let image::Rgba([r, g, b, a]) = *scaled.get_pixel(x, y);
+        // Transparent pixels are left to the window's colors
+        if a < 128 {
+            None
+        } else {
+            Some(Color::Rgb { r, g, b })
+        }
+    };
+
+    let mut data = String::new();
+    let mut map = Vec::with_capacity(height as usize);
+    for y in 0..height {
+        if y > 0 {
+            data.push('\n');
+        }
+        let mut row = Vec::with_capacity(width as usize);
+        for x in 0..width {
+            let (block, colors) = match (color(x, y * 2), color(x, y * 2 + 1)) {
+                (Some(top), bottom) => (
+                    '▀',
+                    Colors {
+                        foreground: Some(top),
+                        background: bottom,
+                    },
+                ),
+                // A background would fill the transparent top half too, so draw the bottom half instead
+                (None, Some(bottom)) => ('▄', Colors::fg(bottom)),
+                (None, None) => (' ', Colors::default()),
+            };
+            data.push(block);
+            row.push(Style::with_colors(colors));
+        }
+        map.push(row);
+    }
+
+    (Sprite::new(data), StyleMap::new(Style::default(), map))
+}
+
+#[cfg(all(test, feature = "image"))]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::*;
+    use crate::testing;
+
+    /// 16x8 pixels, opaque white on the left half and transparent on the right
+    const FIXTURE: &str = "fixtures/half_white.png";
+    /// 8x4 pixels, transparent except for an opaque white second row
+    const EDGE_FIXTURE: &str = "fixtures/bottom_edge.png";
+
+    fn load<A: Asset>(app: &mut App, path: &str, width: u16, mode: ImageMode) -> Handle<A> {
+        let handle = app.world.resource::<AssetServer>().load_with_settings(
+            path.to_string(),
+            move |settings: &mut ImageLoaderSettings| {
+                settings.width = width;
+                settings.mode = mode;
+            },
+        );
+        // Loading happens on another thread, so give it a moment
+        for _ in 0..200 {
+            app.update();
+            if app.world.resource::<Assets<A>>().contains(&handle) {
+                return handle;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        panic!("{path} didn't load");
+    }
+
+    #[test]
+    fn ascii_images_keep_their_aspect_ratio() {
+        let (mut app, _) = testing::app(20, 10);
+        let handle = load(&mut app, FIXTURE, 8, ImageMode::Ascii);
+        let sprite = app.world.resource::<Assets<Sprite>>().get(&handle).unwrap();
+
+        // Cells are twice as tall as they're wide, so 8 columns of a 2:1 image only need 2 rows
+        assert_eq!((sprite.width(), sprite.height()), (8, 2));
+        for row in sprite.data().lines() {
+            assert!(row.starts_with('@') && row.ends_with(' '), "{row:?}");
+        }
+    }
+
+    #[test]
+    fn half_block_images_come_with_a_stylemap() {
+        let (mut app, _) = testing::app(20, 10);
+        let sprite = load(&mut app, FIXTURE, 8, ImageMode::HalfBlock);
+        let stylemap = load(
+            &mut app,
+            &format!("{FIXTURE}#stylemap"),
+            8,
+            ImageMode::HalfBlock,
+        );
+        let sprite = app.world.resource::<Assets<Sprite>>().get(&sprite).unwrap();
+        assert_eq!((sprite.width(), sprite.height()), (8, 2));
+
+        let stylemap = app.world.resource::<Assets<StyleMap>>().get(&stylemap);
+        let stylemap = stylemap.unwrap();
+        assert_eq!(stylemap.map.len(), 2);
+        assert!(stylemap.map.iter().all(|row| row.len() == 8));
+
+        let white = crate::components::Color::Rgb {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        let colors = stylemap.style_for(0, 1).colors;
+        assert_eq!(
+            (colors.foreground, colors.background),
+            (Some(white), Some(white))
+        );
+        assert_eq!(stylemap.style_for(7, 1).colors.foreground, None);
+
+        // Only the lower half of the top row of cells is opaque, so it mustn't get a background
+        let sprite = load(&mut app, EDGE_FIXTURE, 8, ImageMode::HalfBlock);
+        let stylemap = load(
+            &mut app,
+            &format!("{EDGE_FIXTURE}#stylemap"),
+            8,
+            ImageMode::HalfBlock,
+        );
+        let sprite = app.world.resource::<Assets<Sprite>>().get(&sprite).unwrap();
+        assert_eq!(sprite.data(), "▄▄▄▄▄▄▄▄\n        ");
+        let stylemap = app.world.resource::<Assets<StyleMap>>().get(&stylemap);
+        let colors = stylemap.unwrap().style_for(3, 0).colors;
+        assert_eq!((colors.foreground, colors.background), (Some(white), None));
+        assert_eq!(stylemap.unwrap().style_for(3, 1).colors.background, None);
+    }
+
+    #[test]
+    fn conversions_fill_the_requested_size() {
+        let image = image::DynamicImage::new_rgba8(3, 3);
+        for (width, height) in [(1, 1), (5, 2), (12, 7)] {
+            let sprite = image_to_ascii(&image, width, height, " #");
+            assert_eq!(
+                (sprite.width(), sprite.height()),
+                (width as usize, height as usize)
+            );
+
+            let (sprite, stylemap) = image_to_half_blocks(&image, width, height);
+            assert_eq!(
+                (sprite.width(), sprite.height()),
+                (width as usize, height as usize)
+            );
+            assert_eq!(stylemap.map.len(), height as usize);
+        }
+    }
+}
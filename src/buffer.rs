@@ -0,0 +1,370 @@
+use bevy::prelude::*;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::components::{grapheme_width, Colors, Style};
+
+/// A single cell of the terminal
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Cell {
+    /// Wide graphemes take up two cells. The second cell holds an empty string, and is never
+    /// printed. `CellBuffer::put` keeps the two halves together
+    pub grapheme: String,
+    /// Colors which are left unset use the terminal's default colors, not the window's
+    pub style: Style,
+    /// The URL this cell links to. Only drawn as a link by terminals which support hyperlinks
+    pub link: Option<std::sync::Arc<str>>,
+}
+
+impl Cell {
+    /// An empty cell in the given colors
+    pub fn blank(colors: Colors) -> Cell {
+        Cell {
+            grapheme: " ".to_string(),
+            style: Style::with_colors(colors),
+            link: None,
+        }
+    }
+
+    /// Overwrites this cell, reusing its allocation. Any link it had is removed
+    pub fn set(&mut self, grapheme: &str, style: Style) {
+        if self.grapheme != grapheme {
+            self.grapheme.clear();
+            self.grapheme.push_str(grapheme);
+        }
+        self.style = style;
+        self.link = None;
+    }
+
+    /// Whether this cell is the second half of a wide grapheme
+    pub fn is_continuation(&self) -> bool {
+        self.grapheme.is_empty()
+    }
+}
+
+/// A grid of cells, stored row by row.
+///
+/// The `CellBuffer` resource is the frame that's about to be written to the terminal. It's built
+/// during `CrosstermSet::Compose`, and anything written into it before `CrosstermSet::Flush` shows
+/// up on the screen. Only the cells that differ from what's already on the screen are written.
+#[derive(Clone, Default, Eq, PartialEq, Debug, Resource)]
+pub struct CellBuffer {
+    width: u16,
+    height: u16,
+    cells: Vec<Cell>,
+}
+
+impl CellBuffer {
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// Writes text into the buffer starting at x,y. Wide graphemes take up two cells. Anything that
+    /// doesn't fit on the line is cut off.
+    pub fn put_str(&mut self, x: i32, y: i32, text: &str, style: Style) {
+        let mut x = x;
+        for grapheme in text.graphemes(true) {
+            self.put(x, y, grapheme, style);
+            x += grapheme_width(grapheme) as i32;
+        }
+    }
+
+    /// Writes a single grapheme into the cell at x,y, and the cell after it too if the grapheme is
+    /// wide. A wide grapheme which doesn't fit on the line is drawn as a space instead.
+    ///
+    /// A wide grapheme can't be split in half, so when this covers up only one half of a wide
+    /// grapheme which is already in the buffer, the other half is replaced with a space.
+    pub fn put(&mut self, x: i32, y: i32, grapheme: &str, style: Style) {
+        if self.index(x, y).is_none() {
+            return;
+        }
+        let wide = grapheme_width(grapheme) == 2;
+        let grapheme = if wide && self.index(x + 1, y).is_none() {
+            " "
+        } else {
+            grapheme
+        };
+
+        // Blank out the first half of a wide grapheme this covers the second half of
+        if self.get(x, y).is_some_and(Cell::is_continuation) && self.is_wide(x - 1, y) {
+            self.blank_half(x - 1, y);
+        }
+        let end = if wide && grapheme != " " { x + 1 } else { x };
+        // And the second half of one whose first half is the last cell this covers
+        if self.is_wide(end, y) && self.get(end + 1, y).is_some_and(Cell::is_continuation) {
+            self.blank_half(end + 1, y);
+        }
+
+        self.get_mut(x, y).unwrap().set(grapheme, style);
+        if end != x {
+            self.get_mut(end, y).unwrap().set("", style);
+        }
+    }
+
+    /// Turns what's left of a wide grapheme, which is in the cell at x,y, into a space
+    fn blank_half(&mut self, x: i32, y: i32) {
+        if let Some(cell) = self.get_mut(x, y) {
+            let style = cell.style;
+            cell.set(" ", style);
+        }
+    }
+
+    /// The graphemes in the buffer without any styling, with a newline after each row
+    pub fn to_text(&self) -> String {
+        let mut text = String::with_capacity(self.cells.len() + self.height as usize);
+        for row in self.cells.chunks(std::cmp::max(1, self.width as usize)) {
+            for cell in row {
+                text.push_str(&cell.grapheme);
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Changes the size of the buffer, and blanks out every cell.
+    ///
+    /// The memory for the cells is kept when the buffer shrinks, and the cells that are already there
+    /// are overwritten rather than replaced, so resizing back and forth doesn't allocate.
+    pub(crate) fn resize(&mut self, width: u16, height: u16, blank: &Cell) {
+        self.width = width;
+        self.height = height;
+
+        let len = width as usize * height as usize;
+        self.cells.truncate(len);
+        for cell in &mut self.cells {
+            cell.set(&blank.grapheme, blank.style);
+        }
+        self.cells.resize(len, blank.clone());
+    }
+
+    /// Blanks every cell outside of `area`
+    pub(crate) fn clear_outside(&mut self, area: IRect, blank: &Cell) {
+        if area == IRect::new(0, 0, self.width as i32, self.height as i32) {
+            return;
+        }
+        for y in 0..self.height as i32 {
+            for x in 0..self.width as i32 {
+                if !(area.min.x..area.max.x).contains(&x) || !(area.min.y..area.max.y).contains(&y)
+                {
+                    self.cells[y as usize * self.width as usize + x as usize]
+                        .set(&blank.grapheme, blank.style);
+                }
+            }
+        }
+    }
+
+    /// Makes this buffer a copy of another one, reusing allocations where possible
+    pub(crate) fn copy_from(&mut self, other: &CellBuffer) {
+        self.width = other.width;
+        self.height = other.height;
+        self.cells.clone_from(&other.cells);
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            None
+        } else {
+            Some(y as usize * self.width as usize + x as usize)
+        }
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> Option<&Cell> {
+        self.index(x, y).map(|idx| &self.cells[idx])
+    }
+
+    pub fn get_mut(&mut self, x: i32, y: i32) -> Option<&mut Cell> {
+        self.index(x, y).map(|idx| &mut self.cells[idx])
+    }
+
+    /// Whether the cell at x,y holds a grapheme that's two cells wide
+    pub(crate) fn is_wide(&self, x: i32, y: i32) -> bool {
+        self.get(x, y)
+            .is_some_and(|cell| grapheme_width(&cell.grapheme) == 2)
+    }
+}
+
+#[cfg(feature = "ratatui")]
+impl CellBuffer {
+    /// Copies the buffer into a new ratatui `Buffer` of the same size
+    pub fn to_ratatui_buffer(&self) -> ratatui::buffer::Buffer {
+        use ratatui::widgets::Widget;
+
+        let area = ratatui::layout::Rect::new(0, 0, self.width, self.height);
+        let mut buffer = ratatui::buffer::Buffer::empty(area);
+        self.render(area, &mut buffer);
+        buffer
+    }
+}
+
+/// Draws the buffer into a ratatui layout, so a frame composed by bevy_crossterm can be shown inside
+/// a ratatui app. Anything that doesn't fit in the area is cut off.
+#[cfg(feature = "ratatui")]
+impl ratatui::widgets::Widget for &CellBuffer {
+    fn render(self, area: ratatui::layout::Rect, buf: &mut ratatui::buffer::Buffer) {
+        let area = area.intersection(buf.area);
+        let width = std::cmp::min(area.width, self.width);
+        let height = std::cmp::min(area.height, self.height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let cell = &self.cells[y as usize * self.width as usize + x as usize];
+                let target = buf.get_mut(area.x + x, area.y + y);
+                target.reset();
+                target.set_style(to_ratatui_style(cell.style));
+
+                // ratatui skips the cell after a wide grapheme by itself, so it's left blank. If the
+                // second half is cut off, draw a space instead
+                if cell.is_continuation() {
+                    continue;
+                }
+                if grapheme_width(&cell.grapheme) == 2 && x + 1 >= width {
+                    target.set_symbol(" ");
+                } else {
+                    target.set_symbol(&cell.grapheme);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "ratatui")]
+fn to_ratatui_style(style: Style) -> ratatui::style::Style {
+    use crossterm::style::Attribute;
+    use ratatui::style::Modifier;
+
+    let mut converted = ratatui::style::Style::default();
+    if let Some(color) = style.colors.foreground {
+        converted = converted.fg(to_ratatui_color(color));
+    }
+    if let Some(color) = style.colors.background {
+        converted = converted.bg(to_ratatui_color(color));
+    }
+    if let Some(color) = style.underline_color {
+        converted = converted.underline_color(to_ratatui_color(color));
+    }
+
+    // ratatui only has one kind of underline, so the fancier ones become plain underlines
+    let modifiers = [
+        (Attribute::Bold, Modifier::BOLD),
+        (Attribute::Dim, Modifier::DIM),
+        (Attribute::Italic, Modifier::ITALIC),
+        (Attribute::Underlined, Modifier::UNDERLINED),
+        (Attribute::DoubleUnderlined, Modifier::UNDERLINED),
+        (Attribute::Undercurled, Modifier::UNDERLINED),
+        (Attribute::Underdotted, Modifier::UNDERLINED),
+        (Attribute::Underdashed, Modifier::UNDERLINED),
+        (Attribute::SlowBlink, Modifier::SLOW_BLINK),
+        (Attribute::RapidBlink, Modifier::RAPID_BLINK),
+        (Attribute::Reverse, Modifier::REVERSED),
+        (Attribute::Hidden, Modifier::HIDDEN),
+        (Attribute::CrossedOut, Modifier::CROSSED_OUT),
+    ];
+    for (attribute, modifier) in modifiers {
+        if style.attributes.has(attribute) {
+            converted = converted.add_modifier(modifier);
+        }
+    }
+
+    converted
+}
+
+/// crossterm names the bright colors after the color and the normal ones "dark", where ratatui names
+/// the normal colors after the color and the bright ones "light"
+#[cfg(feature = "ratatui")]
+fn to_ratatui_color(color: crossterm::style::Color) -> ratatui::style::Color {
+    use crossterm::style::Color as C;
+    use ratatui::style::Color as R;
+
+    match color {
+        C::Reset => R::Reset,
+        C::Black => R::Black,
+        C::DarkGrey => R::DarkGray,
+        C::Red => R::LightRed,
+        C::DarkRed => R::Red,
+        C::Green => R::LightGreen,
+        C::DarkGreen => R::Green,
+        C::Yellow => R::LightYellow,
+        C::DarkYellow => R::Yellow,
+        C::Blue => R::LightBlue,
+        C::DarkBlue => R::Blue,
+        C::Magenta => R::LightMagenta,
+        C::DarkMagenta => R::Magenta,
+        C::Cyan => R::LightCyan,
+        C::DarkCyan => R::Cyan,
+        C::White => R::White,
+        C::Grey => R::Gray,
+        C::Rgb { r, g, b } => R::Rgb(r, g, b),
+        C::AnsiValue(value) => R::Indexed(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::Colors;
+
+    #[test]
+    fn shrinking_and_growing_reuses_the_cells() {
+        let blank = Cell::blank(Colors::default());
+        let mut buffer = CellBuffer::default();
+        buffer.resize(20, 10, &blank);
+        buffer.put_str(0, 9, "bottom row", Style::default());
+        let (ptr, capacity) = (buffer.cells.as_ptr(), buffer.cells.capacity());
+
+        buffer.resize(5, 2, &blank);
+        assert_eq!(buffer.cells.len(), 10);
+        buffer.resize(20, 10, &blank);
+        assert_eq!(
+            (buffer.cells.as_ptr(), buffer.cells.capacity()),
+            (ptr, capacity)
+        );
+        assert!(buffer.cells.iter().all(|cell| *cell == blank));
+
+        // Copying a frame of the same size doesn't allocate either
+        let mut previous = CellBuffer::default();
+        previous.resize(20, 10, &blank);
+        let ptr = previous.cells.as_ptr();
+        previous.copy_from(&buffer);
+        assert_eq!(previous.cells.as_ptr(), ptr);
+    }
+
+    #[cfg(feature = "ratatui")]
+    #[test]
+    fn composed_frames_convert_to_ratatui_buffers() {
+        use crate::components::StyleMap;
+        use crate::testing;
+        use crossterm::style::{Attribute, Color};
+        use ratatui::style::{Color as RatatuiColor, Modifier};
+        use ratatui::widgets::Widget;
+
+        let (mut app, _) = testing::app(5, 2);
+        let bold_red = Style::new(
+            Colors::new(Color::Red, Color::DarkBlue),
+            Attribute::Bold.into(),
+        );
+        let stylemap = StyleMap::new(Style::default(), vec![vec![bold_red]]);
+        testing::spawn_styled(&mut app, "hi漢", stylemap, 0, 0);
+        app.update();
+        let frame = app.world.resource::<CellBuffer>();
+
+        let buffer = frame.to_ratatui_buffer();
+        assert_eq!(buffer.area, ratatui::layout::Rect::new(0, 0, 5, 2));
+        let symbols: Vec<_> = (0..5).map(|x| buffer.get(x, 0).symbol()).collect();
+        assert_eq!(symbols, ["h", "i", "漢", " ", " "]);
+        let h = buffer.get(0, 0);
+        // crossterm's bright red is ratatui's light red
+        assert_eq!((h.fg, h.bg), (RatatuiColor::LightRed, RatatuiColor::Blue));
+        assert_eq!(h.modifier, Modifier::BOLD);
+        assert_eq!(buffer.get(1, 0).modifier, Modifier::empty());
+
+        // A wide grapheme which is cut in half by the area becomes a space
+        let area = ratatui::layout::Rect::new(1, 1, 3, 1);
+        let mut cut = ratatui::buffer::Buffer::empty(ratatui::layout::Rect::new(0, 0, 5, 2));
+        frame.render(area, &mut cut);
+        let symbols: Vec<_> = (1..4).map(|x| cut.get(x, 1).symbol()).collect();
+        assert_eq!(symbols, ["h", "i", " "]);
+    }
+}
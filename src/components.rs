@@ -1,35 +1,49 @@
 use bevy::prelude::*;
 use bevy::reflect::{TypePath, TypeUuid};
-use bevy::utils::{HashMap, HashSet};
+use bevy::utils::HashSet;
 use bevy_asset::Handle;
 use serde::{Deserialize, Serialize};
 use std::default::Default;
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
-pub use crossterm::style::Color;
-
-#[derive(Default, Resource)]
-pub(crate) struct PreviousEntityDetails(pub HashMap<Entity, (PreviousPosition, PreviousSize)>);
-
-#[derive(Resource)]
-pub(crate) struct PreviousWindowColors(pub Colors);
+use crate::buffer::CellBuffer;
 
-impl Default for PreviousWindowColors {
-    fn default() -> Self {
-        PreviousWindowColors(Colors::term_colors())
-    }
-}
+pub use crossterm::style::Color;
 
+/// The renderer's state between frames
 #[derive(Default, Resource)]
-pub(crate) struct EntitiesToRedraw {
+pub(crate) struct RenderState {
+    /// What's currently on the screen
+    pub previous: CellBuffer,
+    /// All the static sprites, which every frame is built on top of
+    pub background: CellBuffer,
+    pub static_entities: HashSet<Entity>,
+    /// The window colors the buffers were drawn with
+    pub colors: Option<Colors>,
+    /// The default overflow the buffers were drawn with
+    pub overflow: Option<Overflow>,
+    /// The coordinate space the buffers were drawn with
+    pub coordinate_space: Option<crate::CoordinateSpace>,
+    /// The safe area and fixed canvas the buffers were drawn with
+    pub window_area: Option<(crate::SafeArea, Option<crate::FixedCanvas>)>,
+    /// Whether the next frame needs to be composed and drawn
+    pub redraw: bool,
+    /// Whether the screen needs to be cleared and redrawn from scratch
     pub full_redraw: bool,
-    pub to_clear: HashSet<Entity>,
-    pub to_draw: Vec<EntityDepth>,
-}
-
-pub(crate) struct EntityDepth {
-    pub entity: Entity,
-    pub z: i32,
+    pub rebuild_background: bool,
+    /// The debug overlay the frame was drawn with
+    pub debug_overlay: Option<crate::DebugOverlay>,
+    /// Whether a frame has made it to the terminal yet
+    pub first_frame_rendered: bool,
+    /// Counts every update, so recent reloads can be told apart from old ones
+    pub frame: u64,
+    /// The sprites and stylemaps which were hot reloaded recently, and the frame they were reloaded on
+    pub reloads: bevy::utils::HashMap<bevy_asset::UntypedAssetId, u64>,
+    /// The sprites and stylemaps the asset server has loaded, so loading one again is a reload
+    pub loaded: HashSet<bevy_asset::UntypedAssetId>,
+    /// Sprites which have already been laid out with their stylemaps
+    pub rasters: crate::systems::Rasters,
 }
 
 #[derive(Bundle, Default)]
@@ -40,9 +54,13 @@ pub struct SpriteBundle {
     pub visible: Visible,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Reflect, Default)]
+/// In a `.stylemap` file, colors are written as names like `"red"` or `"dark_gray"`, ignoring case,
+/// or as `"ansi_(160)"`, `"rgb_(255,128,0)"` or `"#ff8000"`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize, Reflect, Default)]
 pub struct Colors {
+    #[serde(default, with = "color_parser")]
     pub foreground: Option<Color>,
+    #[serde(default, with = "color_parser")]
     pub background: Option<Color>,
 }
 
@@ -92,6 +110,189 @@ impl Colors {
     }
 }
 
+mod color_parser {
+    use crossterm::style::Color;
+    use serde::de::Visitor;
+    use serde::{Deserializer, Serialize, Serializer};
+
+    const NAMES: &[(&str, Color)] = &[
+        ("reset", Color::Reset),
+        ("black", Color::Black),
+        ("dark_grey", Color::DarkGrey),
+        ("dark_gray", Color::DarkGrey),
+        ("bright_black", Color::DarkGrey),
+        ("red", Color::Red),
+        ("bright_red", Color::Red),
+        ("dark_red", Color::DarkRed),
+        ("green", Color::Green),
+        ("bright_green", Color::Green),
+        ("dark_green", Color::DarkGreen),
+        ("yellow", Color::Yellow),
+        ("bright_yellow", Color::Yellow),
+        ("dark_yellow", Color::DarkYellow),
+        ("blue", Color::Blue),
+        ("bright_blue", Color::Blue),
+        ("dark_blue", Color::DarkBlue),
+        ("magenta", Color::Magenta),
+        ("bright_magenta", Color::Magenta),
+        ("dark_magenta", Color::DarkMagenta),
+        ("cyan", Color::Cyan),
+        ("bright_cyan", Color::Cyan),
+        ("dark_cyan", Color::DarkCyan),
+        ("white", Color::White),
+        ("bright_white", Color::White),
+        ("grey", Color::Grey),
+        ("gray", Color::Grey),
+    ];
+
+    pub fn serialize<S>(color: &Option<Color>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        color.serialize(serializer)
+    }
+
+    /// How many single character edits it takes to turn one string into another
+    fn edit_distance(a: &str, b: &str) -> usize {
+        let b: Vec<char> = b.chars().collect();
+        let mut previous: Vec<usize> = (0..=b.len()).collect();
+        for (i, a) in a.chars().enumerate() {
+            let mut current = vec![i + 1];
+            for (j, b) in b.iter().enumerate() {
+                let substitution = previous[j] + usize::from(a != *b);
+                current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+            }
+            previous = current;
+        }
+        previous[b.len()]
+    }
+
+    fn parse_bytes(values: &str) -> Option<Vec<u8>> {
+        values
+            .split(',')
+            .map(|value| value.trim().parse().ok())
+            .collect()
+    }
+
+    fn parse(value: &str) -> Result<Color, String> {
+        let lower = value.trim().to_lowercase();
+        let name = lower.replace(['-', ' '], "_");
+        if let Some((_, color)) = NAMES.iter().find(|(known, _)| *known == name) {
+            return Ok(*color);
+        }
+
+        // The numbers may be spaced out, so these are matched before spaces are replaced
+        if let Some(inner) = lower
+            .strip_prefix("ansi_(")
+            .and_then(|v| v.strip_suffix(')'))
+        {
+            return inner.trim().parse().map(Color::AnsiValue).map_err(|_| {
+                format!("`{value}` should have a number from 0 to 255, like `ansi_(160)`")
+            });
+        }
+
+        if let Some(inner) = lower
+            .strip_prefix("rgb_(")
+            .and_then(|v| v.strip_suffix(')'))
+        {
+            return match parse_bytes(inner).as_deref() {
+                Some([r, g, b]) => Ok(Color::Rgb {
+                    r: *r,
+                    g: *g,
+                    b: *b,
+                }),
+                _ => Err(format!(
+                    "`{value}` should have three numbers from 0 to 255, like `rgb_(255,128,0)`"
+                )),
+            };
+        }
+
+        if let Some(hex) = lower.strip_prefix('#') {
+            let digits: Option<Vec<u8>> = match hex.len() {
+                3 => hex
+                    .chars()
+                    .map(|c| c.to_digit(16).map(|d| d as u8 * 0x11))
+                    .collect(),
+                6 => (0..3)
+                    .map(|i| {
+                        hex.get(i * 2..i * 2 + 2)
+                            .and_then(|d| u8::from_str_radix(d, 16).ok())
+                    })
+                    .collect(),
+                _ => None,
+            };
+            return match digits.as_deref() {
+                Some([r, g, b]) => Ok(Color::Rgb {
+                    r: *r,
+                    g: *g,
+                    b: *b,
+                }),
+                _ => Err(format!(
+                    "`{value}` should be a hex color like `#ff8000` or `#f80`"
+                )),
+            };
+        }
+
+        let closest = NAMES
+            .iter()
+            .map(|(known, _)| (edit_distance(&name, known), known))
+            .min()
+            .filter(|(distance, _)| *distance <= 3);
+        Err(match closest {
+            Some((_, known)) => format!("unknown color `{value}`, did you mean `{known}`?"),
+            None => format!(
+                "unknown color `{value}`, expected a color name like `red` or `dark_gray`, \
+                 `ansi_(n)`, `rgb_(r,g,b)` or `#rrggbb`"
+            ),
+        })
+    }
+
+    struct ColorVisitor;
+
+    impl<'de> Visitor<'de> for ColorVisitor {
+        type Value = Option<Color>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("an optional color")
+        }
+
+        fn visit_none<E>(self) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            Ok(None)
+        }
+
+        fn visit_unit<E>(self) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            Ok(None)
+        }
+
+        fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            deserializer.deserialize_str(self)
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            parse(value).map(Some).map_err(E::custom)
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Color>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_option(ColorVisitor)
+    }
+}
+
 mod attribute_parser {
     use serde::de::Visitor;
     use serde::{Deserializer, Serializer};
@@ -149,22 +350,44 @@ mod attribute_parser {
 // impl Reflect for StyleAttributes {}
 // impl FromReflect for StyleAttributes {}
 
-#[derive(Copy, Clone, Serialize, Deserialize, PartialEq, Eq, Reflect)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Reflect)]
 pub struct Style {
     pub colors: Colors,
     #[serde(with = "attribute_parser")]
     pub attributes: crossterm::style::Attributes,
+    /// The color of underlines, when it should be different from the text. Only some terminals
+    /// support this, the rest draw underlines in the text color.
+    ///
+    /// In a `.stylemap` file this may be written as either `underline_color` or `ul`.
+    #[serde(default, alias = "ul", with = "color_parser")]
+    pub underline_color: Option<Color>,
+}
+
+// crossterm's attributes can't be hashed, so they're hashed one by one
+impl std::hash::Hash for Style {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.colors.hash(state);
+        for attribute in crossterm::style::Attribute::iterator() {
+            self.attributes.has(attribute).hash(state);
+        }
+        self.underline_color.hash(state);
+    }
 }
 
 impl Style {
     pub fn new(colors: Colors, attributes: crossterm::style::Attributes) -> Style {
-        Style { colors, attributes }
+        Style {
+            colors,
+            attributes,
+            underline_color: None,
+        }
     }
 
     pub fn with_attrib(attribute: crossterm::style::Attribute) -> Style {
         Style {
             colors: Colors::default(),
             attributes: attribute.into(),
+            underline_color: None,
         }
     }
 
@@ -172,6 +395,7 @@ impl Style {
         Style {
             colors: Colors::default(),
             attributes,
+            underline_color: None,
         }
     }
 
@@ -179,6 +403,7 @@ impl Style {
         Style {
             colors: Colors::fg(foreground),
             attributes: crossterm::style::Attribute::Reset.into(),
+            underline_color: None,
         }
     }
 
@@ -186,6 +411,7 @@ impl Style {
         Style {
             colors: Colors::bg(background),
             attributes: crossterm::style::Attribute::Reset.into(),
+            underline_color: None,
         }
     }
 
@@ -193,6 +419,26 @@ impl Style {
         Style {
             colors,
             attributes: crossterm::style::Attribute::Reset.into(),
+            underline_color: None,
+        }
+    }
+
+    /// Underlines the text, with the underline in the given color
+    pub fn with_underline_color(color: Color) -> Style {
+        Style {
+            colors: Colors::default(),
+            attributes: crossterm::style::Attribute::Underlined.into(),
+            underline_color: Some(color),
+        }
+    }
+
+    /// Fills in any colors this style doesn't specify with the provided defaults
+    #[must_use]
+    pub fn with_default_colors(&self, default_colors: Colors) -> Style {
+        Style {
+            colors: self.colors.with_default(default_colors),
+            attributes: self.attributes,
+            underline_color: self.underline_color,
         }
     }
 }
@@ -202,14 +448,30 @@ impl Default for Style {
         Style {
             attributes: crossterm::style::Attribute::Reset.into(),
             colors: Colors::default(),
+            underline_color: None,
         }
     }
 }
 
-#[derive(Default, Serialize, Deserialize, PartialEq, Eq, TypeUuid, TypePath, Asset)]
+/// Whether a byte is an ASCII character which can be shown as it is
+fn is_printable(byte: u8) -> bool {
+    (0x20..0x7f).contains(&byte)
+}
+
+/// Two stylemaps are equal when they have the same default style and the same styles for the same
+/// cells. Rows which are shorter, or missing, count as different even if the default style would make
+/// them look the same.
+#[derive(Default, Serialize, Deserialize, PartialEq, Eq, Hash, TypeUuid, TypePath, Asset)]
 #[uuid = "a5418d12-e050-498a-a31e-37fd0b6c078d"]
 pub struct StyleMap {
+    /// The default style, used for every cell which doesn't have an entry in `map`. Cells which do
+    /// have an entry but leave a color unset also inherit that color from here.
+    ///
+    /// In a `.stylemap` file this may be written as either `style` or `default_style`.
+    #[serde(alias = "default_style")]
     pub style: Style,
+    /// The styles of individual cells, row by row. This can be left empty, in which case the
+    /// stylemap fits whatever sprite it's paired with, even one which changes size.
     pub map: Vec<Vec<Style>>,
 }
 
@@ -253,21 +515,148 @@ impl StyleMap {
         }
     }
 
+    /// A stylemap which colors the whole sprite it's paired with, whatever its size. It doesn't need
+    /// changing when the sprite is resized or reloaded.
+    pub fn solid(foreground: Color, background: Color) -> StyleMap {
+        StyleMap::with_colors(Colors::new(foreground, background))
+    }
+
+    /// A stylemap which fades the foreground from one color to another, left to right
+    pub fn gradient(from: (u8, u8, u8), to: (u8, u8, u8), width: usize, height: usize) -> StyleMap {
+        let lerp =
+            |from: u8, to: u8, t: f32| (from as f32 + (to as f32 - from as f32) * t).round() as u8;
+
+        let row: Vec<Style> = (0..width)
+            .map(|x| {
+                let t = if width > 1 {
+                    x as f32 / (width - 1) as f32
+                } else {
+                    0.0
+                };
+                Style::with_fg(Color::Rgb {
+                    r: lerp(from.0, to.0, t),
+                    g: lerp(from.1, to.1, t),
+                    b: lerp(from.2, to.2, t),
+                })
+            })
+            .collect();
+
+        StyleMap {
+            map: vec![row; height],
+            ..Default::default()
+        }
+    }
+
+    /// A stylemap with the style of each cell worked out by `f`, which is called with the x and y of
+    /// every cell once, row by row
+    pub fn from_fn(width: usize, height: usize, f: impl Fn(usize, usize) -> Style) -> StyleMap {
+        let map = (0..height)
+            .map(|y| (0..width).map(|x| f(x, y)).collect())
+            .collect();
+
+        StyleMap {
+            map,
+            ..Default::default()
+        }
+    }
+
+    /// A stylemap for `Sprite::hexdump`, which dims the bytes that aren't printable ASCII in both the
+    /// hex and the text
+    pub fn hexdump(bytes: &[u8], width: usize) -> StyleMap {
+        let width = width.max(1);
+        let (hex_start, text_start) = (10, width * 3 + 12);
+        let dim = Style::with_attrib(crossterm::style::Attribute::Dim);
+
+        let rows = bytes.len().div_ceil(width);
+        StyleMap::from_fn(text_start + width + 1, rows, |x, y| {
+            let column = if (hex_start..text_start - 3).contains(&x) && (x - hex_start) % 3 != 2 {
+                Some((x - hex_start) / 3)
+            } else if (text_start..text_start + width).contains(&x) {
+                Some(x - text_start)
+            } else {
+                None
+            };
+            match column.and_then(|column| bytes.get(y * width + column)) {
+                Some(&byte) if !is_printable(byte) => dim,
+                _ => Style::default(),
+            }
+        })
+    }
+
+    /// A stylemap for `sprite` which styles each cell by the character in it, e.g. to color digits
+    /// differently from letters. Graphemes made of several characters are styled by their first one,
+    /// and both halves of a wide grapheme get the same style.
+    pub fn from_sprite_classified(sprite: &Sprite, classifier: impl Fn(char) -> Style) -> StyleMap {
+        let map = (0..sprite.height())
+            .map(|y| {
+                let mut row = Vec::with_capacity(sprite.line_width(y));
+                for (x, grapheme) in sprite.glyphs(y) {
+                    // Any columns since the last grapheme are the second half of it, so they get its style
+                    let style = classifier(grapheme.chars().next().unwrap_or(' '));
+                    row.resize(x, row.last().copied().unwrap_or(style));
+                    row.push(style);
+                }
+                if let Some(&last) = row.last() {
+                    row.resize(sprite.line_width(y), last);
+                }
+                row
+            })
+            .collect();
+
+        StyleMap {
+            map,
+            ..Default::default()
+        }
+    }
+
+    pub fn default_style(&self) -> Style {
+        self.style
+    }
+
+    pub fn set_default_style(&mut self, style: Style) -> &mut Self {
+        self.style = style;
+        self
+    }
+
     /// If there is a style available in the map, this fetches it. Otherwise, this returns None
     pub fn style_at(&self, x: usize, y: usize) -> Option<&Style> {
         self.map.get(y).and_then(|vec| vec.get(x))
     }
 
     /// If there is a style for the grapheme at position x,y in the map, this fetches it. Otherwise
-    /// the global sprite's style is returned
+    /// the global sprite's style is returned. Any colors the cell's style leaves unset are taken
+    /// from the global sprite's style.
     pub fn style_for(&self, x: usize, y: usize) -> Style {
         let grapheme = self.style_at(x, y);
         if let Some(style) = grapheme {
-            *style
+            let mut style = style.with_default_colors(self.style.colors);
+            style.underline_color = style.underline_color.or(self.style.underline_color);
+            style
         } else {
             self.style
         }
     }
+
+    /// Returns the part of this stylemap inside `rect`, to go with a sprite cropped by `Sprite::crop`.
+    /// The default style is kept.
+    #[must_use]
+    pub fn crop(&self, rect: URect) -> StyleMap {
+        let (left, right) = (rect.min.x as usize, rect.max.x as usize);
+        let map = self
+            .map
+            .iter()
+            .take(rect.max.y as usize)
+            .skip(rect.min.y as usize)
+            .map(|row| {
+                row.iter()
+                    .take(right)
+                    .skip(left)
+                    .copied()
+                    .collect::<Vec<Style>>()
+            })
+            .collect();
+        StyleMap::new(self.style, map)
+    }
 }
 
 #[derive(Eq, PartialEq, Debug, Component)]
@@ -301,6 +690,48 @@ impl Visible {
     }
 }
 
+/// The number of cells a grapheme takes up on the terminal. Wide characters, like most emoji and CJK
+/// characters, take up two.
+pub(crate) fn grapheme_width(grapheme: &str) -> usize {
+    // Zero width graphemes (like a lone combining character) still get a cell of their own
+    grapheme.width().clamp(1, 2)
+}
+
+/// What a `Sprite` does with control characters in its text, like `\0`, `\x1b` or `\x08`. Written to
+/// the terminal they would mess up the screen, so they're never kept. Line breaks and tabs are left alone.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Hash, Debug, Reflect)]
+pub enum ControlChars {
+    /// Remove them from the text
+    #[default]
+    Drop,
+    /// Replace each one with a placeholder, which takes up a cell like any other character
+    Replace(char),
+}
+
+impl ControlChars {
+    fn is_disallowed(c: char) -> bool {
+        c.is_control() && c != '\n' && c != '\r' && c != '\t'
+    }
+
+    fn sanitize(self, text: &str) -> String {
+        match self {
+            ControlChars::Drop => text.chars().filter(|c| !Self::is_disallowed(*c)).collect(),
+            ControlChars::Replace(placeholder) => text
+                .chars()
+                .map(|c| {
+                    if Self::is_disallowed(c) {
+                        placeholder
+                    } else {
+                        c
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Two sprites are equal when they have the same text, once control characters have been dealt with,
+/// and deal with control characters the same way.
 #[derive(Default, Eq, PartialEq, Debug, TypeUuid, Reflect, Asset)]
 #[uuid = "f04f5352-e656-4a90-95a5-2269c02d0091"]
 pub struct Sprite {
@@ -310,7 +741,20 @@ pub struct Sprite {
     // whole character is easily. Since these are indices into the data field, they
     // must be updated in tandem
     graphemes: Vec<Vec<(usize, usize)>>,
+    // The column each grapheme starts at, since wide graphemes take up two columns
+    columns: Vec<Vec<usize>>,
+    // The width of each line, in columns
+    line_widths: Vec<usize>,
     max_width: usize,
+    control_chars: ControlChars,
+}
+
+// Everything else in a sprite is worked out from its text
+impl std::hash::Hash for Sprite {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.data.hash(state);
+        self.control_chars.hash(state);
+    }
 }
 
 impl Sprite {
@@ -325,23 +769,53 @@ impl Sprite {
         sprite
     }
 
+    /// Creates a sprite which handles control characters in its text the given way, including when
+    /// it's updated
+    pub fn with_control_chars<T: ToString>(value: T, control_chars: ControlChars) -> Sprite {
+        let mut sprite = Sprite {
+            data: value.to_string(),
+            control_chars,
+            ..Default::default()
+        };
+
+        Sprite::convert_to_sprite(&mut sprite);
+
+        sprite
+    }
+
     fn convert_to_sprite(sprite: &mut Sprite) {
+        if sprite.data.chars().any(ControlChars::is_disallowed) {
+            sprite.data = sprite.control_chars.sanitize(&sprite.data);
+        }
+
         sprite.max_width = 0;
+        sprite.graphemes.clear();
+        sprite.columns.clear();
+        sprite.line_widths.clear();
 
         let mut current_line = Vec::new();
+        let mut current_columns = Vec::new();
+        let mut current_width = 0;
         for (start, grapheme) in UnicodeSegmentation::grapheme_indices(&*sprite.data, true) {
             if grapheme == "\r" || grapheme == "\n" || grapheme == "\r\n" {
-                sprite.max_width = std::cmp::max(sprite.max_width, current_line.len());
+                sprite.max_width = std::cmp::max(sprite.max_width, current_width);
                 sprite.graphemes.push(std::mem::take(&mut current_line));
+                sprite.columns.push(std::mem::take(&mut current_columns));
+                sprite.line_widths.push(current_width);
+                current_width = 0;
                 continue;
             }
 
             current_line.push((start, start + grapheme.len()));
+            current_columns.push(current_width);
+            current_width += grapheme_width(grapheme);
         }
 
         if !current_line.is_empty() {
-            sprite.max_width = std::cmp::max(sprite.max_width, current_line.len());
-            sprite.graphemes.push(std::mem::take(&mut current_line));
+            sprite.max_width = std::cmp::max(sprite.max_width, current_width);
+            sprite.graphemes.push(current_line);
+            sprite.columns.push(current_columns);
+            sprite.line_widths.push(current_width);
         }
     }
 
@@ -349,6 +823,7 @@ impl Sprite {
         &self.data
     }
 
+    /// The width of the widest line, in columns
     pub fn width(&self) -> usize {
         self.max_width
     }
@@ -357,12 +832,24 @@ impl Sprite {
         self.graphemes.len()
     }
 
+    /// Whether the sprite has nothing to draw. Sprites made of only line breaks are empty too, even
+    /// though they have a height.
+    pub fn is_empty(&self) -> bool {
+        self.width() == 0 || self.height() == 0
+    }
+
+    /// The middle column of the sprite, or 0 if it's empty
     pub fn x_center(&self) -> usize {
         self.width() / 2
     }
 
+    /// The middle line of the sprite, or 0 if it's empty
     pub fn y_center(&self) -> usize {
-        self.height() / 2
+        if self.is_empty() {
+            0
+        } else {
+            self.height() / 2
+        }
     }
 
     pub fn graphemes(&self) -> &[Vec<(usize, usize)>] {
@@ -373,64 +860,2221 @@ impl Sprite {
         &self.data[grapheme.0..grapheme.1]
     }
 
+    /// The width of line y, in columns
+    pub fn line_width(&self, y: usize) -> usize {
+        self.line_widths.get(y).copied().unwrap_or(0)
+    }
+
+    /// Iterates over the graphemes of line y, along with the column each one starts at
+    pub fn glyphs(&self, y: usize) -> impl Iterator<Item = (usize, &str)> {
+        let line = self.graphemes.get(y).map(Vec::as_slice).unwrap_or(&[]);
+        let columns = self.columns.get(y).map(Vec::as_slice).unwrap_or(&[]);
+        columns
+            .iter()
+            .copied()
+            .zip(line.iter().map(|grapheme| self.grapheme(grapheme)))
+    }
+
+    /// Iterates over the lines of the sprite, along with the graphemes in each line and the column
+    /// each one starts at, like `glyphs`. Items are whole grapheme clusters rather than `char`s, so
+    /// an emoji or a letter with combining accents comes out as one `&str`.
+    pub fn rows(&self) -> impl Iterator<Item = (usize, impl Iterator<Item = (usize, &str)>)> {
+        (0..self.height()).map(|y| (y, self.glyphs(y)))
+    }
+
+    /// Iterates over the columns of the sprite, along with the graphemes which start in each column
+    /// and the line each one is on. Like `rows`, items are grapheme clusters. Wide graphemes only
+    /// show up in the first of their two columns, and lines which are too short to reach a column
+    /// are left out of it.
+    pub fn columns(&self) -> impl Iterator<Item = (usize, impl Iterator<Item = (usize, &str)>)> {
+        (0..self.width()).map(move |x| {
+            let column = (0..self.height()).filter_map(move |y| Some((y, self.grapheme_at(x, y)?)));
+            (x, column)
+        })
+    }
+
+    /// Returns the grapheme which starts at column x of line y, if the sprite has one there
+    pub fn grapheme_at(&self, x: usize, y: usize) -> Option<&str> {
+        let columns = self.columns.get(y)?;
+        let idx = columns.binary_search(&x).ok()?;
+        Some(self.grapheme(&self.graphemes[y][idx]))
+    }
+
+    /// The cells this sprite covers when it's at the given position. Wide graphemes count as both of
+    /// the cells they cover. Empty sprites have empty bounds, which never overlap anything.
+    pub fn bounds(&self, position: &Position) -> IRect {
+        IRect::new(
+            position.x,
+            position.y,
+            position.x + self.width() as i32,
+            position.y + self.height() as i32,
+        )
+    }
+
+    /// Whether the cell at column x of line y has something other than a space in it
+    pub(crate) fn is_filled(&self, x: usize, y: usize) -> bool {
+        match self.grapheme_at(x, y) {
+            Some(grapheme) => grapheme != " ",
+            None => self.is_continuation(x, y),
+        }
+    }
+
+    /// Whether column x of line y is covered by the second half of a wide grapheme
+    pub fn is_continuation(&self, x: usize, y: usize) -> bool {
+        let columns = match self.columns.get(y) {
+            Some(columns) => columns,
+            None => return false,
+        };
+        match columns.binary_search(&x) {
+            Ok(_) => false,
+            Err(0) => false,
+            Err(idx) => x < self.line_width(y) && columns[idx - 1] < x,
+        }
+    }
+
     pub fn update<T: ToString>(&mut self, value: T) {
         self.data = value.to_string();
-        self.graphemes.clear();
         Sprite::convert_to_sprite(self);
     }
-}
 
-#[derive(Default, Eq, PartialEq, Debug, Component)]
-pub struct Position {
-    pub x: i32,
-    pub y: i32,
-    pub z: i32,
-}
+    /// Collects statistics about the sprite, for validating art. Widths are measured in terminal
+    /// columns, so wide characters count twice.
+    pub fn metrics(&self) -> SpriteMetrics {
+        let mut distinct = HashSet::default();
+        let mut row_widths = Vec::with_capacity(self.graphemes.len());
+        let mut cell_count = 0;
+
+        for line in &self.graphemes {
+            let mut row_width = 0;
+            for grapheme in line {
+                let grapheme = self.grapheme(grapheme);
+                row_width += grapheme_width(grapheme);
+                cell_count += 1;
+                distinct.insert(grapheme);
+            }
+            row_widths.push(row_width);
+        }
 
-impl Position {
-    pub fn new(x: i32, y: i32, z: i32) -> Position {
-        Position { x, y, z }
+        SpriteMetrics {
+            width: row_widths.iter().copied().max().unwrap_or(0),
+            height: self.graphemes.len(),
+            cell_count,
+            distinct_graphemes: distinct.len(),
+            uniform_width: row_widths.windows(2).all(|pair| pair[0] == pair[1]),
+            row_widths,
+        }
     }
 
-    pub fn with_x(x: i32) -> Position {
-        Position {
-            x,
-            ..Default::default()
+    /// Looks for things in the sprite which are likely to be drawn differently than intended, such
+    /// as lines of different widths or characters that terminals don't agree on the width of. Nothing
+    /// here stops the sprite from being drawn, so it's meant for checking art before it ships.
+    pub fn validate(&self) -> Vec<SpriteWarning> {
+        let mut warnings = Vec::new();
+        let width = self.width();
+
+        for y in 0..self.height() {
+            for (x, grapheme) in self.glyphs(y) {
+                let problem = if grapheme.chars().any(char::is_control) {
+                    SpriteProblem::ControlChar
+                } else if grapheme.width() == 0 {
+                    SpriteProblem::CombiningWithoutBase
+                } else if grapheme_width(grapheme) == 2 {
+                    SpriteProblem::WideGlyph
+                } else {
+                    continue;
+                };
+                warnings.push(SpriteWarning {
+                    x,
+                    y,
+                    grapheme: grapheme.to_string(),
+                    problem,
+                });
+            }
+
+            let line_width = self.line_width(y);
+            if line_width != width {
+                warnings.push(SpriteWarning {
+                    x: line_width,
+                    y,
+                    grapheme: String::new(),
+                    problem: SpriteProblem::RaggedLine,
+                });
+            }
         }
+
+        warnings
     }
 
-    pub fn with_y(y: i32) -> Position {
-        Position {
-            y,
-            ..Default::default()
+    /// Lays out bytes the way hex dumps usually do, with `width` bytes to a line. Each line starts with
+    /// the offset of its first byte, then the bytes in hex, then the bytes as text between bars, with
+    /// a `.` for each byte that isn't printable ASCII. The last line is padded so its bars line up.
+    /// `StyleMap::hexdump` makes a matching stylemap.
+    pub fn hexdump(bytes: &[u8], width: usize) -> Sprite {
+        let width = width.max(1);
+        let lines: Vec<String> = bytes
+            .chunks(width)
+            .enumerate()
+            .map(|(row, chunk)| {
+                let hex: Vec<String> = chunk.iter().map(|byte| format!("{byte:02x}")).collect();
+                let text: String = chunk
+                    .iter()
+                    .map(|&byte| {
+                        if is_printable(byte) {
+                            byte as char
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect();
+                format!(
+                    "{:08x}  {:hex_width$}  |{:width$}|",
+                    row * width,
+                    hex.join(" "),
+                    text,
+                    hex_width = width * 3 - 1,
+                )
+            })
+            .collect();
+        Sprite::new(lines.join("\n"))
+    }
+
+    /// Creates a sprite from text, word wrapped so that no line is wider than `width`.
+    /// Existing line breaks are kept, and words that are too long to fit on a line are split.
+    pub fn wrapped<T: AsRef<str>>(text: T, width: usize) -> Sprite {
+        let width = std::cmp::max(width, 1);
+        let mut lines = Vec::new();
+
+        for paragraph in text.as_ref().lines() {
+            let mut line = String::new();
+            let mut line_width = 0;
+
+            for word in paragraph.split_whitespace() {
+                let mut word: Vec<&str> = word.graphemes(true).collect();
+                let word_width =
+                    |word: &[&str]| word.iter().map(|g| grapheme_width(g)).sum::<usize>();
+
+                // Start a new line if the word won't fit on the current one
+                if line_width > 0 && line_width + 1 + word_width(&word) > width {
+                    lines.push(std::mem::take(&mut line));
+                    line_width = 0;
+                }
+
+                // Words that can't fit on a line at all get split up
+                while word_width(&word) > width {
+                    let mut split = 0;
+                    let mut split_width = 0;
+                    while split < word.len() && split_width + grapheme_width(word[split]) <= width {
+                        split_width += grapheme_width(word[split]);
+                        split += 1;
+                    }
+                    // Always make progress, even if a single grapheme is wider than the line
+                    let rest = word.split_off(std::cmp::max(split, 1));
+                    lines.push(word.concat());
+                    word = rest;
+                }
+
+                if line_width > 0 {
+                    line.push(' ');
+                    line_width += 1;
+                }
+                line_width += word_width(&word);
+                line.push_str(&word.concat());
+            }
+
+            lines.push(line);
         }
+
+        Sprite::new(lines.join("\n"))
     }
 
-    pub fn with_xy(x: i32, y: i32) -> Position {
-        Position {
-            x,
-            y,
-            ..Default::default()
+    /// Creates a sprite from a grid of intensities, where each one picks a character from `ramp`. The
+    /// ramp goes from 0.0 to 1.0, like `" .:-=+*#%@"`. Intensities outside of that are clamped, and
+    /// an empty ramp gives a sprite of spaces.
+    pub fn from_intensity(grid: &[Vec<f32>], ramp: &str) -> Sprite {
+        let ramp: Vec<&str> = ramp.graphemes(true).collect();
+
+        let mut data = String::new();
+        for (y, row) in grid.iter().enumerate() {
+            if y > 0 {
+                data.push('\n');
+            }
+            for intensity in row {
+                if ramp.is_empty() {
+                    data.push(' ');
+                    continue;
+                }
+                // NaN doesn't have an intensity, so it's treated as nothing
+                let intensity = if intensity.is_nan() {
+                    0.0
+                } else {
+                    intensity.clamp(0.0, 1.0)
+                };
+                let idx = (intensity * (ramp.len() - 1) as f32).round() as usize;
+                data.push_str(ramp[idx]);
+            }
         }
+
+        Sprite::new(data)
     }
-}
 
-#[derive(Default, Eq, PartialEq, Debug)]
-pub(crate) struct PreviousPosition {
-    pub x: i32,
-    pub y: i32,
-    pub z: i32,
-}
+    /// Creates a sprite from a grid of intensities like `from_intensity`, but uses ordered dithering:
+    /// intensities between two characters of `chars` are shown as a pattern of both, so gradients
+    /// look smooth even with only a few characters. The pattern comes from a Bayer matrix, and
+    /// bigger matrices can show more shades between each pair of characters.
+    pub fn dither(grid: &[Vec<f32>], chars: &str, matrix: BayerMatrix) -> Sprite {
+        let chars: Vec<&str> = chars.graphemes(true).collect();
+        let thresholds = matrix.thresholds();
+        let size = thresholds.len();
+
+        let mut data = String::new();
+        for (y, row) in grid.iter().enumerate() {
+            if y > 0 {
+                data.push('\n');
+            }
+            for (x, intensity) in row.iter().enumerate() {
+                if chars.is_empty() {
+                    data.push(' ');
+                    continue;
+                }
+                let intensity = if intensity.is_nan() {
+                    0.0
+                } else {
+                    intensity.clamp(0.0, 1.0)
+                };
+                // Pick between the two closest characters, based on where this cell is in the matrix
+                let scaled = intensity * (chars.len() - 1) as f32;
+                let lower = scaled.floor();
+                let threshold =
+                    (thresholds[y % size][x % size] as f32 + 0.5) / (size * size) as f32;
+                let idx = lower as usize + usize::from(scaled - lower > threshold);
+                data.push_str(chars[std::cmp::min(idx, chars.len() - 1)]);
+            }
+        }
 
-#[derive(Default, Eq, PartialEq, Debug)]
-pub(crate) struct PreviousSize {
-    pub width: u16,
-    pub height: u16,
-}
+        Sprite::new(data)
+    }
 
-#[derive(Default, Eq, PartialEq, Debug)]
-pub(crate) struct GlobalPosition {
-    pub x: i32,
-    pub y: i32,
-    pub z: i32,
+    /// Returns a copy of this sprite with only the edge of its shape left. Cells that aren't spaces
+    /// are kept if they're next to a space or the edge of the sprite, and the rest become spaces, so
+    /// whatever is underneath shows through when the sprite is drawn with `Visible::transparent`.
+    ///
+    /// Unlike `bordered`, which draws around the outside of the sprite, this only takes cells away.
+    #[must_use]
+    pub fn hollow(&self) -> Sprite {
+        let (width, height) = (self.width() as i32, self.height() as i32);
+        let filled = |x: i32, y: i32| {
+            (0..width).contains(&x)
+                && (0..height).contains(&y)
+                && self.is_filled(x as usize, y as usize)
+        };
+        let on_edge = |x: i32, y: i32| {
+            filled(x, y)
+                && [(-1, 0), (1, 0), (0, -1), (0, 1)]
+                    .iter()
+                    .any(|(dx, dy)| !filled(x + dx, y + dy))
+        };
+
+        let mut data = String::new();
+        for y in 0..self.height() {
+            if y > 0 {
+                data.push('\n');
+            }
+            for (x, grapheme) in self.glyphs(y) {
+                // Wide graphemes are kept if either of their halves is on the edge
+                let columns = grapheme_width(grapheme);
+                if (x..x + columns).any(|x| on_edge(x as i32, y as i32)) {
+                    data.push_str(grapheme);
+                } else {
+                    data.push_str(&" ".repeat(columns));
+                }
+            }
+        }
+
+        Sprite::new(data)
+    }
+
+    /// Replaces the grapheme at column x of line y with `fill`, along with every grapheme like it that
+    /// can be reached from there going up, down, left or right through graphemes like it, like the
+    /// bucket tool in a paint program. Wide graphemes are replaced with `fill` in both of their
+    /// columns, so `fill` should be one column wide to keep the sprite's shape.
+    ///
+    /// Only the cells a line actually has are filled, not the space after the end of a shorter line.
+    /// Nothing happens when there's no grapheme at x,y, or it's already `fill`.
+    pub fn flood_fill(&mut self, x: usize, y: usize, fill: char) {
+        // The columns each grapheme covers, and which grapheme covers each column
+        let span = |y: usize, idx: usize| {
+            let columns = &self.columns[y];
+            let end = columns.get(idx + 1).copied();
+            columns[idx]..end.unwrap_or(self.line_width(y))
+        };
+        let owners: Vec<Vec<usize>> = (0..self.height())
+            .map(|y| {
+                (0..self.graphemes[y].len())
+                    .flat_map(|idx| std::iter::repeat_n(idx, span(y, idx).len()))
+                    .collect()
+            })
+            .collect();
+
+        let grapheme_at = |x: usize, y: usize| {
+            let idx = *owners.get(y)?.get(x)?;
+            Some((idx, self.grapheme(&self.graphemes[y][idx])))
+        };
+        let target = match grapheme_at(x, y) {
+            Some((_, target)) if target != fill.to_string() => target,
+            _ => return,
+        };
+
+        let mut filled: Vec<Vec<bool>> = self
+            .graphemes
+            .iter()
+            .map(|line| vec![false; line.len()])
+            .collect();
+        let mut stack = vec![(x, y)];
+        while let Some((x, y)) = stack.pop() {
+            let idx = match grapheme_at(x, y) {
+                Some((idx, grapheme)) if grapheme == target && !filled[y][idx] => idx,
+                _ => continue,
+            };
+            filled[y][idx] = true;
+
+            // Every column of a wide grapheme leads on to its neighbors
+            for x in span(y, idx) {
+                stack.push((x + 1, y));
+                stack.push((x, y + 1));
+                if x > 0 {
+                    stack.push((x - 1, y));
+                }
+                if y > 0 {
+                    stack.push((x, y - 1));
+                }
+            }
+        }
+
+        let mut data = String::with_capacity(self.data.len());
+        for (y, filled) in filled.iter().enumerate() {
+            if y > 0 {
+                data.push('\n');
+            }
+            for (idx, grapheme) in self.graphemes[y].iter().enumerate() {
+                if filled[idx] {
+                    data.extend(std::iter::repeat_n(fill, span(y, idx).len()));
+                } else {
+                    data.push_str(self.grapheme(grapheme));
+                }
+            }
+        }
+        self.update(data);
+    }
+
+    /// Terminal cells are usually about twice as tall as they are wide, so this many columns take up
+    /// about as much space as one line. The shape constructors use this to keep shapes in proportion
+    pub const CELL_ASPECT_RATIO: f32 = 2.0;
+
+    /// Creates a rectangle filled with `glyph`. Sizes are measured in lines, and the width is
+    /// multiplied by `aspect_ratio` to get the number of columns, so equal sizes give a square.
+    pub fn rectangle(width: f32, height: f32, glyph: char, aspect_ratio: f32) -> Sprite {
+        let columns = (width * aspect_ratio).round().max(0.0) as usize;
+        let lines = height.round().max(0.0) as usize;
+        Sprite::from_mask(&vec![vec![true; columns]; lines], glyph)
+    }
+
+    /// Creates a filled circle of `glyph`. The radius is measured in lines, and stretched by
+    /// `aspect_ratio` across the columns, so the circle looks round rather than tall.
+    pub fn circle(radius: f32, glyph: char, aspect_ratio: f32) -> Sprite {
+        let radius = radius.max(0.0);
+        let rows = radius.round() as i32;
+        let columns = (radius * aspect_ratio).round() as i32;
+        // The extra half a cell keeps the cells at the very edge, so small circles aren't diamonds
+        let limit = (radius + 0.5) * (radius + 0.5);
+
+        let mask: Vec<Vec<bool>> = (-rows..=rows)
+            .map(|y| {
+                (-columns..=columns)
+                    .map(|x| {
+                        let x = x as f32 / aspect_ratio;
+                        let y = y as f32;
+                        x * x + y * y <= limit
+                    })
+                    .collect()
+            })
+            .collect();
+        Sprite::from_mask(&mask, glyph)
+    }
+
+    /// Creates a straight line of `glyph` between two `(x, y)` points. Points are measured in lines,
+    /// and `x` is multiplied by `aspect_ratio` to get the column, like `rectangle`. The sprite starts at the
+    /// top left of the line, so it should be positioned at the smaller of the two `x`s and `y`s.
+    pub fn line(from: (f32, f32), to: (f32, f32), glyph: char, aspect_ratio: f32) -> Sprite {
+        let cell = |(x, y): (f32, f32)| ((x * aspect_ratio).round() as i32, y.round() as i32);
+        let ((x0, y0), (x1, y1)) = (cell(from), cell(to));
+        let (left, top) = (std::cmp::min(x0, x1), std::cmp::min(y0, y1));
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let mut mask = vec![vec![false; dx as usize + 1]; (-dy) as usize + 1];
+
+        // Bresenham's line algorithm
+        let (step_x, step_y) = ((x1 - x0).signum(), (y1 - y0).signum());
+        let (mut x, mut y) = (x0, y0);
+        let mut error = dx + dy;
+        loop {
+            mask[(y - top) as usize][(x - left) as usize] = true;
+            if x == x1 && y == y1 {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+
+        Sprite::from_mask(&mask, glyph)
+    }
+
+    /// A sprite with `glyph` wherever the mask is true, and spaces everywhere else
+    fn from_mask(mask: &[Vec<bool>], glyph: char) -> Sprite {
+        let lines: Vec<String> = mask
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|filled| if *filled { glyph } else { ' ' })
+                    .collect()
+            })
+            .collect();
+        Sprite::new(lines.join("\n"))
+    }
+
+    /// Returns a copy of this sprite with a border drawn around it
+    #[must_use]
+    pub fn bordered(&self, border: BorderStyle) -> Sprite {
+        let [top_left, top_right, bottom_left, bottom_right, horizontal, vertical] = border.chars();
+        let edge = horizontal.to_string().repeat(self.width());
+
+        let mut data = format!("{top_left}{edge}{top_right}\n");
+        for (line_num, line) in self.graphemes.iter().enumerate() {
+            data.push(vertical);
+            for grapheme in line {
+                data.push_str(self.grapheme(grapheme));
+            }
+            data.push_str(&" ".repeat(self.width() - self.line_width(line_num)));
+            data.push(vertical);
+            data.push('\n');
+        }
+        data.push_str(&format!("{bottom_left}{edge}{bottom_right}"));
+
+        Sprite::new(data)
+    }
+
+    /// Returns a copy of this sprite with each line moved `offset_per_row` columns further right than
+    /// the line above it, like italics. Negative offsets lean the other way. The sprite grows to fit.
+    #[must_use]
+    pub fn shear_horizontal(&self, offset_per_row: i32) -> Sprite {
+        // With a negative offset the last line is the furthest left, so everything is moved right to fit
+        let shift = |row: usize| {
+            let last = (self.height().saturating_sub(1) as i32).saturating_mul(offset_per_row);
+            (row as i32 * offset_per_row - std::cmp::min(0, last)) as usize
+        };
+
+        let mut data = String::new();
+        for (line_num, line) in self.graphemes.iter().enumerate() {
+            if line_num > 0 {
+                data.push('\n');
+            }
+            data.push_str(&" ".repeat(shift(line_num)));
+            for grapheme in line {
+                data.push_str(self.grapheme(grapheme));
+            }
+        }
+
+        Sprite::new(data)
+    }
+
+    /// Returns a copy of this sprite with each column moved `offset_per_column` lines further down than
+    /// the column to its left. Negative offsets lean the other way. The sprite grows to fit. Wide
+    /// graphemes whose halves end up on different lines are replaced with spaces.
+    #[must_use]
+    pub fn shear_vertical(&self, offset_per_column: i32) -> Sprite {
+        let width = self.width();
+        let last = (width.saturating_sub(1) as i32).saturating_mul(offset_per_column);
+        let shift =
+            |column: usize| (column as i32 * offset_per_column - std::cmp::min(0, last)) as usize;
+        let height = self.height() + last.unsigned_abs() as usize;
+
+        // Lay out every cell, with an empty string for the second half of wide graphemes
+        let mut cells = vec![vec![" "; width]; height];
+        for y in 0..self.height() {
+            for x in 0..width {
+                let cell = match self.grapheme_at(x, y) {
+                    Some(grapheme) => grapheme,
+                    None if self.is_continuation(x, y) => "",
+                    None => " ",
+                };
+                cells[y + shift(x)][x] = cell;
+            }
+        }
+
+        let mut data = String::new();
+        for (y, row) in cells.iter().enumerate() {
+            if y > 0 {
+                data.push('\n');
+            }
+            for (x, cell) in row.iter().enumerate() {
+                let next = row.get(x + 1).copied();
+                if cell.is_empty() {
+                    // Already covered by the first half, unless that ended up somewhere else
+                    let previous = x.checked_sub(1).map(|x| row[x]);
+                    if !previous.is_some_and(|previous| grapheme_width(previous) == 2) {
+                        data.push(' ');
+                    }
+                } else if grapheme_width(cell) == 2 && next != Some("") {
+                    data.push(' ');
+                } else {
+                    data.push_str(cell);
+                }
+            }
+        }
+
+        Sprite::new(data)
+    }
+
+    /// Returns the part of this sprite inside `rect`, which is measured in columns and lines. The
+    /// parts of `rect` outside the sprite are left out, and wide graphemes which are cut in half are
+    /// replaced with a space.
+    #[must_use]
+    pub fn crop(&self, rect: URect) -> Sprite {
+        let (left, right) = (rect.min.x as usize, rect.max.x as usize);
+        let bottom = std::cmp::min(rect.max.y as usize, self.height());
+
+        let mut data = String::new();
+        for y in (rect.min.y as usize)..bottom {
+            if y > rect.min.y as usize {
+                data.push('\n');
+            }
+            let right = std::cmp::min(right, self.line_width(y));
+            let mut x = left;
+            while x < right {
+                match self.grapheme_at(x, y) {
+                    Some(grapheme) if x + grapheme_width(grapheme) <= right => {
+                        data.push_str(grapheme);
+                        x += grapheme_width(grapheme);
+                    }
+                    _ => {
+                        data.push(' ');
+                        x += 1;
+                    }
+                }
+            }
+        }
+
+        Sprite::new(data)
+    }
+}
+
+/// Statistics about a sprite, returned by `Sprite::metrics`
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SpriteMetrics {
+    /// The width of the widest row, in terminal columns
+    pub width: usize,
+    pub height: usize,
+    /// The number of graphemes in the sprite, not counting the padding at the end of shorter rows
+    pub cell_count: usize,
+    pub distinct_graphemes: usize,
+    /// Whether every row is the same width. Sprites where this is false are padded out with spaces
+    /// when they're drawn.
+    pub uniform_width: bool,
+    /// The width of each row, in terminal columns
+    pub row_widths: Vec<usize>,
+}
+
+/// The size of the pattern `Sprite::dither` uses
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug)]
+pub enum BayerMatrix {
+    /// 2x2, which can show 3 shades between each pair of characters
+    Two,
+    /// 4x4, which can show 15 shades between each pair of characters
+    #[default]
+    Four,
+}
+
+impl BayerMatrix {
+    /// The order each cell of the pattern is filled in
+    fn thresholds(self) -> &'static [&'static [u8]] {
+        match self {
+            BayerMatrix::Two => &[&[0, 2], &[3, 1]],
+            BayerMatrix::Four => &[
+                &[0, 8, 2, 10],
+                &[12, 4, 14, 6],
+                &[3, 11, 1, 9],
+                &[15, 7, 13, 5],
+            ],
+        }
+    }
+}
+
+/// Something found by `Sprite::validate`
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SpriteProblem {
+    /// The line is narrower than the sprite. It's padded out with spaces when drawn, which can hide
+    /// missing characters
+    RaggedLine,
+    /// A grapheme which takes up two columns, like most emoji and CJK characters. Some fonts and
+    /// terminals draw these a single column wide, which shifts the rest of the line
+    WideGlyph,
+    /// A control character like a tab, which terminals move the cursor for instead of drawing
+    ControlChar,
+    /// A combining character, like an accent, at the start of a line with nothing to combine with
+    CombiningWithoutBase,
+}
+
+/// A problem found in a sprite by `Sprite::validate`
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SpriteWarning {
+    /// The column the problem is at. For ragged lines this is where the padding starts
+    pub x: usize,
+    pub y: usize,
+    /// The grapheme with the problem, which is empty for ragged lines
+    pub grapheme: String,
+    pub problem: SpriteProblem,
+}
+
+impl SpriteWarning {
+    /// How the problem could be fixed
+    pub fn suggestion(&self) -> &'static str {
+        match self.problem {
+            SpriteProblem::RaggedLine => "pad the line with spaces to the width of the sprite",
+            SpriteProblem::WideGlyph => {
+                "use a single width character, or check it looks right in the terminals you support"
+            }
+            SpriteProblem::ControlChar => "replace it with spaces or a visible character",
+            SpriteProblem::CombiningWithoutBase => {
+                "put the character it should combine with in front of it, or remove it"
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for SpriteWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let problem = match self.problem {
+            SpriteProblem::RaggedLine => "the line is narrower than the sprite",
+            SpriteProblem::WideGlyph => "wide character",
+            SpriteProblem::ControlChar => "control character",
+            SpriteProblem::CombiningWithoutBase => {
+                "combining character with nothing to combine with"
+            }
+        };
+        write!(f, "line {}, column {}: {problem}", self.y + 1, self.x + 1)?;
+        if !self.grapheme.is_empty() {
+            write!(f, " {:?}", self.grapheme)?;
+        }
+        write!(f, ", {}", self.suggestion())
+    }
+}
+
+/// The characters used to draw a border with `Sprite::bordered`
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug)]
+pub enum BorderStyle {
+    /// `+`, `-` and `|`, which every terminal can display
+    Ascii,
+    #[default]
+    Single,
+    Double,
+    Rounded,
+    Thick,
+}
+
+impl BorderStyle {
+    /// The top left, top right, bottom left, bottom right, horizontal and vertical characters
+    pub fn chars(&self) -> [char; 6] {
+        match self {
+            BorderStyle::Ascii => ['+', '+', '+', '+', '-', '|'],
+            BorderStyle::Single => ['┌', '┐', '└', '┘', '─', '│'],
+            BorderStyle::Double => ['╔', '╗', '╚', '╝', '═', '║'],
+            BorderStyle::Rounded => ['╭', '╮', '╰', '╯', '─', '│'],
+            BorderStyle::Thick => ['┏', '┓', '┗', '┛', '━', '┃'],
+        }
+    }
+}
+
+/// Writes the sprite back out as text, one line per row. Line endings are always written as `\n`,
+/// so `Sprite::new(sprite.to_string())` produces the same rows.
+impl std::fmt::Display for Sprite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (line_num, line) in self.graphemes.iter().enumerate() {
+            if line_num > 0 {
+                f.write_str("\n")?;
+            }
+            for grapheme in line {
+                f.write_str(self.grapheme(grapheme))?;
+            }
+        }
+
+        // `Sprite::new` ignores the text after the last newline if it's empty, so a blank last line
+        // needs its own newline to survive
+        if self.graphemes.last().is_some_and(|line| line.is_empty()) {
+            f.write_str("\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the bounds of two sprites overlap. Sprites which are only touching don't overlap.
+pub fn sprites_overlap(a_pos: &Position, a: &Sprite, b_pos: &Position, b: &Sprite) -> bool {
+    !a.bounds(a_pos).intersect(b.bounds(b_pos)).is_empty()
+}
+
+/// Whether two sprites have any cells in the same place. Unlike `sprites_overlap`, this ignores cells
+/// that are empty, like spaces and the padding at the end of short lines, so two sprites can overlap
+/// where one of them is transparent without colliding.
+pub fn sprites_overlap_precise(a_pos: &Position, a: &Sprite, b_pos: &Position, b: &Sprite) -> bool {
+    let overlap = a.bounds(a_pos).intersect(b.bounds(b_pos));
+    if overlap.is_empty() {
+        return false;
+    }
+
+    for y in overlap.min.y..overlap.max.y {
+        for x in overlap.min.x..overlap.max.x {
+            let a_filled = a.is_filled((x - a_pos.x) as usize, (y - a_pos.y) as usize);
+            let b_filled = b.is_filled((x - b_pos.x) as usize, (y - b_pos.y) as usize);
+            if a_filled && b_filled {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+/// Named bands of z values, so different parts of a game agree on what's drawn on top of what. Each
+/// layer covers `Layer::SIZE` z values, centered on `Layer::base`, from lowest to highest in the order
+/// listed. A z of 0 is in the middle of `World`.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum Layer {
+    Background,
+    World,
+    Ui,
+    Overlay,
+    Tooltip,
+}
+
+impl Layer {
+    /// How many z values each layer covers
+    pub const SIZE: i32 = 1000;
+
+    /// The z in the middle of the layer
+    pub fn base(self) -> i32 {
+        let index = match self {
+            Layer::Background => -1,
+            Layer::World => 0,
+            Layer::Ui => 1,
+            Layer::Overlay => 2,
+            Layer::Tooltip => 3,
+        };
+        index * Layer::SIZE
+    }
+
+    /// Every z value in the layer
+    pub fn z_range(self) -> std::ops::RangeInclusive<i32> {
+        let half = Layer::SIZE / 2;
+        self.base() - half..=self.base() + half - 1
+    }
+
+    /// The z value `sub_z` away from the middle of the layer, kept inside the layer
+    pub fn z(self, sub_z: i32) -> i32 {
+        let range = self.z_range();
+        self.base()
+            .saturating_add(sub_z)
+            .clamp(*range.start(), *range.end())
+    }
+}
+
+/// Where an entity's sprite is drawn. If the entity has a `Parent`, this is relative to the parent's
+/// `Position`, so moving the parent moves its children too. `GridSnap` rounds the relative position
+/// when drawing, without changing this.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug, Component)]
+pub struct Position {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+impl Position {
+    pub fn new(x: i32, y: i32, z: i32) -> Position {
+        Position { x, y, z }
+    }
+
+    pub fn with_x(x: i32) -> Position {
+        Position {
+            x,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_y(y: i32) -> Position {
+        Position {
+            y,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_xy(x: i32, y: i32) -> Position {
+        Position {
+            x,
+            y,
+            ..Default::default()
+        }
+    }
+
+    /// A position whose z is `sub_z` within the given layer. `sub_z` is clamped to the layer's range, so
+    /// the sprite is always drawn above every sprite in a lower layer.
+    pub fn in_layer(layer: Layer, sub_z: i32) -> Position {
+        Position {
+            z: layer.z(sub_z),
+            ..Default::default()
+        }
+    }
+}
+
+/// Moves an entity's `Position` at a constant rate, measured in cells per second.
+///
+/// Since positions are whole cells, fractional movement is accumulated between frames and only
+/// applied once it adds up to at least one cell. This keeps motion smooth regardless of how long
+/// each frame takes.
+#[derive(Default, PartialEq, Debug, Component)]
+pub struct Velocity {
+    pub x: f32,
+    pub y: f32,
+    // Movement which hasn't added up to a whole cell yet
+    pub(crate) remainder_x: f32,
+    pub(crate) remainder_y: f32,
+}
+
+impl Velocity {
+    pub fn new(x: f32, y: f32) -> Velocity {
+        Velocity {
+            x,
+            y,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_x(x: f32) -> Velocity {
+        Velocity {
+            x,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_y(y: f32) -> Velocity {
+        Velocity {
+            y,
+            ..Default::default()
+        }
+    }
+}
+
+/// Marks a sprite which rarely changes, such as a large background.
+///
+/// Static sprites are drawn once into a cached layer, and every frame is built on top of that layer
+/// instead of drawing them again. The layer is only redrawn when a static sprite, its assets, or the
+/// window change. Since they're in their own layer, static sprites are always drawn underneath
+/// sprites that aren't static, regardless of their z.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug, Component)]
+pub struct Static;
+
+/// Marks a sprite which can be clicked on. A `SpriteClicked` event is sent when a mouse button is
+/// pressed over it, as long as no other clickable sprite is drawn on top of it there. The spaces in
+/// transparent sprites can be clicked through. `SpriteHoverEnter` and `SpriteHoverExit` are sent as
+/// the mouse moves on and off it the same way.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug, Component)]
+pub struct Clickable;
+
+/// Turns parts of a sprite into hyperlinks, which terminals that support OSC 8 let the user click
+/// on to open. Elsewhere the sprite is drawn as normal text.
+///
+/// Each link covers a rectangle of the sprite's cells. Where links overlap, the one added last wins.
+#[derive(Clone, Default, Eq, PartialEq, Debug, Component)]
+pub struct Hyperlinks {
+    links: Vec<(URect, std::sync::Arc<str>)>,
+}
+
+impl Hyperlinks {
+    pub fn new() -> Hyperlinks {
+        Hyperlinks::default()
+    }
+
+    /// Links the cells in `area` to `url`. URLs can only contain printable ASCII, so anything else
+    /// has to be percent encoded.
+    pub fn with_link(mut self, area: URect, url: &str) -> Hyperlinks {
+        self.add(area, url);
+        self
+    }
+
+    pub fn add(&mut self, area: URect, url: &str) {
+        self.links.push((area, url.into()));
+    }
+
+    /// The URL the cell at x,y of the sprite links to, if any
+    pub fn url_at(&self, x: usize, y: usize) -> Option<&std::sync::Arc<str>> {
+        let (x, y) = (x as u32, y as u32);
+        self.links
+            .iter()
+            .rev()
+            .find(|(area, _)| {
+                (area.min.x..area.max.x).contains(&x) && (area.min.y..area.max.y).contains(&y)
+            })
+            .map(|(_, url)| url)
+    }
+}
+
+/// Decides which of two sprites with the same z gets drawn on top, without having to change their z.
+///
+/// Sprites are drawn in order of their z, then their `RenderPriority`, then their `Entity`, with the
+/// highest drawn last. Sprites without a `RenderPriority` have a priority of 0.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Debug, Component)]
+pub struct RenderPriority(pub i32);
+
+/// What happens to the parts of a sprite that are outside the window. Sprites without an `Overflow`
+/// use the one from `CrosstermWindowSettings`, which is `Clip` by default. The edges are those of the
+/// window's drawable area, so the safe area and the space around a fixed canvas count as outside.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug, Component)]
+pub enum Overflow {
+    /// Only the parts inside the window are drawn
+    #[default]
+    Clip,
+    /// The parts outside the window wrap around to the opposite edge
+    Wrap,
+    /// The sprite isn't drawn at all unless it's entirely inside the window
+    Hidden,
+}
+
+/// Keeps an entity's sprite inside the `CrosstermWindow`, by moving its `Position` back whenever it
+/// would leave the window. Sprites which are larger than the window are kept at the top left. For a
+/// child entity it's where the sprite ends up after adding its parents' positions that's kept inside.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug, Component)]
+pub enum ClampToWindow {
+    /// The whole sprite must stay inside the window
+    #[default]
+    Fully,
+    /// At least this many columns and rows of the sprite must stay inside the window
+    Partially { columns: u16, rows: u16 },
+}
+
+/// Draws an entity at its `Position` rounded to the nearest multiple of the grid size, so it always
+/// lands on a tile boundary. The `Position` itself isn't rounded, so a `Velocity` slower than a grid
+/// cell per frame still moves the entity a whole tile at a time.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Component)]
+pub struct GridSnap {
+    pub cell_w: u16,
+    pub cell_h: u16,
+}
+
+impl GridSnap {
+    pub fn new(cell_w: u16, cell_h: u16) -> GridSnap {
+        GridSnap { cell_w, cell_h }
+    }
+}
+
+/// Where the caret is within an entity's sprite, for `CursorMode::AtCaret`. Text inputs should
+/// move this as text is typed.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug, Component)]
+pub struct Caret {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// Decides the order in which a `Transition` reveals the cells of its target sprite
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug)]
+pub enum DissolveMask {
+    /// Cells are revealed left to right, top to bottom
+    #[default]
+    Ordered,
+    /// Cells are revealed in a random order
+    Random,
+}
+
+/// Dissolves an entity's sprite from `source` into `target` over the duration of `timer`.
+///
+/// While the transition is running the entity's sprite is replaced by a blend of both sprites. Once
+/// the timer finishes the entity's sprite is set to `target`, this component is removed, and a
+/// `TransitionFinished` event is sent.
+#[derive(Component)]
+pub struct Transition {
+    pub source: Handle<Sprite>,
+    pub target: Handle<Sprite>,
+    pub timer: Timer,
+    pub mask: DissolveMask,
+    // The sprite which is displayed while the transition is in progress
+    pub(crate) blended: Option<Handle<Sprite>>,
+    // Indices of cells in the order they should be revealed
+    pub(crate) reveal_order: Vec<usize>,
+    pub(crate) revealed: usize,
+}
+
+impl Transition {
+    pub fn new(
+        source: Handle<Sprite>,
+        target: Handle<Sprite>,
+        duration: std::time::Duration,
+        mask: DissolveMask,
+    ) -> Transition {
+        Transition {
+            source,
+            target,
+            timer: Timer::new(duration, TimerMode::Once),
+            mask,
+            blended: None,
+            reveal_order: Vec::new(),
+            revealed: 0,
+        }
+    }
+
+    /// How far along the transition is, from 0.0 to 1.0
+    pub fn progress(&self) -> f32 {
+        self.timer.percent()
+    }
+}
+
+/// Sent when an entity's `Transition` has completed
+#[derive(Event, Debug)]
+pub struct TransitionFinished {
+    pub entity: Entity,
+}
+
+/// Fades an entity's sprite away into a solid color over the duration of `timer`, one cell at a
+/// time in the order given by `mask`.
+///
+/// While it's fading the entity's sprite and stylemap are replaced by ones where the faded cells are
+/// blank and filled with `color`, or with the window's colors if `color` is `None`. Once the timer
+/// finishes every cell has faded, a `FadeFinished` event is sent, and either the entity is despawned
+/// or this component is removed, leaving the faded sprite behind.
+#[derive(Component)]
+pub struct FadeOut {
+    pub color: Option<Color>,
+    pub timer: Timer,
+    pub mask: DissolveMask,
+    /// Despawns the entity, and its children, once it has faded
+    pub despawn: bool,
+    // The entity's own sprite and stylemap, which are faded from
+    pub(crate) source: Option<(Handle<Sprite>, Handle<StyleMap>)>,
+    // The sprite and stylemap which are displayed while the fade is in progress
+    pub(crate) faded: Option<(Handle<Sprite>, Handle<StyleMap>)>,
+    // Indices of cells in the order they should fade
+    pub(crate) fade_order: Vec<usize>,
+    pub(crate) revealed: usize,
+}
+
+impl FadeOut {
+    pub fn new(color: Color, duration: std::time::Duration, mask: DissolveMask) -> FadeOut {
+        FadeOut {
+            color: Some(color),
+            timer: Timer::new(duration, TimerMode::Once),
+            mask,
+            despawn: false,
+            source: None,
+            faded: None,
+            fade_order: Vec::new(),
+            revealed: 0,
+        }
+    }
+
+    /// Fades into the window's colors, as if the sprite was never there
+    pub fn to_background(duration: std::time::Duration, mask: DissolveMask) -> FadeOut {
+        FadeOut {
+            color: None,
+            ..FadeOut::new(Color::Reset, duration, mask)
+        }
+    }
+
+    pub fn despawn_when_finished(mut self) -> FadeOut {
+        self.despawn = true;
+        self
+    }
+
+    /// How far along the fade is, from 0.0 to 1.0
+    pub fn progress(&self) -> f32 {
+        self.timer.percent()
+    }
+}
+
+/// Sent when an entity's `FadeOut` has completed. If the fade despawns the entity, it's already
+/// gone by the time this is read.
+#[derive(Event, Debug)]
+pub struct FadeFinished {
+    pub entity: Entity,
+}
+
+/// Types out an entity's sprite one grapheme at a time, in reading order, like dialogue in a game.
+///
+/// While it's typing the entity's sprite is replaced by one where the graphemes that haven't been
+/// typed yet are spaces. Once every grapheme has been typed the entity's sprite is set to `target`,
+/// this component is removed, and a `TypewriterDone` event is sent.
+#[derive(Component)]
+pub struct Typewriter {
+    pub target: Handle<Sprite>,
+    /// How many graphemes are typed each second
+    pub chars_per_second: f32,
+    /// Pressing this key types out the rest of the sprite at once
+    pub skip_key: Option<crossterm::event::KeyCode>,
+    pub(crate) elapsed: f32,
+    pub(crate) skipped: bool,
+    // The sprite which is displayed while typing, and how many graphemes it shows
+    pub(crate) typed: Option<Handle<Sprite>>,
+    pub(crate) revealed: usize,
+}
+
+impl Typewriter {
+    pub fn new(target: Handle<Sprite>, chars_per_second: f32) -> Typewriter {
+        Typewriter {
+            target,
+            chars_per_second,
+            skip_key: None,
+            elapsed: 0.0,
+            skipped: false,
+            typed: None,
+            revealed: usize::MAX,
+        }
+    }
+
+    pub fn with_skip_key(mut self, key: crossterm::event::KeyCode) -> Typewriter {
+        self.skip_key = Some(key);
+        self
+    }
+
+    /// How many graphemes have been typed so far
+    pub fn typed_chars(&self) -> usize {
+        if self.skipped {
+            usize::MAX
+        } else {
+            (self.elapsed * self.chars_per_second).max(0.0) as usize
+        }
+    }
+
+    /// Types out the rest of the sprite on the next update
+    pub fn skip(&mut self) {
+        self.skipped = true;
+    }
+}
+
+/// Sent when an entity's `Typewriter` has typed out all of its sprite
+#[derive(Event, Debug)]
+pub struct TypewriterDone {
+    pub entity: Entity,
+}
+
+/// Blinks an entity by showing and hiding it, for terminals which don't support the blink attributes
+/// or when the blink speed matters. The entity is shown for the first half of every `period` and
+/// hidden for the second half.
+///
+/// Every `SoftBlink` follows the same clock, the time since the app started, so blinking entities
+/// stay in step with each other. Give them different `phase_offset`s to have them blink at different
+/// times instead.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Component)]
+pub struct SoftBlink {
+    pub period: std::time::Duration,
+    /// How far ahead of the clock this entity's blinking is
+    pub phase_offset: std::time::Duration,
+}
+
+impl SoftBlink {
+    pub fn new(period: std::time::Duration) -> SoftBlink {
+        SoftBlink {
+            period,
+            phase_offset: std::time::Duration::ZERO,
+        }
+    }
+
+    pub fn with_phase_offset(mut self, phase_offset: std::time::Duration) -> SoftBlink {
+        self.phase_offset = phase_offset;
+        self
+    }
+
+    /// Whether the entity is shown at the given time since the app started
+    pub fn is_visible_at(&self, elapsed: std::time::Duration) -> bool {
+        if self.period.is_zero() {
+            return true;
+        }
+        let phase = (elapsed + self.phase_offset).as_nanos() % self.period.as_nanos();
+        phase < self.period.as_nanos() / 2
+    }
+}
+
+/// Cycles an entity's sprite through frames cropped out of a single sprite sheet, so an animation
+/// doesn't need a separate asset for every frame.
+///
+/// Each frame is a rectangle on the sheet, measured in columns and lines. The frames are cropped
+/// with `Sprite::crop` once the sheet has loaded, and again whenever it's modified. If the sheet has
+/// a stylemap, it's cropped with the same rectangles so the colors stay lined up with each frame.
+#[derive(Component)]
+pub struct AnimatedSprite {
+    pub sheet: Handle<Sprite>,
+    pub stylemap: Option<Handle<StyleMap>>,
+    pub frame_rects: Vec<URect>,
+    pub timer: Timer,
+    pub(crate) frame: usize,
+    // The cropped frames, in the same order as frame_rects
+    pub(crate) frames: Vec<(Handle<Sprite>, Option<Handle<StyleMap>>)>,
+}
+
+impl AnimatedSprite {
+    pub fn from_sheet(
+        sheet: Handle<Sprite>,
+        frame_rects: Vec<URect>,
+        frame_time: std::time::Duration,
+    ) -> AnimatedSprite {
+        AnimatedSprite {
+            sheet,
+            stylemap: None,
+            frame_rects,
+            timer: Timer::new(frame_time, TimerMode::Repeating),
+            frame: 0,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Sets a stylemap for the sheet, which is cropped along with it. Without one the entity keeps
+    /// its own stylemap.
+    pub fn with_stylemap(mut self, stylemap: Handle<StyleMap>) -> AnimatedSprite {
+        self.stylemap = Some(stylemap);
+        self
+    }
+
+    /// The index of the frame being shown
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    /// Jumps to a frame. Frames past the end wrap around to the start.
+    pub fn set_frame(&mut self, frame: usize) -> &mut Self {
+        self.frame = frame;
+        self
+    }
+}
+
+/// Where a `StreamingSprite` reads its text from
+#[derive(Debug)]
+enum StreamSource {
+    Text(String),
+    File {
+        reader: std::io::BufReader<std::fs::File>,
+        // Where the reader is in the file, if that's known
+        position: Option<u64>,
+    },
+}
+
+/// Shows a window of lines from a document which is too big to keep in a `Sprite`, like a huge log
+/// file. Only the lines that are visible are turned into a sprite, which replaces the entity's
+/// `Handle<Sprite>` whenever the scroll position changes.
+///
+/// Lines are found lazily, so only the part of the document up to the bottom of the window has
+/// been read. Files are read from disk as needed, and a read error is treated as the end of the file.
+#[derive(Debug, Component)]
+pub struct StreamingSprite {
+    source: StreamSource,
+    // The byte offset each line found so far starts at
+    line_starts: Vec<u64>,
+    // Where to start looking for the next line
+    indexed_to: u64,
+    reached_end: bool,
+    scroll: usize,
+    visible_lines: usize,
+    tab_width: usize,
+    // The scroll position, height and tab width of the lines in the rendered sprite
+    pub(crate) shown: Option<(usize, usize, usize)>,
+    pub(crate) rendered: Option<Handle<Sprite>>,
+}
+
+impl StreamingSprite {
+    /// Streams lines from a string that's already in memory
+    pub fn from_string<T: Into<String>>(text: T, visible_lines: usize) -> StreamingSprite {
+        StreamingSprite::with_source(StreamSource::Text(text.into()), visible_lines)
+    }
+
+    /// Streams lines from a file, which is read as needed instead of all at once
+    pub fn from_file<P: AsRef<std::path::Path>>(
+        path: P,
+        visible_lines: usize,
+    ) -> std::io::Result<StreamingSprite> {
+        let file = std::fs::File::open(path)?;
+        Ok(StreamingSprite::with_source(
+            StreamSource::File {
+                reader: std::io::BufReader::new(file),
+                position: Some(0),
+            },
+            visible_lines,
+        ))
+    }
+
+    fn with_source(source: StreamSource, visible_lines: usize) -> StreamingSprite {
+        StreamingSprite {
+            source,
+            line_starts: vec![0],
+            indexed_to: 0,
+            reached_end: false,
+            scroll: 0,
+            visible_lines,
+            tab_width: 8,
+            shown: None,
+            rendered: None,
+        }
+    }
+
+    /// The first visible line
+    pub fn scroll(&self) -> usize {
+        self.scroll
+    }
+
+    /// Scrolls so the given line is at the top. Scrolling past the end of the document stops at the
+    /// last line.
+    pub fn set_scroll(&mut self, line: usize) -> &mut Self {
+        self.scroll = line;
+        self
+    }
+
+    /// Scrolls up (negative) or down (positive) by a number of lines
+    pub fn scroll_by(&mut self, lines: isize) -> &mut Self {
+        self.scroll = self.scroll.saturating_add_signed(lines);
+        self
+    }
+
+    /// How many lines are shown at once
+    pub fn visible_lines(&self) -> usize {
+        self.visible_lines
+    }
+
+    pub fn set_visible_lines(&mut self, visible_lines: usize) -> &mut Self {
+        self.visible_lines = visible_lines;
+        self
+    }
+
+    /// How many columns apart the tab stops are
+    pub fn tab_width(&self) -> usize {
+        self.tab_width
+    }
+
+    /// Tabs are replaced with spaces up to the next tab stop, so text lined up with tabs stays lined
+    /// up. Stops are 8 columns apart by default. With a width of 0, tabs are left in the text.
+    pub fn set_tab_width(&mut self, tab_width: usize) -> &mut Self {
+        self.tab_width = tab_width;
+        self
+    }
+
+    /// The number of lines in the document, if all of it has been read. Otherwise `None`. A newline at
+    /// the end of the document ends the last line, it doesn't start another one.
+    pub fn line_count(&self) -> Option<usize> {
+        self.reached_end.then_some(self.line_starts.len())
+    }
+
+    /// Finds the start of lines until at least `lines + 1` are known, or the document ends
+    fn index_lines(&mut self, lines: usize) {
+        use std::io::{BufRead, Seek};
+
+        let mut line = Vec::new();
+        while self.line_starts.len() <= lines && !self.reached_end {
+            // How long the next line is, and whether it ends with a newline
+            let (length, ended) = match &mut self.source {
+                StreamSource::Text(text) => {
+                    let rest = &text[self.indexed_to as usize..];
+                    rest.find('\n')
+                        .map_or((rest.len(), false), |idx| (idx + 1, true))
+                }
+                StreamSource::File { reader, position } => {
+                    // Reading carries on from the last line, so the reader only has to seek when
+                    // `read_lines` has moved it
+                    line.clear();
+                    let read = match *position == Some(self.indexed_to) {
+                        true => Ok(()),
+                        false => reader
+                            .seek(std::io::SeekFrom::Start(self.indexed_to))
+                            .map(|_| ()),
+                    }
+                    .and_then(|_| reader.read_until(b'\n', &mut line));
+                    match read {
+                        Ok(length) => {
+                            *position = Some(self.indexed_to + length as u64);
+                            (length, line.ends_with(b"\n"))
+                        }
+                        Err(_) => {
+                            *position = None;
+                            (0, false)
+                        }
+                    }
+                }
+            };
+
+            if ended {
+                self.indexed_to += length as u64;
+                self.line_starts.push(self.indexed_to);
+            } else {
+                self.reached_end = true;
+                // Nothing comes after the last newline, so it didn't start a line after all
+                if length == 0 && self.line_starts.len() > 1 {
+                    self.line_starts.pop();
+                }
+            }
+        }
+    }
+
+    /// Reads the text from the start of line `from` up to the start of line `to`
+    fn read_lines(&mut self, from: usize, to: usize) -> String {
+        use std::io::{Read, Seek};
+
+        let start = self.line_starts[from];
+        let end = self.line_starts.get(to).copied();
+        match &mut self.source {
+            StreamSource::Text(text) => {
+                let end = end.map_or(text.len(), |end| end as usize);
+                text[start as usize..end].to_string()
+            }
+            StreamSource::File { reader, position } => {
+                let mut bytes = Vec::new();
+                let read = reader
+                    .seek(std::io::SeekFrom::Start(start))
+                    .and_then(|_| match end {
+                        Some(end) => reader.take(end - start).read_to_end(&mut bytes),
+                        None => reader.read_to_end(&mut bytes),
+                    });
+                *position = read.as_ref().ok().map(|_| start + bytes.len() as u64);
+                if read.is_err() {
+                    bytes.clear();
+                }
+                String::from_utf8_lossy(&bytes).into_owned()
+            }
+        }
+    }
+
+    /// Builds the text of the visible lines, moving the scroll position back if it's past the end
+    pub(crate) fn visible_text(&mut self) -> String {
+        self.index_lines(self.scroll + self.visible_lines);
+        if self.reached_end {
+            self.scroll = std::cmp::min(self.scroll, self.line_starts.len() - 1);
+        }
+
+        let end = std::cmp::min(self.scroll + self.visible_lines, self.line_starts.len());
+        let mut text = self.read_lines(self.scroll, end);
+        // The sprite shouldn't get an extra empty line from the last line's newline
+        if text.ends_with('\n') {
+            text.pop();
+            if text.ends_with('\r') {
+                text.pop();
+            }
+        }
+        expand_tabs(&text, self.tab_width)
+    }
+}
+
+/// Replaces each tab with spaces up to the next multiple of `tab_width` columns, counting columns
+/// from the start of its line. Nothing is replaced when `tab_width` is 0.
+pub(crate) fn expand_tabs(text: &str, tab_width: usize) -> String {
+    if tab_width == 0 || !text.contains('\t') {
+        return text.to_string();
+    }
+
+    let mut expanded = String::with_capacity(text.len());
+    let mut column = 0;
+    for grapheme in text.graphemes(true) {
+        match grapheme {
+            "\t" => {
+                let spaces = tab_width - column % tab_width;
+                expanded.extend(std::iter::repeat_n(' ', spaces));
+                column += spaces;
+            }
+            "\n" | "\r\n" | "\r" => {
+                expanded.push_str(grapheme);
+                column = 0;
+            }
+            _ => {
+                expanded.push_str(grapheme);
+                column += grapheme_width(grapheme);
+            }
+        }
+    }
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn sparse_stylemap_falls_back_to_default_style() {
+        // Only the first cell has a style of its own, and it leaves the background unset
+        let stylemap: StyleMap = ron::from_str(
+            r#"(
+                default_style: (attributes: 0, colors: (foreground: Some("red"), background: Some("blue"))),
+                map: [[(attributes: 0, colors: (foreground: Some("green"), background: None))]],
+            )"#,
+        )
+        .unwrap();
+
+        let own = stylemap.style_for(0, 0).colors;
+        assert_eq!(own, Colors::new(Color::Green, Color::Blue));
+        let fallback = Colors::new(Color::Red, Color::Blue);
+        assert_eq!(stylemap.style_for(1, 0).colors, fallback);
+        assert_eq!(stylemap.style_for(0, 1).colors, fallback);
+
+        let (mut app, _) = testing::app(10, 4);
+        testing::spawn_styled(&mut app, "ab\ncd", stylemap, 0, 0);
+        app.update();
+        let frame = app.world.resource::<CellBuffer>();
+        let colors = |x, y| frame.get(x, y).unwrap().style.colors;
+        assert_eq!(colors(0, 0), own);
+        assert_eq!(colors(1, 0), fallback);
+        assert_eq!(colors(1, 1), fallback);
+    }
+
+    #[test]
+    fn sprite_text_round_trips() {
+        for text in [
+            "",
+            "single",
+            "two\nlines",
+            "trailing  \nspaces  ",
+            "blank\n\n\nlines",
+            "ends with a blank line\n\n",
+            "\nstarts with one",
+            "wide 漢字 and e\u{301}",
+        ] {
+            let sprite = Sprite::new(text);
+            let written = sprite.to_string();
+            let reparsed = Sprite::new(written.as_str());
+            assert_eq!(reparsed, sprite, "{text:?} came back as {written:?}");
+            assert_eq!(reparsed.height(), sprite.height(), "{text:?}");
+            assert_eq!(reparsed.width(), sprite.width(), "{text:?}");
+        }
+
+        // Windows line endings come back as plain newlines
+        assert_eq!(Sprite::new("a\r\nb").to_string(), "a\nb");
+        assert_eq!(Sprite::new("keep  \n  me").to_string(), "keep  \n  me");
+    }
+
+    #[test]
+    fn metrics_catch_ragged_sprites() {
+        let metrics = Sprite::new("##\n#漢字#\n#").metrics();
+        assert!(!metrics.uniform_width);
+        assert_eq!(metrics.width, 6);
+        assert_eq!(metrics.height, 3);
+        assert_eq!(metrics.row_widths, vec![2, 6, 1]);
+        assert_eq!(metrics.cell_count, 7);
+        assert_eq!(metrics.distinct_graphemes, 3);
+
+        assert!(Sprite::new("ab\ncd").metrics().uniform_width);
+    }
+
+    #[test]
+    fn validation_finds_every_problem_in_broken_art() {
+        let sprite = Sprite::new("a\tb\n漢x\n\u{301}y\nz");
+        let warning = |x, y, grapheme: &str, problem| SpriteWarning {
+            x,
+            y,
+            grapheme: grapheme.to_string(),
+            problem,
+        };
+        // Ragged lines are reported where their padding starts
+        assert_eq!(
+            sprite.validate(),
+            [
+                warning(1, 0, "\t", SpriteProblem::ControlChar),
+                warning(0, 1, "漢", SpriteProblem::WideGlyph),
+                warning(0, 2, "\u{301}", SpriteProblem::CombiningWithoutBase),
+                warning(2, 2, "", SpriteProblem::RaggedLine),
+                warning(1, 3, "", SpriteProblem::RaggedLine),
+            ]
+        );
+        assert!(sprite.validate().iter().all(|w| !w.suggestion().is_empty()));
+        assert_eq!(Sprite::new("ab\ncd").validate(), []);
+    }
+
+    #[test]
+    fn rows_and_columns_yield_graphemes_where_they_start() {
+        let sprite = Sprite::new("a漢b\nxy");
+        let rows: Vec<(usize, Vec<_>)> = sprite.rows().map(|(y, row)| (y, row.collect())).collect();
+        assert_eq!(
+            rows,
+            [
+                (0, vec![(0, "a"), (1, "漢"), (3, "b")]),
+                (1, vec![(0, "x"), (1, "y")])
+            ]
+        );
+
+        let columns: Vec<(usize, Vec<_>)> = sprite
+            .columns()
+            .map(|(x, column)| (x, column.collect()))
+            .collect();
+        assert_eq!(
+            columns,
+            [
+                (0, vec![(0, "a"), (1, "x")]),
+                (1, vec![(0, "漢"), (1, "y")]),
+                // The second half of the wide grapheme, past the end of the short line
+                (2, vec![]),
+                (3, vec![(0, "b")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn flags_are_one_glyph_over_two_cells() {
+        let flag = "\u{1F1EF}\u{1F1F5}";
+        let sprite = Sprite::new(format!("a{flag}b"));
+        let glyphs: Vec<_> = sprite.glyphs(0).collect();
+        assert_eq!(glyphs, [(0, "a"), (1, flag), (3, "b")]);
+        assert_eq!(sprite.width(), 4);
+        assert_eq!(sprite.grapheme_at(2, 0), None);
+
+        let (mut app, output) = testing::app(10, 2);
+        testing::spawn_sprite(&mut app, sprite.data(), 0, 0);
+        app.update();
+        let frame = app.world.resource::<CellBuffer>();
+        assert_eq!(frame.get(1, 0).unwrap().grapheme, flag);
+        assert!(frame.get(2, 0).unwrap().is_continuation());
+        assert_eq!(frame.get(3, 0).unwrap().grapheme, "b");
+        assert!(output.take().contains(&format!("a{flag}b")));
+    }
+
+    #[test]
+    fn streaming_sprites_only_read_the_visible_lines() {
+        let document: String = (0..100_000).map(|line| format!("line {line}\n")).collect();
+        let (mut app, _) = testing::app(20, 6);
+        let entity = testing::spawn_sprite(&mut app, "", 0, 0);
+        app.world
+            .entity_mut(entity)
+            .insert(StreamingSprite::from_string(document, 5));
+        app.update();
+
+        let shown = |app: &App| {
+            let handle = app.world.get::<Handle<Sprite>>(entity).unwrap();
+            let sprite = app.world.resource::<Assets<Sprite>>().get(handle).unwrap();
+            sprite.data().to_string()
+        };
+        assert_eq!(shown(&app), "line 0\nline 1\nline 2\nline 3\nline 4");
+        assert_eq!(testing::screen(&app)[4], "line 4");
+        let stream = app.world.get::<StreamingSprite>(entity).unwrap();
+        assert!(
+            stream.line_starts.len() <= 6,
+            "{}",
+            stream.line_starts.len()
+        );
+        assert_eq!(stream.line_count(), None);
+
+        // Scrolling reuses the same sprite instead of adding a new one each time
+        let rendered = app.world.get::<Handle<Sprite>>(entity).unwrap().id();
+        for scroll in [10, 50_000, 99_998] {
+            let mut stream = app.world.get_mut::<StreamingSprite>(entity).unwrap();
+            stream.set_scroll(scroll);
+            app.update();
+            assert_eq!(shown(&app).lines().next(), Some(&*format!("line {scroll}")));
+            assert!(shown(&app).lines().count() <= 5);
+        }
+        let handle = app.world.get::<Handle<Sprite>>(entity).unwrap();
+        assert_eq!(handle.id(), rendered);
+        let stream = app.world.get::<StreamingSprite>(entity).unwrap();
+        assert_eq!(stream.line_count(), Some(100_000));
+    }
+
+    #[test]
+    fn streaming_sprites_read_files_as_they_scroll() {
+        let path =
+            std::env::temp_dir().join(format!("bevy_crossterm_stream_{}", std::process::id()));
+        let document: String = (0..1000).map(|line| format!("line {line}\n")).collect();
+        std::fs::write(&path, document).unwrap();
+
+        let (mut app, _) = testing::app(12, 4);
+        let entity = testing::spawn_sprite(&mut app, "", 0, 0);
+        let stream = StreamingSprite::from_file(&path, 3).unwrap();
+        app.world.entity_mut(entity).insert(stream);
+        app.update();
+        assert_eq!(testing::screen(&app)[..3], ["line 0", "line 1", "line 2"]);
+
+        // Reading the visible lines moves the reader, so indexing further has to find its place again
+        for (scroll, top) in [(500, "line 500"), (5000, "line 999"), (1, "line 1")] {
+            let mut stream = app.world.get_mut::<StreamingSprite>(entity).unwrap();
+            stream.set_scroll(scroll);
+            app.update();
+            assert_eq!(testing::screen(&app)[0], top);
+        }
+        assert_eq!(testing::screen(&app)[..3], ["line 1", "line 2", "line 3"]);
+        let stream = app.world.get::<StreamingSprite>(entity).unwrap();
+        assert_eq!(stream.line_count(), Some(1000));
+        assert_eq!(stream.scroll(), 1);
+
+        // Without a newline at the end, the last line still counts
+        std::fs::write(&path, "one\ntwo").unwrap();
+        let mut stream = StreamingSprite::from_file(&path, 5).unwrap();
+        assert_eq!(stream.visible_text(), "one\ntwo");
+        assert_eq!(stream.line_count(), Some(2));
+        std::fs::remove_file(&path).unwrap();
+        assert!(StreamingSprite::from_file(&path, 5).is_err());
+    }
+
+    #[test]
+    fn streamed_tabs_line_up_wherever_the_view_starts() {
+        let log = "time\tlevel\tmessage\n1\tinfo\tstarted\n12345\twarn\tslow\n日本\terror\tdisk\n";
+        let (mut app, _) = testing::app(30, 4);
+        let entity = testing::spawn_sprite(&mut app, "", 0, 0);
+        let mut stream = StreamingSprite::from_string(log, 3);
+        stream.set_scroll(1);
+        app.world.entity_mut(entity).insert(stream);
+        app.update();
+        assert_eq!(
+            testing::screen(&app)[..3],
+            [
+                "1       info    started",
+                "12345   warn    slow",
+                "日本    error   disk"
+            ]
+        );
+
+        // Stops are counted from the start of each line, not the start of the view
+        let mut stream = app.world.get_mut::<StreamingSprite>(entity).unwrap();
+        stream.set_scroll(2).set_tab_width(6);
+        app.update();
+        assert_eq!(
+            testing::screen(&app)[..2],
+            ["12345 warn  slow", "日本  error disk"]
+        );
+        assert_eq!(testing::screen(&app)[2], "");
+    }
+
+    #[test]
+    fn control_characters_are_sanitized() {
+        let text = "a\0b\x1b[2Jc\x08\td\ne\x7f";
+        let dropped = Sprite::new(text);
+        assert_eq!(dropped.data(), "ab[2Jc\td\ne");
+        assert_eq!(dropped.line_width(0), 8);
+        assert_eq!(dropped.width(), 8);
+
+        let replaced = Sprite::with_control_chars(text, ControlChars::Replace('?'));
+        assert_eq!(replaced.data(), "a?b?[2Jc?\td\ne?");
+        assert_eq!(replaced.line_width(0), 11);
+        assert_eq!(replaced.line_width(1), 2);
+
+        // Nothing that could control the terminal reaches it
+        let (mut app, output) = testing::app(20, 3);
+        testing::spawn_sprite(&mut app, text, 0, 0);
+        app.update();
+        let written = output.take();
+        assert!(
+            !written.contains('\0') && !written.contains('\x08'),
+            "{written:?}"
+        );
+        assert!(written.contains("ab[2Jc"), "{written:?}");
+    }
+
+    #[test]
+    fn touching_sprites_dont_overlap() {
+        let block = Sprite::new("##\n##");
+        let wide = Sprite::new("漢");
+        assert_eq!(
+            wide.bounds(&Position::with_xy(3, 0)),
+            IRect::new(3, 0, 5, 1)
+        );
+
+        let origin = Position::with_xy(0, 0);
+        assert!(!sprites_overlap(
+            &origin,
+            &block,
+            &Position::with_xy(2, 0),
+            &block
+        ));
+        assert!(!sprites_overlap(
+            &origin,
+            &block,
+            &Position::with_xy(0, 2),
+            &block
+        ));
+        // The second half of a wide glyph counts
+        assert!(!sprites_overlap(
+            &Position::with_xy(-2, 0),
+            &wide,
+            &origin,
+            &block
+        ));
+        assert!(sprites_overlap(
+            &Position::with_xy(-1, 0),
+            &wide,
+            &origin,
+            &block
+        ));
+    }
+
+    #[test]
+    fn sprites_in_the_same_place_overlap() {
+        let block = Sprite::new("###\n###");
+        let dot = Sprite::new("#");
+        let pos = Position::with_xy(4, 4);
+        assert!(sprites_overlap(&pos, &block, &pos, &block));
+        assert!(sprites_overlap(
+            &pos,
+            &block,
+            &Position::with_xy(5, 5),
+            &dot
+        ));
+        assert!(sprites_overlap_precise(&pos, &block, &pos, &block));
+        assert!(!sprites_overlap(&pos, &Sprite::new(""), &pos, &block));
+    }
+
+    #[test]
+    fn precise_overlap_ignores_transparent_cells() {
+        let ring = Sprite::new("###\n# #\n###");
+        let dot = Sprite::new("@");
+        let origin = Position::with_xy(0, 0);
+        let center = Position::with_xy(1, 1);
+        assert!(sprites_overlap(&origin, &ring, &center, &dot));
+        assert!(!sprites_overlap_precise(&origin, &ring, &center, &dot));
+        assert!(sprites_overlap_precise(
+            &origin,
+            &ring,
+            &Position::with_xy(1, 0),
+            &dot
+        ));
+
+        // Short lines are padded with transparent cells too
+        let ragged = Sprite::new("#\n###");
+        assert!(!sprites_overlap_precise(
+            &origin,
+            &ragged,
+            &Position::with_xy(2, 0),
+            &dot
+        ));
+    }
+
+    #[test]
+    fn higher_layers_draw_above_lower_ones_whatever_their_sub_z() {
+        let layers = [
+            Layer::Background,
+            Layer::World,
+            Layer::Ui,
+            Layer::Overlay,
+            Layer::Tooltip,
+        ];
+        for pair in layers.windows(2) {
+            let top_of_lower = Position::in_layer(pair[0], i32::MAX).z;
+            let bottom_of_upper = Position::in_layer(pair[1], i32::MIN).z;
+            assert!(top_of_lower < bottom_of_upper, "{pair:?}");
+        }
+
+        let (mut app, _) = testing::app(10, 2);
+        let overlay = testing::spawn_sprite(&mut app, "overlay", 0, 0);
+        let world = testing::spawn_sprite(&mut app, "world", 0, 0);
+        app.world.get_mut::<Position>(overlay).unwrap().z = Layer::Overlay.z(-100_000);
+        app.world.get_mut::<Position>(world).unwrap().z = Layer::World.z(100_000);
+        app.update();
+        assert_eq!(testing::screen(&app)[0], "overlay");
+    }
+
+    #[test]
+    fn shearing_a_block_makes_a_staircase() {
+        let block = Sprite::new("###\n###\n###");
+        let rows = |sprite: Sprite| {
+            sprite
+                .data()
+                .lines()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(rows(block.shear_horizontal(2)), ["###", "  ###", "    ###"]);
+        assert_eq!(block.shear_horizontal(2).width(), 7);
+        assert_eq!(rows(block.shear_horizontal(-1)), ["  ###", " ###", "###"]);
+        assert_eq!(block.shear_horizontal(0), block);
+
+        let block = Sprite::new("ab\ncd");
+        assert_eq!(rows(block.shear_vertical(1)), ["a ", "cb", " d"]);
+        assert_eq!(rows(block.shear_vertical(-1)), [" b", "ad", "c "]);
+    }
+
+    #[test]
+    fn intensities_pick_characters_from_the_ramp() {
+        let ramp = " .:-=+*#%@";
+        let gradient: Vec<f32> = (0..10).map(|idx| idx as f32 / 9.0).collect();
+        let grid = vec![gradient, vec![-1.0, 0.5, 2.0, f32::NAN]];
+
+        let sprite = Sprite::from_intensity(&grid, ramp);
+        assert_eq!(sprite.data(), " .:-=+*#%@\n +@ ");
+        // Halfway rounds up to the brighter character
+        assert_eq!(
+            Sprite::from_intensity(&grid, "_#").data(),
+            "_____#####\n_##_"
+        );
+        assert_eq!(
+            Sprite::from_intensity(&grid, "").data(),
+            format!("{}\n    ", " ".repeat(10))
+        );
+    }
+
+    #[test]
+    fn mid_gray_dithers_to_a_checkerboard() {
+        let gray = vec![vec![0.5; 4]; 4];
+        let sprite = Sprite::dither(&gray, " #", BayerMatrix::Two);
+        assert_eq!(sprite.data(), "# # \n # #\n# # \n # #");
+
+        // A bigger matrix still fills half the cells, and black and white stay flat
+        let sprite = Sprite::dither(&gray, " #", BayerMatrix::Four);
+        assert_eq!(sprite.data().matches('#').count(), 8);
+        let flat = vec![vec![0.0, 1.0]; 2];
+        assert_eq!(
+            Sprite::dither(&flat, " #", BayerMatrix::Two).data(),
+            " #\n #"
+        );
+    }
+
+    #[test]
+    fn shapes_are_corrected_for_tall_cells() {
+        let ratio = Sprite::CELL_ASPECT_RATIO;
+        let circle = Sprite::circle(2.0, '#', ratio);
+        assert_eq!((circle.width(), circle.height()), (9, 5));
+        assert!(circle.width() > circle.height());
+        assert_eq!(circle.data().lines().next(), Some(" ####### "));
+        // Without the correction the circle is as many columns as rows, which looks tall
+        let uncorrected = Sprite::circle(2.0, '#', 1.0);
+        assert_eq!((uncorrected.width(), uncorrected.height()), (5, 5));
+
+        assert_eq!(Sprite::rectangle(2.0, 2.0, '#', ratio).data(), "####\n####");
+        // A line of equal width and height covers twice as many columns, one cell in each
+        let line = Sprite::line((0.0, 0.0), (2.0, 2.0), '#', ratio);
+        assert_eq!((line.width(), line.height()), (5, 3));
+        assert_eq!(line.data().matches('#').count(), 5);
+    }
+
+    #[test]
+    fn hollow_rectangles_keep_only_their_border() {
+        let solid = Sprite::rectangle(2.5, 4.0, '#', Sprite::CELL_ASPECT_RATIO);
+        let hollow = solid.hollow();
+        assert_eq!(hollow.data(), "#####\n#   #\n#   #\n#####");
+        assert_eq!((hollow.width(), hollow.height()), (5, 4));
+        assert!(!hollow.is_filled(2, 1));
+
+        // Only the cells above, below and to either side count, so the middle of a plus is inside it
+        let plus = Sprite::new(" # \n###\n # ");
+        assert_eq!(plus.hollow().data(), " # \n# #\n # ");
+    }
+
+    #[test]
+    fn flood_fills_stop_at_the_edge_of_the_region() {
+        // The inside of the box only touches the outside diagonally, through its corners
+        let mut sprite = Sprite::new("......\n.####.\n.#..#.\n.#..##\n.####\n...");
+        sprite.flood_fill(2, 2, '~');
+        assert_eq!(sprite.data(), "......\n.####.\n.#~~#.\n.#~~##\n.####\n...");
+
+        // Outside the box, the short lines are filled as far as they go
+        sprite.flood_fill(0, 0, ' ');
+        assert_eq!(sprite.data(), "      \n #### \n #~~# \n #~~##\n ####\n   ");
+
+        let unchanged = sprite.data().to_string();
+        sprite.flood_fill(5, 4, '?');
+        sprite.flood_fill(9, 0, '?');
+        sprite.flood_fill(2, 2, '~');
+        assert_eq!(sprite.data(), unchanged);
+    }
+
+    #[test]
+    fn identical_content_is_equal_and_hashes_the_same() {
+        fn hash(value: &impl std::hash::Hash) -> u64 {
+            use std::hash::Hasher;
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            value.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        let sprite = Sprite::new("ab\ncd");
+        for same in [Sprite::new(String::from("ab\ncd")), Sprite::new("a\0b\ncd")] {
+            assert_eq!(sprite, same);
+            assert_eq!(hash(&sprite), hash(&same));
+        }
+        let replaced = Sprite::with_control_chars("ab\ncd", ControlChars::Replace('?'));
+        for different in [Sprite::new("ab\ndc"), Sprite::new("ab\ncd\n"), replaced] {
+            assert_ne!(sprite, different);
+            assert_ne!(hash(&sprite), hash(&different));
+        }
+
+        let stylemap = StyleMap::solid(Color::Red, Color::Black);
+        let same = StyleMap::with_colors(Colors::new(Color::Red, Color::Black));
+        assert!(stylemap == same);
+        assert_eq!(hash(&stylemap), hash(&same));
+        let bold = Style::new(
+            Colors::new(Color::Red, Color::Black),
+            crossterm::style::Attribute::Bold.into(),
+        );
+        let different = StyleMap::new(stylemap.style, vec![vec![bold]]);
+        assert!(stylemap != different);
+        assert_ne!(hash(&stylemap), hash(&different));
+    }
+
+    #[test]
+    fn empty_sprites_have_nothing_to_draw() {
+        for text in ["", "\n", "\n\n\n"] {
+            let sprite = Sprite::new(text);
+            assert!(sprite.is_empty(), "{text:?}");
+            assert_eq!(
+                (sprite.width(), sprite.x_center(), sprite.y_center()),
+                (0, 0, 0)
+            );
+            assert!(
+                sprite.bounds(&Position::with_xy(3, 3)).is_empty(),
+                "{text:?}"
+            );
+            let metrics = sprite.metrics();
+            assert_eq!((metrics.width, metrics.cell_count), (0, 0), "{text:?}");
+            assert!(metrics.uniform_width, "{text:?}");
+        }
+
+        let (mut app, _) = testing::app(6, 3);
+        for text in ["", "\n\n"] {
+            testing::spawn_sprite(&mut app, text, 1, 1);
+        }
+        testing::spawn_sprite(&mut app, "ok", 0, 0);
+        app.update();
+        assert_eq!(testing::screen(&app), ["ok", "", ""]);
+        assert_eq!(app.world.resource::<crate::RenderStats>().drawn, 1);
+        // Something empty is never on top of anything else either
+        let empty = Sprite::new("");
+        let ok = Sprite::new("ok");
+        assert!(!sprites_overlap(
+            &Position::default(),
+            &empty,
+            &Position::default(),
+            &ok
+        ));
+    }
+
+    #[test]
+    fn hexdumps_line_up_offsets_bytes_and_text() {
+        let bytes = b"Hi\x00\xffA";
+        let dump = Sprite::hexdump(bytes, 4);
+        assert_eq!(
+            dump.data(),
+            "00000000  48 69 00 ff  |Hi..|\n00000004  41           |A   |"
+        );
+
+        // The zero and 0xff are dimmed as hex, and as their dots in the text, but nothing else is
+        let stylemap = StyleMap::hexdump(bytes, 4);
+        let dim = Style::with_attrib(crossterm::style::Attribute::Dim);
+        let dimmed: Vec<usize> = (0..dump.line_width(0))
+            .filter(|&x| stylemap.style_for(x, 0) == dim)
+            .collect();
+        assert_eq!(dimmed, [16, 17, 19, 20, 26, 27]);
+        assert!((0..dump.line_width(1)).all(|x| stylemap.style_for(x, 1) != dim));
+    }
+
+    #[test]
+    fn stylemaps_from_fn_style_each_cell() {
+        let red = Style::with_bg(Color::Red);
+        let blue = Style::with_bg(Color::Blue);
+        let checkered = StyleMap::from_fn(4, 4, |x, y| if (x + y) % 2 == 0 { red } else { blue });
+
+        assert_eq!(checkered.map.len(), 4);
+        for (y, row) in checkered.map.iter().enumerate() {
+            let expected = if y % 2 == 0 {
+                [red, blue, red, blue]
+            } else {
+                [blue, red, blue, red]
+            };
+            assert_eq!(row[..], expected, "row {y}");
+        }
+        assert_eq!(checkered.style_for(3, 3), red);
+        assert_eq!(StyleMap::from_fn(0, 3, |_, _| red).map, vec![Vec::new(); 3]);
+    }
+
+    #[test]
+    fn classified_stylemaps_color_cells_by_their_character() {
+        let digit = Style::with_fg(Color::Cyan);
+        let letter = Style::with_fg(Color::Green);
+        let other = Style::with_fg(Color::DarkGrey);
+        let classify = |c: char| match c {
+            '0'..='9' => digit,
+            c if c.is_alphabetic() => letter,
+            _ => other,
+        };
+
+        let sprite = Sprite::new("x = 42;\n漢1");
+        let stylemap = StyleMap::from_sprite_classified(&sprite, classify);
+        let (d, l, o) = (digit, letter, other);
+        assert_eq!(stylemap.map[0], [l, o, o, o, d, d, o]);
+        // Both halves of the wide grapheme get its style
+        assert_eq!(stylemap.map[1], [l, l, d]);
+    }
+
+    #[test]
+    fn stylemaps_accept_color_names() {
+        let names = [
+            ("reset", Color::Reset),
+            ("black", Color::Black),
+            ("dark_grey", Color::DarkGrey),
+            ("dark_gray", Color::DarkGrey),
+            ("bright_black", Color::DarkGrey),
+            ("red", Color::Red),
+            ("bright_red", Color::Red),
+            ("dark_red", Color::DarkRed),
+            ("green", Color::Green),
+            ("bright_green", Color::Green),
+            ("dark_green", Color::DarkGreen),
+            ("yellow", Color::Yellow),
+            ("bright_yellow", Color::Yellow),
+            ("dark_yellow", Color::DarkYellow),
+            ("blue", Color::Blue),
+            ("bright_blue", Color::Blue),
+            ("dark_blue", Color::DarkBlue),
+            ("magenta", Color::Magenta),
+            ("bright_magenta", Color::Magenta),
+            ("dark_magenta", Color::DarkMagenta),
+            ("cyan", Color::Cyan),
+            ("bright_cyan", Color::Cyan),
+            ("dark_cyan", Color::DarkCyan),
+            ("white", Color::White),
+            ("bright_white", Color::White),
+            ("grey", Color::Grey),
+            ("gray", Color::Grey),
+            ("Dark-Gray", Color::DarkGrey),
+            ("BRIGHT BLUE", Color::Blue),
+            ("ansi_(160)", Color::AnsiValue(160)),
+            (
+                "rgb_(255, 128, 0)",
+                Color::Rgb {
+                    r: 255,
+                    g: 128,
+                    b: 0,
+                },
+            ),
+            (
+                "#ff8000",
+                Color::Rgb {
+                    r: 255,
+                    g: 128,
+                    b: 0,
+                },
+            ),
+            (
+                "#f80",
+                Color::Rgb {
+                    r: 255,
+                    g: 136,
+                    b: 0,
+                },
+            ),
+        ];
+        for (name, color) in names {
+            let colors: Colors = ron::from_str(&format!(
+                r#"(foreground: Some("{name}"), background: None)"#
+            ))
+            .unwrap_or_else(|error| panic!("{name}: {error}"));
+            assert_eq!(colors.foreground, Some(color), "{name}");
+        }
+    }
+
+    #[test]
+    fn misspelled_colors_say_where_they_are() {
+        let Err(error) = ron::from_str::<StyleMap>(
+            "(\n    default_style: (\n        attributes: 0,\n        colors: (foreground: Some(\"yelow\")),\n    ),\n    map: [],\n)",
+        ) else {
+            panic!("`yelow` isn't a color");
+        };
+        assert_eq!(error.position.line, 4);
+        let message = error.code.to_string();
+        assert!(message.contains("did you mean `yellow`?"), "{message}");
+    }
 }
@@ -0,0 +1,643 @@
+use bevy::ecs::entity::Entities;
+use bevy::prelude::*;
+use bevy_asset::{Assets, Handle};
+use crossterm::event::{KeyEvent, MouseEvent};
+
+use crate::components::{self, Position, Sprite};
+use crate::{
+    CoordinateSpace, CrosstermKeyEventWrapper, CrosstermMouseEventWrapper, CrosstermWindow,
+};
+
+/// A stack of entities which want first pick of the input, such as a modal dialog. Only the entity
+/// on top of the stack is considered focused.
+#[derive(Default, Debug, Resource)]
+pub struct InputFocus {
+    stack: Vec<Entity>,
+}
+
+impl InputFocus {
+    /// Puts an entity on top of the stack, giving it focus. If the entity was already on the stack,
+    /// it's moved to the top.
+    pub fn push(&mut self, entity: Entity) {
+        self.remove(entity);
+        self.stack.push(entity);
+    }
+
+    /// Removes an entity from the stack, wherever it is
+    pub fn remove(&mut self, entity: Entity) {
+        self.stack.retain(|e| *e != entity);
+    }
+
+    /// The entity which currently has focus
+    pub fn top(&self) -> Option<Entity> {
+        self.stack.last().copied()
+    }
+
+    pub fn is_focused(&self, entity: Entity) -> bool {
+        self.top() == Some(entity)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    /// Consumes all remaining key events, but only if `entity` has focus
+    pub fn consume_keys(&self, entity: Entity, input: &mut ConsumableInput) -> Vec<KeyEvent> {
+        if self.is_focused(entity) {
+            input.consume_keys()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Consumes all remaining mouse events, but only if `entity` has focus
+    pub fn consume_mouse_events(
+        &self,
+        entity: Entity,
+        input: &mut ConsumableInput,
+    ) -> Vec<MouseEvent> {
+        if self.is_focused(entity) {
+            input.consume_mouse_events()
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// This frame's key and mouse events, which can be consumed so that systems that run afterwards
+/// don't see them. This is filled in during `PreUpdate`, so a system which consumes input has to be
+/// ordered before any systems that shouldn't observe the consumed events.
+///
+/// The regular `CrosstermKeyEventWrapper` and `CrosstermMouseEventWrapper` events are still sent
+/// for every event, consumed or not.
+#[derive(Default, Debug, Resource)]
+pub struct ConsumableInput {
+    keys: Vec<(KeyEvent, bool)>,
+    mouse: Vec<(MouseEvent, bool)>,
+}
+
+impl ConsumableInput {
+    /// Key events which haven't been consumed yet
+    pub fn keys(&self) -> impl Iterator<Item = &KeyEvent> {
+        self.keys
+            .iter()
+            .filter(|(_, consumed)| !consumed)
+            .map(|(event, _)| event)
+    }
+
+    /// Mouse events which haven't been consumed yet
+    pub fn mouse_events(&self) -> impl Iterator<Item = &MouseEvent> {
+        self.mouse
+            .iter()
+            .filter(|(_, consumed)| !consumed)
+            .map(|(event, _)| event)
+    }
+
+    /// Consumes and returns every key event which hasn't been consumed yet
+    pub fn consume_keys(&mut self) -> Vec<KeyEvent> {
+        self.consume_keys_matching(|_| true)
+    }
+
+    /// Consumes and returns every remaining key event the predicate returns true for
+    pub fn consume_keys_matching(
+        &mut self,
+        mut predicate: impl FnMut(&KeyEvent) -> bool,
+    ) -> Vec<KeyEvent> {
+        consume_matching(&mut self.keys, &mut predicate)
+    }
+
+    /// Consumes and returns every mouse event which hasn't been consumed yet
+    pub fn consume_mouse_events(&mut self) -> Vec<MouseEvent> {
+        self.consume_mouse_events_matching(|_| true)
+    }
+
+    /// Consumes and returns every remaining mouse event the predicate returns true for
+    pub fn consume_mouse_events_matching(
+        &mut self,
+        mut predicate: impl FnMut(&MouseEvent) -> bool,
+    ) -> Vec<MouseEvent> {
+        consume_matching(&mut self.mouse, &mut predicate)
+    }
+}
+
+fn consume_matching<T: Clone>(
+    events: &mut [(T, bool)],
+    predicate: &mut impl FnMut(&T) -> bool,
+) -> Vec<T> {
+    let mut consumed = Vec::new();
+    for (event, is_consumed) in events.iter_mut() {
+        if !*is_consumed && predicate(event) {
+            *is_consumed = true;
+            consumed.push(event.clone());
+        }
+    }
+    consumed
+}
+
+/// Collects this frame's input so it can be consumed
+pub(crate) fn collect_consumable_input(
+    mut input: ResMut<ConsumableInput>,
+    mut focus: ResMut<InputFocus>,
+    entities: &Entities,
+    mut keys: EventReader<CrosstermKeyEventWrapper>,
+    mut mouse: EventReader<CrosstermMouseEventWrapper>,
+) {
+    // Despawned entities can't consume anything, so don't let them hold on to focus
+    if focus.stack.iter().any(|entity| !entities.contains(*entity)) {
+        focus.stack.retain(|entity| entities.contains(*entity));
+    }
+
+    input.keys.clear();
+    input.mouse.clear();
+    input.keys.extend(keys.read().map(|event| (event.0, false)));
+    input
+        .mouse
+        .extend(mouse.read().map(|event| (event.0, false)));
+}
+
+/// Sent when a mouse button is pressed over a `Clickable` sprite
+#[derive(Event, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct SpriteClicked {
+    pub entity: Entity,
+    pub button: crossterm::event::MouseButton,
+    /// Where the click was, in cells from the top left corner of the sprite
+    pub local_pos: UVec2,
+}
+
+/// Sent when the mouse moves onto a `Clickable` sprite, or the sprite it's over becomes the one on
+/// top
+#[derive(Event, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct SpriteHoverEnter {
+    pub entity: Entity,
+    /// Where the mouse is, in cells from the top left corner of the sprite
+    pub local_pos: UVec2,
+}
+
+/// Sent when the mouse is no longer over the `Clickable` sprite it entered, either because it moved
+/// or because another sprite is on top there now. This is sent before the `SpriteHoverEnter` for the
+/// next sprite.
+#[derive(Event, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct SpriteHoverExit {
+    pub entity: Entity,
+}
+
+type Clickables<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Position,
+        &'static Handle<Sprite>,
+        &'static components::Visible,
+        Option<&'static components::RenderPriority>,
+        Has<components::Static>,
+    ),
+    With<components::Clickable>,
+>;
+
+/// The clickable sprite which is drawn on top at a cell of the screen, and where in the sprite the
+/// cell is
+fn clickable_at(
+    column: i32,
+    row: i32,
+    clickables: &Clickables,
+    sprites: &Assets<Sprite>,
+    space: CoordinateSpace,
+    window: &CrosstermWindow,
+    hierarchy: &crate::systems::Hierarchy,
+) -> Option<(Entity, UVec2)> {
+    // Whichever sprite is drawn last at this cell is the one on top
+    let hit = clickables
+        .iter()
+        .filter(|(_, _, _, visible, ..)| visible.is_visible)
+        .filter_map(|(entity, pos, sprite, visible, priority, is_static)| {
+            let sprite = sprites.get(sprite)?;
+            let mut pos = crate::systems::global_position(entity, pos, hierarchy);
+            (pos.x, pos.y) = space.to_screen(pos.x, pos.y, window);
+            // The bounds are exclusive of their max corner
+            let bounds = sprite.bounds(&pos);
+            let inside = (bounds.min.x..bounds.max.x).contains(&column)
+                && (bounds.min.y..bounds.max.y).contains(&row);
+            if !inside {
+                return None;
+            }
+
+            let (x, y) = ((column - pos.x) as usize, (row - pos.y) as usize);
+            if visible.is_transparent && !sprite.is_filled(x, y) {
+                return None;
+            }
+
+            let order = (
+                !is_static,
+                pos.z,
+                priority.copied().unwrap_or_default(),
+                entity,
+            );
+            Some((order, UVec2::new(x as u32, y as u32)))
+        })
+        .max_by_key(|(order, _)| *order);
+
+    hit.map(|((.., entity), local_pos)| (entity, local_pos))
+}
+
+/// Finds the clickable sprite on top at each mouse press, if there is one
+pub(crate) fn detect_sprite_clicks(
+    mut mouse: EventReader<CrosstermMouseEventWrapper>,
+    mut clicked: EventWriter<SpriteClicked>,
+    window: Query<&CrosstermWindow>,
+    sprites: Res<Assets<Sprite>>,
+    space: Option<Res<CoordinateSpace>>,
+    clickables: Clickables,
+    hierarchy: crate::systems::Hierarchy,
+) {
+    let presses: Vec<_> = mouse
+        .read()
+        .filter_map(|event| match event.0.kind {
+            crossterm::event::MouseEventKind::Down(button) => {
+                Some((button, event.0.column as i32, event.0.row as i32))
+            }
+            _ => None,
+        })
+        .collect();
+    if presses.is_empty() {
+        return;
+    }
+
+    let window = window.single();
+    let space = space.as_deref().copied().unwrap_or_default();
+    for (button, column, row) in presses {
+        let hit = clickable_at(
+            column,
+            row,
+            &clickables,
+            &sprites,
+            space,
+            window,
+            &hierarchy,
+        );
+        if let Some((entity, local_pos)) = hit {
+            clicked.send(SpriteClicked {
+                entity,
+                button,
+                local_pos,
+            });
+        }
+    }
+}
+
+/// Keeps track of which clickable sprite the mouse is over. Only where the mouse ends up each update
+/// counts, so sprites it skips over on the way don't get any events. Sprites moving under the mouse
+/// count too, even if the mouse doesn't move.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn detect_sprite_hovers(
+    mut mouse: EventReader<CrosstermMouseEventWrapper>,
+    mut entered: EventWriter<SpriteHoverEnter>,
+    mut exited: EventWriter<SpriteHoverExit>,
+    window: Query<&CrosstermWindow>,
+    sprites: Res<Assets<Sprite>>,
+    space: Option<Res<CoordinateSpace>>,
+    clickables: Clickables,
+    hierarchy: crate::systems::Hierarchy,
+    mut mouse_pos: Local<Option<(i32, i32)>>,
+    mut hovered: Local<Option<Entity>>,
+) {
+    // Every kind of mouse event says where the mouse is
+    if let Some(event) = mouse.read().last() {
+        *mouse_pos = Some((event.0.column as i32, event.0.row as i32));
+    }
+    let Some((column, row)) = *mouse_pos else {
+        return;
+    };
+
+    let window = window.single();
+    let space = space.as_deref().copied().unwrap_or_default();
+    let hit = clickable_at(
+        column,
+        row,
+        &clickables,
+        &sprites,
+        space,
+        window,
+        &hierarchy,
+    );
+    if hit.map(|(entity, _)| entity) == *hovered {
+        return;
+    }
+
+    if let Some(entity) = *hovered {
+        exited.send(SpriteHoverExit { entity });
+    }
+    if let Some((entity, local_pos)) = hit {
+        entered.send(SpriteHoverEnter { entity, local_pos });
+    }
+    *hovered = hit.map(|(entity, _)| entity);
+}
+
+/// Somewhere input events come from. The runner polls it before every update, and sends the events
+/// it returns as `CrosstermKeyEventWrapper`s and so on, as if they came from the terminal.
+pub trait InputSource: Send + Sync + 'static {
+    /// Returns the next event that's ready, if there is one. This must not block. `elapsed` is how
+    /// long the app has been running.
+    fn poll(&mut self, elapsed: std::time::Duration) -> Option<crossterm::event::Event>;
+}
+
+/// Reads input from the terminal. This is the default `InputSource`
+#[derive(Default, Debug)]
+pub struct TerminalInput;
+
+impl InputSource for TerminalInput {
+    fn poll(&mut self, _elapsed: std::time::Duration) -> Option<crossterm::event::Event> {
+        match crossterm::event::poll(std::time::Duration::from_secs(0)) {
+            Ok(true) => crossterm::event::read().ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Replays a recorded list of events, for tests and demos. Each event is sent once the app has been
+/// running for at least its `Duration`, so events must be listed in order. The terminal's own input
+/// is ignored.
+#[derive(Default, Debug)]
+pub struct ScriptedInput {
+    events: std::collections::VecDeque<(std::time::Duration, crossterm::event::Event)>,
+}
+
+impl ScriptedInput {
+    pub fn new(events: Vec<(std::time::Duration, crossterm::event::Event)>) -> ScriptedInput {
+        ScriptedInput {
+            events: events.into(),
+        }
+    }
+
+    /// Whether every event has been sent
+    pub fn is_finished(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+impl InputSource for ScriptedInput {
+    fn poll(&mut self, elapsed: std::time::Duration) -> Option<crossterm::event::Event> {
+        match self.events.front() {
+            Some((at, _)) if *at <= elapsed => self.events.pop_front().map(|(_, event)| event),
+            _ => None,
+        }
+    }
+}
+
+/// What to do with key and mouse events that arrive once the queue for an update is full. However
+/// many are dropped, Ctrl+C still exits the app. Window events like resizes and focus changes don't
+/// count towards the limit and are never dropped, since missing one would leave the window wrong.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum InputOverflow {
+    /// Throw away the oldest events to make room, so the most recent input is kept
+    #[default]
+    DropOldest,
+    /// Throw away the new events, so the input that came first is kept
+    DropNewest,
+    /// Merge a new event into the last one if they're the same kind of event, like repeats of the
+    /// same key or mouse moves, so only the latest is kept. Other events throw away the oldest event
+    /// instead.
+    Coalesce,
+}
+
+impl InputOverflow {
+    /// Adds a key or mouse event to a queue which can hold `capacity` of them, returning whether one
+    /// was dropped
+    pub(crate) fn push(
+        self,
+        queue: &mut std::collections::VecDeque<crossterm::event::Event>,
+        event: crossterm::event::Event,
+        capacity: usize,
+    ) -> bool {
+        debug_assert!(is_limited(&event));
+        if queue.len() < capacity {
+            queue.push_back(event);
+            return false;
+        }
+
+        match self {
+            InputOverflow::DropNewest => {}
+            InputOverflow::Coalesce if queue.back().is_some_and(|last| coalesces(last, &event)) => {
+                *queue.back_mut().unwrap() = event;
+            }
+            InputOverflow::DropOldest | InputOverflow::Coalesce => {
+                queue.pop_front();
+                queue.push_back(event);
+            }
+        }
+        true
+    }
+}
+
+/// Whether an event counts towards the input capacity
+pub(crate) fn is_limited(event: &crossterm::event::Event) -> bool {
+    matches!(
+        event,
+        crossterm::event::Event::Key(_) | crossterm::event::Event::Mouse(_)
+    )
+}
+
+/// Whether `next` can replace `last` without losing anything but how many times it happened
+fn coalesces(last: &crossterm::event::Event, next: &crossterm::event::Event) -> bool {
+    use crossterm::event::{Event, MouseEventKind};
+    match (last, next) {
+        (Event::Key(last), Event::Key(next)) => {
+            last.code == next.code && last.modifiers == next.modifiers && last.kind == next.kind
+        }
+        (Event::Mouse(last), Event::Mouse(next)) => match (last.kind, next.kind) {
+            (MouseEventKind::Moved, MouseEventKind::Moved) => true,
+            (MouseEventKind::Drag(last), MouseEventKind::Drag(next)) => last == next,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Picks where input comes from. Insert it before the `CrosstermPlugin` is added to replace the
+/// terminal with another `InputSource`.
+#[derive(Resource)]
+pub struct CrosstermInputSource(pub Box<dyn InputSource>);
+
+impl CrosstermInputSource {
+    pub fn new<T: InputSource>(source: T) -> CrosstermInputSource {
+        CrosstermInputSource(Box::new(source))
+    }
+}
+
+impl Default for CrosstermInputSource {
+    fn default() -> Self {
+        CrosstermInputSource::new(TerminalInput)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use bevy::ecs::event::ManualEventReader;
+    use crossterm::event::{KeyCode, KeyModifiers, MouseButton, MouseEventKind};
+
+    #[derive(Resource, Default)]
+    struct Seen(Vec<KeyCode>);
+
+    #[derive(Component)]
+    struct Modal;
+
+    fn modal(
+        modal: Query<Entity, With<Modal>>,
+        focus: Res<InputFocus>,
+        mut input: ResMut<ConsumableInput>,
+    ) {
+        for entity in &modal {
+            focus.consume_keys(entity, &mut input);
+        }
+    }
+
+    fn game(input: Res<ConsumableInput>, mut seen: ResMut<Seen>) {
+        seen.0.extend(input.keys().map(|key| key.code));
+    }
+
+    fn press(app: &mut App, c: char) {
+        let key = KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+        app.world.send_event(CrosstermKeyEventWrapper(key));
+        app.update();
+    }
+
+    #[test]
+    fn focused_consumer_hides_keys_from_lower_systems() {
+        let (mut app, _) = testing::app(10, 4);
+        app.init_resource::<Seen>()
+            .add_systems(Update, (modal, game).chain());
+        let dialog = app.world.spawn(Modal).id();
+        let other = app.world.spawn(Modal).id();
+
+        // Nothing has focus, so nothing is consumed
+        press(&mut app, 'a');
+
+        // Only the entity on top of the stack gets to consume
+        let mut focus = app.world.resource_mut::<InputFocus>();
+        focus.push(dialog);
+        focus.push(other);
+        focus.remove(other);
+        press(&mut app, 'b');
+
+        // Once the dialog is gone, the game sees keys again
+        app.world.despawn(dialog);
+        press(&mut app, 'c');
+
+        assert_eq!(
+            app.world.resource::<Seen>().0,
+            [KeyCode::Char('a'), KeyCode::Char('c')]
+        );
+        assert!(app.world.resource::<InputFocus>().is_empty());
+    }
+
+    fn click(app: &mut App, button: MouseButton, column: u16, row: u16) {
+        app.world.send_event(CrosstermMouseEventWrapper(MouseEvent {
+            kind: MouseEventKind::Down(button),
+            column,
+            row,
+            modifiers: KeyModifiers::NONE,
+        }));
+        app.update();
+    }
+
+    #[test]
+    fn clicks_go_to_the_sprite_on_top() {
+        let (mut app, _) = testing::app(10, 4);
+        let below = testing::spawn_sprite(&mut app, "######\n######", 1, 1);
+        let above = testing::spawn_sprite(&mut app, "##", 3, 1);
+        app.world.entity_mut(below).insert(components::Clickable);
+        app.world.entity_mut(above).insert(components::Clickable);
+        app.world.get_mut::<Position>(above).unwrap().z = 1;
+        let mut reader = ManualEventReader::<SpriteClicked>::default();
+
+        click(&mut app, MouseButton::Left, 4, 1);
+        click(&mut app, MouseButton::Right, 2, 2);
+        // Neither sprite is here
+        click(&mut app, MouseButton::Left, 0, 0);
+
+        let clicks: Vec<_> = reader
+            .read(app.world.resource::<Events<SpriteClicked>>())
+            .copied()
+            .collect();
+        assert_eq!(
+            clicks,
+            [
+                SpriteClicked {
+                    entity: above,
+                    button: MouseButton::Left,
+                    local_pos: UVec2::new(1, 0),
+                },
+                SpriteClicked {
+                    entity: below,
+                    button: MouseButton::Right,
+                    local_pos: UVec2::new(1, 1),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn hovers_follow_the_sprite_the_mouse_ends_up_over() {
+        let (mut app, _) = testing::app(12, 2);
+        let a = testing::spawn_sprite(&mut app, "aaa", 0, 0);
+        let b = testing::spawn_sprite(&mut app, "bbb", 5, 0);
+        app.world.entity_mut(a).insert(components::Clickable);
+        app.world.entity_mut(b).insert(components::Clickable);
+        let mut entered = ManualEventReader::<SpriteHoverEnter>::default();
+        let mut exited = ManualEventReader::<SpriteHoverExit>::default();
+
+        // Every move is sent before the update, and only the last one counts
+        let mut hover = |app: &mut App, moves: &[u16]| {
+            for &column in moves {
+                app.world.send_event(CrosstermMouseEventWrapper(MouseEvent {
+                    kind: MouseEventKind::Moved,
+                    column,
+                    row: 0,
+                    modifiers: KeyModifiers::NONE,
+                }));
+            }
+            app.update();
+            let events = app.world.resource::<Events<SpriteHoverEnter>>();
+            let entered: Vec<_> = entered
+                .read(events)
+                .map(|event| (event.entity, event.local_pos.x))
+                .collect();
+            let events = app.world.resource::<Events<SpriteHoverExit>>();
+            let exited: Vec<_> = exited.read(events).map(|event| event.entity).collect();
+            (exited, entered)
+        };
+
+        assert_eq!(hover(&mut app, &[1]), (vec![], vec![(a, 1)]));
+        // Moving within a sprite doesn't send anything
+        assert_eq!(hover(&mut app, &[2]), (vec![], vec![]));
+        assert_eq!(hover(&mut app, &[3, 4, 6]), (vec![a], vec![(b, 1)]));
+        assert_eq!(hover(&mut app, &[9]), (vec![b], vec![]));
+        // Passing over a sprite on the way somewhere else doesn't count
+        assert_eq!(hover(&mut app, &[6, 10]), (vec![], vec![]));
+    }
+
+    #[test]
+    fn pastes_bigger_than_the_queue_keep_it_full() {
+        use crossterm::event::Event;
+
+        let key = |idx: usize| {
+            let c = char::from_u32('a' as u32 + (idx % 26) as u32).unwrap();
+            Event::Key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE))
+        };
+        for (overflow, oldest_kept) in [
+            (InputOverflow::DropOldest, 100_000 - 1024),
+            (InputOverflow::DropNewest, 0),
+            (InputOverflow::Coalesce, 100_000 - 1024),
+        ] {
+            let mut queue = std::collections::VecDeque::new();
+            let dropped = (0..100_000)
+                .filter(|&idx| overflow.push(&mut queue, key(idx), 1024))
+                .count();
+            assert_eq!(queue.len(), 1024, "{overflow:?}");
+            assert_eq!(dropped, 100_000 - 1024, "{overflow:?}");
+            assert_eq!(queue[0], key(oldest_kept), "{overflow:?}");
+        }
+    }
+}
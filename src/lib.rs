@@ -4,19 +4,33 @@ use bevy::prelude::*;
 use bevy_app::App;
 
 mod asset_loaders;
+pub mod buffer;
 pub mod components;
+pub mod input;
 pub mod prelude;
 mod runner;
 mod systems;
+#[cfg(test)]
+mod testing;
+pub mod widgets;
+
+#[cfg(feature = "image")]
+pub use asset_loaders::{ImageLoaderSettings, ImageMode};
 
 pub struct CrosstermPlugin;
 
 impl Plugin for CrosstermPlugin {
     fn build(&self, app: &mut App) {
+        #[cfg(feature = "image")]
+        app.register_asset_loader(asset_loaders::ImageLoader);
+
         app.insert_resource(Cursor::default())
-            .insert_resource(components::PreviousEntityDetails::default())
-            .insert_resource(components::EntitiesToRedraw::default())
-            .insert_resource(components::PreviousWindowColors::default())
+            .init_resource::<CrosstermRng>()
+            .insert_resource(components::RenderState::default())
+            .init_resource::<CrosstermPaused>()
+            .init_resource::<buffer::CellBuffer>()
+            .init_resource::<RenderStats>()
+            .init_resource::<CrosstermWriter>()
             // Custom assets
             .register_asset_loader(asset_loaders::SpriteLoader)
             .init_asset::<components::Sprite>()
@@ -25,34 +39,386 @@ impl Plugin for CrosstermPlugin {
             // Crossterm events
             .add_event::<CrosstermKeyEventWrapper>()
             .add_event::<CrosstermMouseEventWrapper>()
+            .add_event::<CrosstermRenderError>()
+            .add_event::<FirstFrameRendered>()
+            .add_event::<input::SpriteClicked>()
+            .add_event::<input::SpriteHoverEnter>()
+            .add_event::<input::SpriteHoverExit>()
+            .add_event::<widgets::ConfirmResult>()
+            .init_resource::<input::InputFocus>()
+            .init_resource::<input::ConsumableInput>()
             .set_runner(runner::crossterm_runner)
-            // TODO check if asset events work correctly this way
-            // Old comment:
-            // This must be before LAST because change tracking is cleared during LAST, but AssetEvents are published
-            // after POST_UPDATE. The timing for all these things is pretty delicate
+            .add_event::<components::TransitionFinished>()
+            .add_event::<components::TypewriterDone>()
+            .add_event::<components::FadeFinished>()
             .add_systems(
-                PostUpdate,
+                Update,
                 (
-                    systems::add_previous_position,
-                    systems::calculate_entities_to_redraw,
-                    systems::crossterm_render,
-                    systems::update_previous_position,
+                    systems::apply_velocity,
+                    systems::update_transitions,
+                    systems::update_fades,
+                    systems::update_typewriters,
+                    systems::update_animated_sprites,
+                    systems::update_soft_blinks,
                 )
-                    .chain(),
+                    .run_if(systems::not_paused),
             );
+
+        // Settings have to be inserted before the plugin is added for these to take effect
+        let (render_schedule, input_schedule) = app
+            .world
+            .get_resource::<CrosstermWindowSettings>()
+            .map(|settings| (settings.render_schedule(), settings.input_schedule()))
+            .unwrap_or((CrosstermSchedule::PostUpdate, CrosstermSchedule::PreUpdate));
+
+        match input_schedule {
+            CrosstermSchedule::PreUpdate => add_input_systems(app, PreUpdate),
+            CrosstermSchedule::Update => add_input_systems(app, Update),
+            CrosstermSchedule::PostUpdate => add_input_systems(app, PostUpdate),
+            CrosstermSchedule::FixedUpdate => add_input_systems(app, FixedUpdate),
+        }
+        match render_schedule {
+            CrosstermSchedule::PreUpdate => add_render_systems(app, PreUpdate),
+            CrosstermSchedule::Update => add_render_systems(app, Update),
+            CrosstermSchedule::PostUpdate => add_render_systems(app, PostUpdate),
+            CrosstermSchedule::FixedUpdate => add_render_systems(app, FixedUpdate),
+        }
     }
 }
 
+fn add_input_systems(app: &mut App, schedule: impl bevy::ecs::schedule::ScheduleLabel) {
+    app.add_systems(
+        schedule,
+        (
+            input::collect_consumable_input,
+            input::detect_sprite_clicks,
+            input::detect_sprite_hovers,
+            widgets::handle_confirm_dialogs.after(input::collect_consumable_input),
+        ),
+    );
+}
+
+fn add_render_systems(app: &mut App, schedule: impl bevy::ecs::schedule::ScheduleLabel + Clone) {
+    // TODO check if asset events work correctly this way
+    // Old comment:
+    // This must be before LAST because change tracking is cleared during LAST, but AssetEvents are published
+    // after POST_UPDATE. The timing for all these things is pretty delicate
+    app.configure_sets(
+        schedule.clone(),
+        (
+            CrosstermSet::Prepare,
+            CrosstermSet::Compose,
+            CrosstermSet::PostProcess.run_if(systems::frame_composed),
+            CrosstermSet::Flush,
+        )
+            .chain(),
+    )
+    .add_systems(
+        schedule.clone(),
+        (
+            systems::sync_window_settings,
+            systems::update_streaming_sprites,
+            widgets::update_progress_bars,
+            widgets::update_selection.run_if(resource_exists::<widgets::Selection>()),
+            widgets::update_confirm_dialogs,
+            systems::clamp_to_window,
+            systems::apply_cursor_mode.run_if(resource_exists::<CursorMode>()),
+        )
+            .chain()
+            .in_set(CrosstermSet::Prepare),
+    )
+    .add_systems(
+        schedule.clone(),
+        (
+            systems::calculate_redraw,
+            systems::compose_frame,
+            systems::draw_debug_overlay.run_if(resource_exists::<DebugOverlay>()),
+        )
+            .chain()
+            .in_set(CrosstermSet::Compose),
+    )
+    .add_systems(
+        schedule,
+        systems::crossterm_render.in_set(CrosstermSet::Flush),
+    );
+}
+
+/// The stages of drawing a frame, which all run in `PostUpdate` unless a different
+/// `CrosstermWindowSettings::render_schedule` is set. Systems which draw extra things on
+/// top of the frame, like a debug overlay, can write into the `CellBuffer` resource by running after
+/// `Compose` and before `Flush`. Systems which change what was drawn, like tinting the whole frame,
+/// belong in `PostProcess`.
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CrosstermSet {
+    /// Entities are moved, clamped and so on, ready to be drawn
+    Prepare,
+    /// The frame is drawn into the `CellBuffer`
+    Compose,
+    /// Runs only when a new frame was just drawn into the `CellBuffer`, so systems here can change it
+    /// before it's written to the terminal without changing the same frame twice
+    PostProcess,
+    /// Changes in the `CellBuffer` are written to the terminal
+    Flush,
+}
+
 #[derive(Event)]
 pub struct CrosstermKeyEventWrapper(pub crossterm::event::KeyEvent);
 
 #[derive(Event)]
 pub struct CrosstermMouseEventWrapper(pub crossterm::event::MouseEvent);
 
+/// Sent once, right after the first frame has been written to the terminal. The first frame is
+/// drawn as soon as the app starts, whether or not any assets have loaded by then.
+#[derive(Event, Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct FirstFrameRendered;
+
+/// Where finished frames are written, the terminal's stdout by default. Insert one with another
+/// writer to send the frames somewhere else, like a buffer in a test or a recording. Setting up
+/// and restoring the terminal always goes to stdout.
+#[derive(Resource)]
+pub struct CrosstermWriter(pub Box<dyn std::io::Write + Send + Sync>);
+
+impl CrosstermWriter {
+    pub fn new<T: std::io::Write + Send + Sync + 'static>(writer: T) -> CrosstermWriter {
+        CrosstermWriter(Box::new(writer))
+    }
+}
+
+impl Default for CrosstermWriter {
+    fn default() -> Self {
+        CrosstermWriter::new(std::io::stdout())
+    }
+}
+
+/// Sent when something couldn't be drawn. Rendering carries on without it, so one broken asset
+/// doesn't take down the app.
+#[derive(Event, Debug)]
+pub enum CrosstermRenderError {
+    /// An entity's sprite failed to load, or was removed from `Assets<Sprite>`. The entity is skipped
+    MissingSprite { entity: Entity },
+    /// An entity's stylemap failed to load, or was removed from `Assets<StyleMap>`. The entity is skipped
+    MissingStyleMap { entity: Entity },
+    /// An entity's stylemap has more lines than its sprite, so it was probably made for a different
+    /// sprite. The entity is skipped. Sizes are `(width, height)`.
+    ///
+    /// Right after one of them is hot reloaded, the entity is quietly held back for a moment instead,
+    /// to give the other one a chance to be reloaded to match.
+    StyleMapMismatch {
+        entity: Entity,
+        sprite_size: (usize, usize),
+        stylemap_size: (usize, usize),
+    },
+    /// Writing to the terminal failed. The whole screen is redrawn next frame
+    Io(std::io::Error),
+    /// Writing the screen to the file set with `CrosstermWindowSettings::mirror_text_to` failed
+    MirrorText(std::io::Error),
+}
+
+impl std::fmt::Display for CrosstermRenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CrosstermRenderError::MissingSprite { entity } => {
+                write!(f, "the sprite for {entity:?} is missing")
+            }
+            CrosstermRenderError::MissingStyleMap { entity } => {
+                write!(f, "the stylemap for {entity:?} is missing")
+            }
+            CrosstermRenderError::StyleMapMismatch {
+                entity,
+                sprite_size,
+                stylemap_size,
+            } => write!(
+                f,
+                "the stylemap for {entity:?} is {}x{} but its sprite is {}x{}",
+                stylemap_size.0, stylemap_size.1, sprite_size.0, sprite_size.1
+            ),
+            CrosstermRenderError::Io(error) => {
+                write!(f, "could not write to the terminal: {error}")
+            }
+            CrosstermRenderError::MirrorText(error) => {
+                write!(f, "could not write the screen to the mirror file: {error}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CrosstermRenderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CrosstermRenderError::Io(error) | CrosstermRenderError::MirrorText(error) => {
+                Some(error)
+            }
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone, Eq, PartialEq, Resource)]
 pub struct CrosstermWindowSettings {
     colors: components::Colors,
     title: Option<String>,
+    bell_suppressed: bool,
+    write_chunk_size: Option<std::num::NonZeroUsize>,
+    synchronized_updates: SynchronizedUpdates,
+    hyperlinks: HyperlinkSupport,
+    render_schedule: CrosstermSchedule,
+    input_schedule: CrosstermSchedule,
+    overflow: components::Overflow,
+    mirror_text_path: Option<std::path::PathBuf>,
+    startup_hooks: Vec<TerminalHook>,
+    shutdown_hooks: Vec<TerminalHook>,
+    diff_strategy: DiffStrategy,
+    input_capacity: std::num::NonZeroUsize,
+    input_overflow: input::InputOverflow,
+    safe_area: SafeArea,
+    fixed_canvas: Option<FixedCanvas>,
+    frame_pacing: FramePacing,
+    handle_sigint: bool,
+    exit_screen: ExitScreen,
+}
+
+/// How many rows or columns at each edge of the terminal are left empty, for terminals where the
+/// edges are covered up or unreliable, like under a tmux status bar
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SafeArea {
+    pub top: u16,
+    pub bottom: u16,
+    pub left: u16,
+    pub right: u16,
+}
+
+/// A fixed size area in the middle of the terminal which everything is drawn in, for games which
+/// are laid out for one size of screen. See `CrosstermWindowSettings::set_fixed_canvas`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FixedCanvas {
+    pub width: u16,
+    pub height: u16,
+    /// The background color of the rest of the terminal
+    pub letterbox: components::Color,
+}
+
+impl FixedCanvas {
+    pub fn new(width: u16, height: u16, letterbox: components::Color) -> FixedCanvas {
+        FixedCanvas {
+            width,
+            height,
+            letterbox,
+        }
+    }
+}
+
+/// How much of the screen is written to the terminal when a frame changes
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum DiffStrategy {
+    /// Only the cells which changed. This writes the least text, but needs a cursor move before
+    /// each group of changed cells.
+    #[default]
+    Cell,
+    /// Every row with a changed cell is written in full, which needs fewer cursor moves
+    Row,
+    /// The whole screen is written every time something changes
+    Full,
+}
+
+/// What's left on the terminal once the app exits. bevy_crossterm draws on the terminal's alternate
+/// screen, so by default leaving it brings back whatever was there before the app started.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum ExitScreen {
+    /// Go back to what was on the terminal before the app started
+    #[default]
+    RestoreAlternate,
+    /// Clear the terminal, so the prompt is at the top of an empty screen
+    ClearScreen,
+    /// Copy the last frame onto the terminal, below whatever was there before the app started, with
+    /// the cursor on the line after it. Empty rows at the bottom of the frame are left out.
+    LeaveFrame,
+}
+
+/// What the runner does when an update takes longer than the `ScheduleRunnerPlugin`'s wait between
+/// updates
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum FramePacing {
+    /// Carry on from the latest state with the next update. Time still passes as normal, but fewer
+    /// updates run.
+    #[default]
+    DropFrames,
+    /// Run the updates that were missed straight away, up to `max_updates` at once, so systems which
+    /// do something every update don't fall behind. Don't set `max_updates` too high, or a game which
+    /// is always too slow never gets to wait.
+    CatchUp { max_updates: u32 },
+}
+
+impl FramePacing {
+    /// How many updates to run after an update which took `took`, including the next one
+    pub(crate) fn updates(self, took: std::time::Duration, wait: std::time::Duration) -> u32 {
+        match self {
+            FramePacing::DropFrames => 1,
+            // Waiting for no time at all means no updates are ever late
+            FramePacing::CatchUp { .. } if wait.is_zero() => 1,
+            FramePacing::CatchUp { max_updates } => {
+                let missed = (took.as_nanos() / wait.as_nanos()).saturating_sub(1);
+                1 + std::cmp::min(missed, max_updates as u128) as u32
+            }
+        }
+    }
+}
+
+/// A function which writes its own commands to the terminal, added with
+/// `CrosstermWindowSettings::on_startup` or `CrosstermWindowSettings::on_shutdown`
+#[derive(Clone)]
+pub struct TerminalHook(std::sync::Arc<HookFn>);
+
+type HookFn = dyn Fn(&mut dyn std::io::Write) -> std::io::Result<()> + Send + Sync;
+
+impl TerminalHook {
+    pub fn run(&self, term: &mut dyn std::io::Write) -> std::io::Result<()> {
+        (self.0)(term)
+    }
+}
+
+impl PartialEq for TerminalHook {
+    fn eq(&self, other: &Self) -> bool {
+        std::sync::Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for TerminalHook {}
+
+impl std::fmt::Debug for TerminalHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TerminalHook")
+    }
+}
+
+/// A schedule that bevy_crossterm's systems can be run in
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CrosstermSchedule {
+    PreUpdate,
+    Update,
+    PostUpdate,
+    FixedUpdate,
+}
+
+/// Whether frames are drawn using the terminal's synchronized output mode (`?2026`), which shows the
+/// whole frame at once instead of as it's being written. Terminals which don't support it ignore it.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum SynchronizedUpdates {
+    /// Use synchronized output if the terminal is known to support it
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Whether the cells of `Hyperlinks` are drawn as OSC 8 hyperlinks. Where they aren't, they're drawn
+/// as plain text.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum HyperlinkSupport {
+    /// Draw hyperlinks if the terminal is known to support them
+    #[default]
+    Auto,
+    Always,
+    Never,
 }
 
 impl Default for CrosstermWindowSettings {
@@ -60,6 +426,24 @@ impl Default for CrosstermWindowSettings {
         CrosstermWindowSettings {
             colors: components::Colors::term_colors(),
             title: None,
+            bell_suppressed: false,
+            write_chunk_size: None,
+            synchronized_updates: SynchronizedUpdates::Auto,
+            hyperlinks: HyperlinkSupport::Auto,
+            render_schedule: CrosstermSchedule::PostUpdate,
+            input_schedule: CrosstermSchedule::PreUpdate,
+            overflow: components::Overflow::Clip,
+            mirror_text_path: None,
+            startup_hooks: Vec::new(),
+            shutdown_hooks: Vec::new(),
+            diff_strategy: DiffStrategy::Cell,
+            input_capacity: std::num::NonZeroUsize::new(1024).unwrap(),
+            input_overflow: input::InputOverflow::DropOldest,
+            safe_area: SafeArea::default(),
+            fixed_canvas: None,
+            frame_pacing: FramePacing::DropFrames,
+            handle_sigint: true,
+            exit_screen: ExitScreen::RestoreAlternate,
         }
     }
 }
@@ -82,14 +466,263 @@ impl CrosstermWindowSettings {
         self.colors = colors;
         self
     }
+
+    /// Sets the colors used for any cell that doesn't have a color of its own, including empty
+    /// cells. Changing this while the app is running redraws the whole window.
+    pub fn default_colors(
+        &mut self,
+        foreground: components::Color,
+        background: components::Color,
+    ) -> &mut Self {
+        self.set_colors(components::Colors::new(foreground, background))
+    }
+
+    pub fn bell_suppressed(&self) -> bool {
+        self.bell_suppressed
+    }
+
+    /// When suppressed, `CrosstermWindow::ring_bell` does nothing. This can be changed at any time.
+    pub fn set_bell_suppressed(&mut self, suppressed: bool) -> &mut Self {
+        self.bell_suppressed = suppressed;
+        self
+    }
+
+    pub fn write_chunk_size(&self) -> Option<std::num::NonZeroUsize> {
+        self.write_chunk_size
+    }
+
+    pub fn synchronized_updates(&self) -> SynchronizedUpdates {
+        self.synchronized_updates
+    }
+
+    /// Frames are drawn with the cursor hidden either way, but synchronized output also stops the
+    /// terminal from showing a frame before it's finished. This is applied when the window is created.
+    pub fn set_synchronized_updates(&mut self, mode: SynchronizedUpdates) -> &mut Self {
+        self.synchronized_updates = mode;
+        self
+    }
+
+    pub fn hyperlinks(&self) -> HyperlinkSupport {
+        self.hyperlinks
+    }
+
+    /// Most terminals that don't support hyperlinks quietly ignore them, but some print the escape
+    /// codes as text, so by default they're only used where they're known to work. This is applied
+    /// when the window is created.
+    pub fn set_hyperlinks(&mut self, mode: HyperlinkSupport) -> &mut Self {
+        self.hyperlinks = mode;
+        self
+    }
+
+    /// Each frame is built up in memory and then written to the terminal. By default the whole
+    /// frame is written at once, but when set, the frame is instead written and flushed in chunks
+    /// of at most this many bytes.
+    pub fn set_write_chunk_size(
+        &mut self,
+        chunk_size: Option<std::num::NonZeroUsize>,
+    ) -> &mut Self {
+        self.write_chunk_size = chunk_size;
+        self
+    }
+
+    pub fn render_schedule(&self) -> CrosstermSchedule {
+        self.render_schedule
+    }
+
+    /// The schedule the `CrosstermSet` systems run in, `PostUpdate` by default. Games which run
+    /// their logic in `FixedUpdate` can draw there too, so each step is drawn exactly once. This is
+    /// only read when the `CrosstermPlugin` is added.
+    pub fn set_render_schedule(&mut self, schedule: CrosstermSchedule) -> &mut Self {
+        self.render_schedule = schedule;
+        self
+    }
+
+    pub fn input_schedule(&self) -> CrosstermSchedule {
+        self.input_schedule
+    }
+
+    /// The schedule `ConsumableInput` is filled in, `PreUpdate` by default. The terminal is polled
+    /// for input before every update either way. This is only read when the `CrosstermPlugin` is added.
+    pub fn set_input_schedule(&mut self, schedule: CrosstermSchedule) -> &mut Self {
+        self.input_schedule = schedule;
+        self
+    }
+
+    pub fn overflow(&self) -> components::Overflow {
+        self.overflow
+    }
+
+    /// What happens to the parts of sprites that are outside the window, for sprites that don't
+    /// have an `Overflow` of their own. This can be changed at any time.
+    pub fn set_overflow(&mut self, overflow: components::Overflow) -> &mut Self {
+        self.overflow = overflow;
+        self
+    }
+
+    pub fn mirror_text_path(&self) -> Option<&std::path::Path> {
+        self.mirror_text_path.as_deref()
+    }
+
+    /// Every time the screen changes, writes what's on it to a file as plain text, without any colors.
+    /// The file is replaced each time, so it always holds just the current screen.
+    pub fn mirror_text_to<P: Into<std::path::PathBuf>>(&mut self, path: P) -> &mut Self {
+        self.mirror_text_path = Some(path.into());
+        self
+    }
+
+    pub fn stop_mirroring_text(&mut self) -> &mut Self {
+        self.mirror_text_path = None;
+        self
+    }
+
+    pub fn diff_strategy(&self) -> DiffStrategy {
+        self.diff_strategy
+    }
+
+    /// How much of the screen is written when a frame changes. Writing less is usually faster, but
+    /// over some connections fewer, longer writes do better. This can be changed at any time.
+    pub fn set_diff_strategy(&mut self, strategy: DiffStrategy) -> &mut Self {
+        self.diff_strategy = strategy;
+        self
+    }
+
+    pub fn frame_pacing(&self) -> FramePacing {
+        self.frame_pacing
+    }
+
+    /// What happens when an update runs late. This can be changed at any time.
+    pub fn set_frame_pacing(&mut self, pacing: FramePacing) -> &mut Self {
+        self.frame_pacing = pacing;
+        self
+    }
+
+    pub fn handle_sigint(&self) -> bool {
+        self.handle_sigint
+    }
+
+    /// In raw mode, Ctrl+C is a key press like any other rather than a signal. By default it sends
+    /// an `AppExit`, so the app shuts down and the terminal is restored. When this is turned off,
+    /// Ctrl+C is only sent as a key event, for apps which use it for something else. This can be
+    /// changed at any time.
+    pub fn set_handle_sigint(&mut self, handle: bool) -> &mut Self {
+        self.handle_sigint = handle;
+        self
+    }
+
+    pub fn exit_screen(&self) -> ExitScreen {
+        self.exit_screen
+    }
+
+    /// What happens to the screen when the app exits normally. After a panic the alternate screen is
+    /// never left, so the panic message stays readable. This can be changed at any time.
+    pub fn set_exit_screen(&mut self, exit_screen: ExitScreen) -> &mut Self {
+        self.exit_screen = exit_screen;
+        self
+    }
+
+    pub fn input_capacity(&self) -> std::num::NonZeroUsize {
+        self.input_capacity
+    }
+
+    /// The most key and mouse events that are sent in a single update. The default of 1024 is far
+    /// more than anyone can type, so only things like huge pastes go over it. Window events such as
+    /// resizes are always sent. This can be changed at any time.
+    pub fn set_input_capacity(&mut self, capacity: std::num::NonZeroUsize) -> &mut Self {
+        self.input_capacity = capacity;
+        self
+    }
+
+    pub fn input_overflow(&self) -> input::InputOverflow {
+        self.input_overflow
+    }
+
+    /// Which events are thrown away when more than `input_capacity` events arrive in one update.
+    /// This can be changed at any time.
+    pub fn set_input_overflow(&mut self, overflow: input::InputOverflow) -> &mut Self {
+        self.input_overflow = overflow;
+        self
+    }
+
+    pub fn safe_area(&self) -> SafeArea {
+        self.safe_area
+    }
+
+    /// Keeps sprites out of the given number of rows and columns at each edge of the terminal. The
+    /// window's center, and so `Origin::Center`, is the center of what's left. Positions are still
+    /// measured from the top left of the terminal. This can be changed at any time.
+    pub fn set_safe_area(&mut self, top: u16, bottom: u16, left: u16, right: u16) -> &mut Self {
+        self.safe_area = SafeArea {
+            top,
+            bottom,
+            left,
+            right,
+        };
+        self
+    }
+
+    pub fn fixed_canvas(&self) -> Option<FixedCanvas> {
+        self.fixed_canvas
+    }
+
+    /// Draws everything in a canvas of a fixed size in the middle of the terminal, and fills the rest
+    /// of the terminal with the letterbox color. Positions are measured from the canvas, so (0, 0) is
+    /// its top left cell and the window's center is its center, wherever it ends up after a resize.
+    /// When the terminal is too small for the canvas, the canvas is cut down to fit. If there's a
+    /// safe area, the canvas goes in the middle of what's left. This can be changed at any time.
+    pub fn set_fixed_canvas(&mut self, canvas: Option<FixedCanvas>) -> &mut Self {
+        self.fixed_canvas = canvas;
+        self
+    }
+
+    /// Runs `hook` while the terminal is being set up, after bevy_crossterm has switched to the
+    /// alternate screen and before the first frame is drawn. Anything it writes is flushed along
+    /// with the rest of the setup. Hooks run in the order they were added, and only the hooks added
+    /// before the app starts are run.
+    pub fn on_startup<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(&mut dyn std::io::Write) -> std::io::Result<()> + Send + Sync + 'static,
+    {
+        self.startup_hooks
+            .push(TerminalHook(std::sync::Arc::new(hook)));
+        self
+    }
+
+    /// Runs `hook` while the terminal is being put back to normal, before bevy_crossterm turns off
+    /// mouse capture and resets the colors. This happens when the app exits, and also if it panics. Hooks run in the order
+    /// they were added, and only the hooks added before the app starts are run.
+    pub fn on_shutdown<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(&mut dyn std::io::Write) -> std::io::Result<()> + Send + Sync + 'static,
+    {
+        self.shutdown_hooks
+            .push(TerminalHook(std::sync::Arc::new(hook)));
+        self
+    }
 }
 
+/// The terminal window. It's a component on the entity marked with `PrimaryWindow`.
+///
+/// The window is only marked as changed when something about it really changes, such as its size
+/// or colors, so `Query<&CrosstermWindow, Changed<CrosstermWindow>>` can be used to react to resizes
+/// without checking every frame. Calling `ring_bell` or `request_full_redraw` also counts as a change.
 #[derive(Debug, Component)]
 pub struct CrosstermWindow {
     height: u16,
     width: u16,
     colors: components::Colors,
     title: Option<String>,
+    bell_pending: bool,
+    full_redraw_pending: bool,
+    synchronized_updates: bool,
+    hyperlinks: bool,
+    shutdown_hooks: Vec<TerminalHook>,
+    safe_area: SafeArea,
+    fixed_canvas: Option<FixedCanvas>,
+    // Windows made without a terminal, in tests, leave the terminal alone when they're dropped
+    headless: bool,
+    // Whether the terminal has already been restored after a normal exit, so dropping the window
+    // doesn't run the shutdown hooks again
+    restored: bool,
 }
 
 impl CrosstermWindow {
@@ -109,16 +742,87 @@ impl CrosstermWindow {
         self.colors
     }
 
+    /// Whether frames are drawn using the terminal's synchronized output mode
+    pub fn synchronized_updates(&self) -> bool {
+        self.synchronized_updates
+    }
+
+    /// Whether `Hyperlinks` are drawn as links the user can click on
+    pub fn hyperlinks(&self) -> bool {
+        self.hyperlinks
+    }
+
     pub fn set_colors(&mut self, new_colors: components::Colors) {
         self.colors = new_colors;
     }
 
+    /// The edges of the window which are kept empty. See `CrosstermWindowSettings::set_safe_area`
+    pub fn safe_area(&self) -> SafeArea {
+        self.safe_area
+    }
+
+    /// The canvas everything is drawn in. See `CrosstermWindowSettings::set_fixed_canvas`
+    pub fn fixed_canvas(&self) -> Option<FixedCanvas> {
+        self.fixed_canvas
+    }
+
+    /// The part of the window sprites are drawn in, which is all of it unless there's a safe area or
+    /// a fixed canvas
+    pub fn drawable_area(&self) -> IRect {
+        let left = std::cmp::min(self.safe_area.left, self.width) as i32;
+        let top = std::cmp::min(self.safe_area.top, self.height) as i32;
+        let right = self.width.saturating_sub(self.safe_area.right) as i32;
+        let bottom = self.height.saturating_sub(self.safe_area.bottom) as i32;
+        let area = IRect::new(
+            left,
+            top,
+            std::cmp::max(left, right),
+            std::cmp::max(top, bottom),
+        );
+
+        match self.fixed_canvas {
+            Some(canvas) => {
+                let width = std::cmp::min(canvas.width as i32, area.width());
+                let height = std::cmp::min(canvas.height as i32, area.height());
+                let left = area.min.x + (area.width() - width) / 2;
+                let top = area.min.y + (area.height() - height) / 2;
+                IRect::new(left, top, left + width, top + height)
+            }
+            None => area,
+        }
+    }
+
     pub fn x_center(&self) -> u16 {
-        self.width / 2
+        let area = self.drawable_area();
+        (area.min.x + area.width() / 2) as u16
     }
 
     pub fn y_center(&self) -> u16 {
-        self.height / 2
+        let area = self.drawable_area();
+        (area.min.y + area.height() / 2) as u16
+    }
+
+    /// Whether a sprite placed at this position would be entirely inside the window, and out of the
+    /// safe area
+    pub fn contains(&self, position: &components::Position, sprite: &components::Sprite) -> bool {
+        let area = self.drawable_area();
+        position.x >= area.min.x
+            && position.y >= area.min.y
+            && position.x + sprite.width() as i32 <= area.max.x
+            && position.y + sprite.height() as i32 <= area.max.y
+    }
+
+    /// Rings the terminal bell the next time the screen is drawn, unless bells are suppressed in
+    /// the `CrosstermWindowSettings`
+    pub fn ring_bell(&mut self) {
+        self.bell_pending = true;
+    }
+
+    /// Clears the terminal and redraws everything the next time the screen is drawn. Only the parts
+    /// of the screen that changed are normally drawn, so use this after writing to the terminal
+    /// without going through bevy_crossterm
+    pub fn request_full_redraw(&mut self) {
+        self.full_redraw_pending = true;
     }
 }
 
@@ -128,3 +832,559 @@ pub struct Cursor {
     pub y: i32,
     pub hidden: bool,
 }
+
+/// Pauses bevy_crossterm's animations and effects: `Velocity`, `Transition`, `FadeOut`,
+/// `Typewriter`, `AnimatedSprite` and `SoftBlink` all stop where they are until it's unpaused. The screen is still
+/// drawn and input is still read, so the game can tell when to unpause.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug, Resource)]
+pub struct CrosstermPaused(pub bool);
+
+impl CrosstermPaused {
+    pub fn is_paused(&self) -> bool {
+        self.0
+    }
+
+    /// Pauses if running, or unpauses if paused
+    pub fn toggle(&mut self) {
+        self.0 = !self.0;
+    }
+}
+
+/// Draws rulers along the edges of the screen, and optionally grid lines, on top of everything else
+/// while this resource exists, to help with laying things out. They're drawn in the terminal's own
+/// cells, whatever the `CoordinateSpace` is. Only the picture on the screen changes; clicks and so
+/// on still go to the sprites underneath.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Resource)]
+pub struct DebugOverlay {
+    /// Whether the overlay is shown. It's shown by default, so inserting the resource shows it
+    pub enabled: bool,
+    /// Whether to number the columns along the top row and the rows down the left column. Only the
+    /// last digit of each number is shown, in bold for multiples of ten
+    pub rulers: bool,
+    /// How many cells apart the grid lines are. No grid is drawn when this is `None`
+    pub grid: Option<std::num::NonZeroU16>,
+}
+
+impl Default for DebugOverlay {
+    fn default() -> DebugOverlay {
+        DebugOverlay {
+            enabled: true,
+            rulers: true,
+            grid: None,
+        }
+    }
+}
+
+impl DebugOverlay {
+    /// Shows the overlay if it's hidden, or hides it if it's shown
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+}
+
+/// The size of the terminal as `(width, height)`, in cells. Unlike `CrosstermWindow` this works
+/// before the app is running, so it can be used to pick the `CrosstermWindowSettings`.
+pub fn terminal_size() -> std::io::Result<(u16, u16)> {
+    crossterm::terminal::size()
+}
+
+/// A system which clears the terminal and redraws everything the next time the screen is drawn,
+/// like `CrosstermWindow::request_full_redraw`. Adding it to `OnExit` or `OnEnter` of a state makes
+/// sure the new state starts from a clean screen, with nothing left over from the entities the old one
+/// despawned, e.g. `app.add_systems(OnExit(GameState::Loading), request_full_redraw)`. Nothing
+/// happens if there's no window yet, like in an app that hasn't called `render_once`.
+pub fn request_full_redraw(mut window: Query<&mut CrosstermWindow>) {
+    if let Ok(mut window) = window.get_single_mut() {
+        window.request_full_redraw();
+    }
+}
+
+/// Composes a frame and writes it to the terminal straight away, for apps which don't use the
+/// plugin's runner or want to draw outside of the usual schedule. Everything is drawn from the world
+/// as it is now, but the `CrosstermSet::Prepare` and `CrosstermSet::PostProcess` systems aren't run,
+/// so entities aren't clamped to the window and so on until the next scheduled render.
+///
+/// If there isn't a `CrosstermWindow` yet, one is created, which sets up the terminal the same way
+/// the runner does. The terminal is restored when the window is despawned, except for leaving the
+/// alternate screen, which is left to the app.
+pub fn render_once(world: &mut World) {
+    use bevy::ecs::system::RunSystemOnce;
+
+    if world
+        .query::<&CrosstermWindow>()
+        .iter(world)
+        .next()
+        .is_none()
+    {
+        runner::setup_window(world);
+    }
+
+    // Assets changed since the last update haven't sent their `AssetEvent`s yet, so redraw everything
+    // rather than wait for them
+    let mut state = world.resource_mut::<components::RenderState>();
+    state.redraw = true;
+    state.rebuild_background = true;
+    world.run_system_once(systems::calculate_redraw);
+    world.run_system_once(systems::compose_frame);
+    world.run_system_once(systems::crossterm_render);
+}
+
+/// The random number generator used by randomized effects, such as `DissolveMask::Random`.
+///
+/// Every effect draws from this one generator, so seeding it makes them play out exactly the same
+/// way each time, which is handy for tests and replays.
+#[derive(Clone, Debug, Resource)]
+pub struct CrosstermRng(fastrand::Rng);
+
+impl Default for CrosstermRng {
+    fn default() -> Self {
+        CrosstermRng(fastrand::Rng::new())
+    }
+}
+
+impl CrosstermRng {
+    pub fn with_seed(seed: u64) -> Self {
+        CrosstermRng(fastrand::Rng::with_seed(seed))
+    }
+
+    /// Restarts the generator from a seed
+    pub fn seed(&mut self, seed: u64) {
+        self.0.seed(seed);
+    }
+
+    /// Shuffles a slice in place
+    pub fn shuffle<T>(&mut self, slice: &mut [T]) {
+        self.0.shuffle(slice);
+    }
+
+    /// A random number in `0.0..1.0`
+    pub fn f32(&mut self) -> f32 {
+        self.0.f32()
+    }
+
+    /// A random number in the given range
+    pub fn usize(&mut self, range: impl std::ops::RangeBounds<usize>) -> usize {
+        self.0.usize(range)
+    }
+}
+
+/// Counts of what happened the last time a frame was composed. Static sprites are only counted in
+/// frames where the static layer had to be redrawn.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Resource)]
+pub struct RenderStats {
+    /// Sprites which were drawn into the frame
+    pub drawn: usize,
+    /// Sprites which were skipped because they were entirely off the screen
+    pub culled: usize,
+    /// Sprites which had to be laid out again, because they're new or they or their stylemaps
+    /// changed. Other sprites reuse the layout from the last time they were drawn, even if they moved
+    pub rasterized: usize,
+    /// Input events which were thrown away because too many arrived at once, since the app started.
+    /// See `CrosstermWindowSettings::set_input_capacity`
+    pub input_dropped: usize,
+}
+
+/// Where the origin of the coordinates used by `Position` is on the screen
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Origin {
+    /// The top left cell of the window, or of the fixed canvas if there is one
+    #[default]
+    TopLeft,
+    /// The cell in the middle of the window, which moves when the window is resized
+    Center,
+    /// A fixed cell of the window, counted from the top left of the fixed canvas if there is one
+    Cell { x: i32, y: i32 },
+}
+
+/// Changes how `Position`s map to cells on the screen, for games which use a different coordinate
+/// system than the terminal. Without this resource, (0, 0) is the top left cell and y goes down.
+///
+/// A `Position` always says where the top left cell of a sprite goes, even when y goes up.
+/// `ClampToWindow` and `CursorMode::AtCaret` use these coordinates too, while the `Cursor`, the
+/// `CellBuffer`, and `CrosstermWindow::contains` always use the terminal's own cells.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Resource)]
+pub struct CoordinateSpace {
+    pub origin: Origin,
+    /// Whether bigger y values are further up the screen
+    pub y_up: bool,
+}
+
+impl CoordinateSpace {
+    /// An origin in the middle of the window with y going up, like a graph
+    pub fn centered_y_up() -> CoordinateSpace {
+        CoordinateSpace {
+            origin: Origin::Center,
+            y_up: true,
+        }
+    }
+
+    fn origin_cell(&self, window: &CrosstermWindow) -> (i32, i32) {
+        // With a fixed canvas, positions are measured from its top left instead of the terminal's
+        let (left, top) = match window.fixed_canvas() {
+            Some(_) => window.drawable_area().min.into(),
+            None => (0, 0),
+        };
+        match self.origin {
+            Origin::TopLeft => (left, top),
+            Origin::Center => (window.x_center() as i32, window.y_center() as i32),
+            Origin::Cell { x, y } => (left + x, top + y),
+        }
+    }
+
+    /// The cell on the screen a position refers to
+    pub fn to_screen(&self, x: i32, y: i32, window: &CrosstermWindow) -> (i32, i32) {
+        let (origin_x, origin_y) = self.origin_cell(window);
+        let y = if self.y_up {
+            origin_y - y
+        } else {
+            origin_y + y
+        };
+        (origin_x + x, y)
+    }
+
+    /// The position which refers to a cell on the screen
+    pub fn from_screen(&self, x: i32, y: i32, window: &CrosstermWindow) -> (i32, i32) {
+        let (origin_x, origin_y) = self.origin_cell(window);
+        let y = if self.y_up {
+            origin_y - y
+        } else {
+            y - origin_y
+        };
+        (x - origin_x, y)
+    }
+}
+
+/// Decides where the `Cursor` should be, so systems don't have to update it by hand.
+///
+/// When this resource exists, the `Cursor` is updated to match it every frame. Remove it to go back
+/// to controlling the `Cursor` directly.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Resource)]
+pub enum CursorMode {
+    #[default]
+    Hidden,
+    /// Show the cursor where an entity is drawn, which includes the positions of its parents, offset
+    /// by its `Caret` if it has one
+    AtCaret(Entity),
+    /// Show the cursor at a fixed position on the screen
+    Free { x: i32, y: i32 },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::CellBuffer;
+    use crate::components::{Position, Sprite, Style};
+    use crate::testing;
+    use bevy::ecs::schedule::ScheduleLabel;
+    use bevy::ecs::system::RunSystemOnce;
+
+    fn ring(app: &mut App) {
+        let mut window = app.world.query::<&mut CrosstermWindow>();
+        window.single_mut(&mut app.world).ring_bell();
+        app.update();
+    }
+
+    #[test]
+    fn bell_is_written_once_unless_suppressed() {
+        let (mut app, output) = testing::app(10, 4);
+        output.take();
+
+        ring(&mut app);
+        assert_eq!(output.take().matches('\x07').count(), 1);
+        // The bell was only rung once
+        app.update();
+        assert!(!output.take().contains('\x07'));
+
+        app.world
+            .resource_mut::<CrosstermWindowSettings>()
+            .set_bell_suppressed(true);
+        ring(&mut app);
+        assert!(!output.take().contains('\x07'));
+    }
+
+    #[test]
+    fn systems_between_compose_and_flush_draw_over_the_frame() {
+        let (mut app, output) = testing::app(10, 4);
+        app.add_systems(
+            PostUpdate,
+            (|mut frame: ResMut<CellBuffer>| frame.put_str(7, 0, "FPS", Style::default()))
+                .after(CrosstermSet::Compose)
+                .before(CrosstermSet::Flush),
+        );
+        testing::spawn_sprite(&mut app, "sprite", 0, 0);
+        output.take();
+        app.update();
+
+        assert_eq!(testing::screen(&app)[0], "sprite FPS");
+        let written = output.take();
+        assert!(written.contains("sprite"), "{written:?}");
+        assert!(written.contains("FPS"), "{written:?}");
+    }
+
+    #[test]
+    fn post_processing_changes_what_is_flushed() {
+        #[derive(Resource, Default)]
+        struct Passes(usize);
+
+        let (mut app, output) = testing::app(6, 2);
+        app.init_resource::<Passes>().add_systems(
+            PostUpdate,
+            (|mut frame: ResMut<CellBuffer>, mut passes: ResMut<Passes>| {
+                passes.0 += 1;
+                for y in 0..frame.height() as i32 {
+                    for x in 0..frame.width() as i32 {
+                        let cell = frame.get_mut(x, y).unwrap();
+                        cell.style.colors.background = Some(crossterm::style::Color::DarkRed);
+                    }
+                }
+            })
+            .in_set(CrosstermSet::PostProcess),
+        );
+        let sprite = testing::spawn_sprite(&mut app, "tint", 0, 0);
+        output.take();
+        app.update();
+
+        let frame = app.world.resource::<CellBuffer>();
+        assert!((0..6).all(|x| {
+            let cell = frame.get(x, 1).unwrap();
+            cell.style.colors.background == Some(crossterm::style::Color::DarkRed)
+        }));
+        let written = output.take();
+        let tinted = written.find("\x1b[48;5;1m").expect(&written);
+        assert!(written[tinted..].contains("tint"), "{written:?}");
+
+        // Nothing new was drawn once the sprite's asset events have arrived, so there's nothing to tint
+        testing::step(&mut app, 0.0);
+        let passes = app.world.resource::<Passes>().0;
+        output.take();
+        testing::step(&mut app, 0.0);
+        assert_eq!(app.world.resource::<Passes>().0, passes);
+        assert_eq!(output.take(), "");
+
+        // Each new frame is tinted before it's compared with the last one, so only the move is written
+        app.world.get_mut::<Position>(sprite).unwrap().x = 1;
+        testing::step(&mut app, 0.0);
+        assert_eq!(app.world.resource::<Passes>().0, passes + 1);
+        let written = output.take();
+        assert!(written.contains("tint"), "{written:?}");
+        assert!(!written.contains("\x1b[49m"), "{written:?}");
+    }
+
+    #[test]
+    fn full_redraw_repaints_an_unchanged_screen() {
+        let (mut app, output) = testing::app(10, 4);
+        testing::spawn_sprite(&mut app, "top", 0, 0);
+        testing::spawn_sprite(&mut app, "bottom", 2, 3);
+        app.update();
+        app.update();
+        output.take();
+        app.update();
+        assert_eq!(output.take(), "");
+
+        let mut window = app.world.query::<&mut CrosstermWindow>();
+        window.single_mut(&mut app.world).request_full_redraw();
+        app.update();
+        let written = output.take();
+        assert!(written.contains("\x1b[2J"), "{written:?}");
+        assert!(written.contains("top"), "{written:?}");
+        assert!(written.contains("bottom"), "{written:?}");
+
+        // Only one frame is repainted
+        app.update();
+        assert_eq!(output.take(), "");
+    }
+
+    #[test]
+    fn render_and_input_systems_can_run_in_fixed_update() {
+        let (mut app, output) = testing::app_with(10, 3, |settings| {
+            settings
+                .set_render_schedule(CrosstermSchedule::FixedUpdate)
+                .set_input_schedule(CrosstermSchedule::FixedUpdate);
+        });
+        fn has_system(app: &App, schedule: impl ScheduleLabel, name: &str) -> bool {
+            // Schedules are only made once something is added to them
+            app.get_schedule(schedule).is_some_and(|schedule| {
+                let mut systems = schedule.graph().systems();
+                systems.any(|(_, system, _)| system.name().ends_with(name))
+            })
+        }
+        assert!(has_system(&app, FixedUpdate, "crossterm_render"));
+        assert!(has_system(&app, FixedUpdate, "collect_consumable_input"));
+        assert!(!has_system(&app, PostUpdate, "crossterm_render"));
+        assert!(!has_system(&app, PreUpdate, "collect_consumable_input"));
+
+        // Frames are only drawn once a fixed timestep has gone by
+        testing::spawn_sprite(&mut app, "fixed", 0, 0);
+        output.take();
+        app.update();
+        assert!(!output.take().contains("fixed"));
+        testing::step(&mut app, 0.1);
+        testing::step(&mut app, 0.1);
+        assert!(output.take().contains("fixed"));
+    }
+
+    #[test]
+    fn centered_origin_puts_zero_in_the_middle_of_the_window() {
+        let (mut app, _) = testing::app(11, 7);
+        app.insert_resource(CoordinateSpace::centered_y_up());
+        testing::spawn_sprite(&mut app, "o", 0, 0);
+        testing::spawn_sprite(&mut app, "u", 2, 1);
+        testing::spawn_sprite(&mut app, "d", -3, -2);
+        app.update();
+
+        let screen = testing::screen(&app);
+        assert_eq!(screen[3], "     o", "{screen:#?}");
+        // y goes up the screen
+        assert_eq!(screen[2], "       u", "{screen:#?}");
+        assert_eq!(screen[5], "  d", "{screen:#?}");
+    }
+
+    #[test]
+    fn terminal_size_works_without_an_app() {
+        // Tests might not be run in a terminal, in which case there's no size to get
+        match terminal_size() {
+            Ok((width, height)) => {
+                assert!(width > 0 && height > 0, "{width}x{height}");
+                assert_eq!(crossterm::terminal::size().unwrap(), (width, height));
+            }
+            Err(error) => assert!(crossterm::terminal::size().is_err(), "{error}"),
+        }
+    }
+
+    #[test]
+    fn safe_areas_are_left_empty_and_moved_out_of_the_center() {
+        let (mut app, output) = testing::app_with(12, 6, |settings| {
+            settings.set_safe_area(1, 2, 3, 1);
+        });
+        let row = "#".repeat(12);
+        testing::spawn_sprite(&mut app, &vec![row; 6].join("\n"), 0, 0);
+        app.update();
+
+        let mut window = app.world.query::<&CrosstermWindow>();
+        let window = window.single(&app.world);
+        assert_eq!(window.drawable_area(), IRect::new(3, 1, 11, 4));
+        assert_eq!((window.x_center(), window.y_center()), (7, 2));
+        let inside = format!("   {}", "#".repeat(8));
+        assert_eq!(
+            testing::screen(&app),
+            ["", &inside, &inside, &inside, "", ""]
+        );
+        // Only the cells inside the safe area were written
+        assert_eq!(output.take().matches('#').count(), 8 * 3);
+    }
+
+    #[test]
+    fn state_transitions_can_start_from_a_clean_screen() {
+        #[derive(States, Default, Clone, Copy, Debug, Eq, PartialEq, Hash)]
+        enum GameState {
+            #[default]
+            Loading,
+            Running,
+        }
+
+        #[derive(Component)]
+        struct LoadingScreen;
+
+        fn despawn_loading(mut commands: Commands, loading: Query<Entity, With<LoadingScreen>>) {
+            for entity in &loading {
+                commands.entity(entity).despawn();
+            }
+        }
+
+        let (mut app, output) = testing::app(12, 2);
+        app.add_state::<GameState>().add_systems(
+            OnExit(GameState::Loading),
+            (despawn_loading, request_full_redraw),
+        );
+        let loading = testing::spawn_sprite(&mut app, "Loading...", 0, 0);
+        app.world.entity_mut(loading).insert(LoadingScreen);
+        app.update();
+        app.update();
+        assert_eq!(testing::screen(&app)[0], "Loading...");
+
+        output.take();
+        app.world
+            .resource_mut::<NextState<GameState>>()
+            .set(GameState::Running);
+        testing::spawn_sprite(&mut app, "Go", 0, 1);
+        app.update();
+
+        assert_eq!(testing::screen(&app), ["", "Go"]);
+        let written = output.take();
+        assert!(written.contains("\x1b[2J"), "{written:?}");
+        assert!(!written.contains("Loading"), "{written:?}");
+
+        // Without a window there's nothing to redraw, which is fine
+        World::new().run_system_once(request_full_redraw);
+    }
+
+    #[test]
+    fn render_once_draws_the_world_as_it_is_now() {
+        let (mut app, output) = testing::app(10, 2);
+        let entity = testing::spawn_sprite(&mut app, "before", 0, 0);
+        app.update();
+        app.update();
+
+        // Without running an update, so no asset events have been sent for the change
+        let handle = app.world.get::<Handle<Sprite>>(entity).unwrap().clone();
+        let mut sprites = app.world.resource_mut::<Assets<Sprite>>();
+        sprites.get_mut(&handle).unwrap().update("after");
+        app.world.get_mut::<Position>(entity).unwrap().y = 1;
+        app.world.resource_mut::<Cursor>().x = 3;
+        output.take();
+        render_once(&mut app.world);
+
+        assert_eq!(testing::screen(&app), ["", "after"]);
+        let written = output.take();
+        assert!(written.contains("after"), "{written:?}");
+        assert!(written.ends_with("\x1b[1;4H\x1b[?25h"), "{written:?}");
+    }
+
+    #[test]
+    fn fixed_canvases_are_centered_in_a_letterbox() {
+        use crossterm::style::Color;
+
+        let canvas = FixedCanvas::new(4, 2, Color::Blue);
+        let (mut app, _) = testing::app_with(10, 4, |settings| {
+            settings.set_fixed_canvas(Some(canvas));
+        });
+        testing::spawn_sprite(&mut app, "abcd", 0, 0);
+        // Wrapping sprites wrap around the canvas, not the terminal
+        let wrapped = testing::spawn_sprite(&mut app, "xy", 3, 1);
+        app.world
+            .entity_mut(wrapped)
+            .insert(components::Overflow::Wrap);
+        app.update();
+
+        let mut window = app.world.query::<&CrosstermWindow>();
+        let window = window.single(&app.world);
+        assert_eq!(window.drawable_area(), IRect::new(3, 1, 7, 3));
+        assert_eq!((window.x_center(), window.y_center()), (5, 2));
+        assert_eq!(testing::screen(&app), ["", "   abcd", "   y  x", ""]);
+        let boxed = |app: &App| {
+            let frame = app.world.resource::<CellBuffer>();
+            let mut boxed = Vec::new();
+            for y in 0..frame.height() as i32 {
+                let row: String = (0..frame.width() as i32)
+                    .map(|x| match frame.get(x, y).unwrap().style.colors.background {
+                        Some(Color::Blue) => '#',
+                        _ => '.',
+                    })
+                    .collect();
+                boxed.push(row);
+            }
+            boxed
+        };
+        assert_eq!(
+            boxed(&app),
+            ["##########", "###....###", "###....###", "##########"]
+        );
+
+        // A bigger terminal moves the canvas back to the middle
+        let mut window = app.world.query::<&mut CrosstermWindow>();
+        window.single_mut(&mut app.world).width = 12;
+        app.update();
+        assert_eq!(testing::screen(&app)[1], "    abcd");
+        assert_eq!(boxed(&app)[1], "####....####");
+    }
+}
@@ -1,9 +1,68 @@
-pub use crate::{CrosstermPlugin, CrosstermWindow, CrosstermWindowSettings, Cursor};
+//! Everything needed to build a game with bevy_crossterm. `use bevy_crossterm::prelude::*;` should
+//! be all most games need, so anything added to the public API that users interact with regularly
+//! belongs in here.
+//!
+//! There's no `Anchor` component, sprites are placed by the top left corner of their `Position`, and
+//! no `Clip` component either, because `Overflow::Clip` does that. Visibility is `Visible`, so it
+//! doesn't clash with bevy's own `Visibility`.
+//!
+//! ```
+//! use bevy_crossterm::prelude::*;
+//!
+//! let sprite = Sprite::wrapped("Hello there, terminal", 8).bordered(BorderStyle::Rounded);
+//! assert_eq!(
+//!     sprite.to_string(),
+//!     "╭────────╮\n│Hello   │\n│there,  │\n│terminal│\n╰────────╯"
+//! );
+//!
+//! let solid = StyleMap::solid(Color::White, Color::DarkBlue);
+//! let gradient = StyleMap::gradient((255, 0, 0), (0, 0, 255), sprite.width(), sprite.height());
+//! assert!(solid != gradient);
+//!
+//! # let mut sprites = bevy::asset::Assets::<Sprite>::default();
+//! # let mut stylemaps = bevy::asset::Assets::<StyleMap>::default();
+//! let bundle = SpriteBundle {
+//!     sprite: sprites.add(sprite),
+//!     stylemap: stylemaps.add(gradient),
+//!     position: Position::with_xy(2, 1),
+//!     visible: Visible::transparent(),
+//! };
+//! # let _ = (bundle, Overflow::Clip);
+//! ```
+
+pub use crate::{
+    render_once, request_full_redraw, terminal_size, CoordinateSpace, CrosstermKeyEventWrapper,
+    CrosstermMouseEventWrapper, CrosstermPaused, CrosstermPlugin, CrosstermRenderError,
+    CrosstermRng, CrosstermSchedule, CrosstermSet, CrosstermWindow, CrosstermWindowSettings,
+    CrosstermWriter, Cursor, CursorMode, DebugOverlay, DiffStrategy, ExitScreen,
+    FirstFrameRendered, FixedCanvas, FramePacing, HyperlinkSupport, Origin, RenderStats, SafeArea,
+    SynchronizedUpdates, TerminalHook,
+};
+
+pub use crate::buffer::{Cell, CellBuffer};
+
+#[cfg(feature = "image")]
+pub use crate::{ImageLoaderSettings, ImageMode};
+
+pub use crate::input::{
+    ConsumableInput, CrosstermInputSource, InputFocus, InputOverflow, InputSource, ScriptedInput,
+    SpriteClicked, SpriteHoverEnter, SpriteHoverExit, TerminalInput,
+};
+
+pub use crate::widgets::{
+    ConfirmDialog, ConfirmResult, Orientation, ProgressBar, Selectable, Selection,
+};
 
 pub use crate::components::{
-    Color, Colors, Position, Sprite, SpriteBundle, Style, StyleMap, Visible,
+    sprites_overlap, sprites_overlap_precise, AnimatedSprite, BayerMatrix, BorderStyle, Caret,
+    ClampToWindow, Clickable, Color, Colors, ControlChars, DissolveMask, FadeFinished, FadeOut,
+    GridSnap, Hyperlinks, Layer, Overflow, Position, RenderPriority, SoftBlink, Sprite,
+    SpriteBundle, SpriteMetrics, SpriteProblem, SpriteWarning, Static, StreamingSprite, Style,
+    StyleMap, Transition, TransitionFinished, Typewriter, TypewriterDone, Velocity, Visible,
 };
 
 // Re-export crossterm structs for easier access
-pub use crossterm::event::{KeyCode, KeyEvent, MouseButton, MouseEvent};
+pub use crossterm::event::{
+    KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+};
 pub use crossterm::style::{Attribute, Attributes};
@@ -1,5 +1,9 @@
+use crate::buffer::Cell;
+use crate::components::{Colors, RenderState};
+use crate::input::CrosstermInputSource;
 use crate::{
     CrosstermKeyEventWrapper, CrosstermMouseEventWrapper, CrosstermWindow, CrosstermWindowSettings,
+    ExitScreen, HyperlinkSupport, RenderStats, SynchronizedUpdates,
 };
 use std::io::Write;
 
@@ -7,6 +11,7 @@ use bevy::window::{PrimaryWindow, WindowCreated, WindowResized};
 use bevy_app::{App, AppExit};
 use bevy_ecs::entity::Entity;
 use bevy_ecs::event::Events;
+use bevy_ecs::world::{Mut, World};
 use crossterm::{queue, ExecutableCommand, QueueableCommand};
 
 impl CrosstermWindow {
@@ -14,61 +19,176 @@ impl CrosstermWindow {
     fn new(settings: &CrosstermWindowSettings) -> Self {
         crossterm::terminal::enable_raw_mode().expect("Could not enable crossterm raw mode");
 
-        let mut term = std::io::stdout();
-        queue!(
-            term,
-            crossterm::terminal::EnterAlternateScreen,
-            crossterm::event::EnableMouseCapture,
-            crossterm::event::EnableFocusChange,
-            crossterm::terminal::Clear(crossterm::terminal::ClearType::All,),
-        )
-        .expect("Could not queue commands");
-
-        let title = if let Some(title) = &settings.title {
-            term.queue(crossterm::terminal::SetTitle(title))
-                .expect("Could not set terminal title");
-            Some(title.clone())
-        } else {
-            None
-        };
-
+        prepare_terminal(settings, &mut std::io::stdout()).expect("Could not initialize terminal");
+        let title = settings.title.clone();
         let colors = settings.colors;
-        term.queue(crossterm::style::SetColors(colors.to_crossterm()))
-            .expect("Could not set window colors");
 
-        term.flush().expect("Could not initialize terminal");
+        let (width, height) = crate::terminal_size().expect("Could not read current terminal size");
+
+        let synchronized_updates = match settings.synchronized_updates {
+            SynchronizedUpdates::Auto => supports_synchronized_updates(),
+            SynchronizedUpdates::Always => true,
+            SynchronizedUpdates::Never => false,
+        };
 
-        let (width, height) =
-            crossterm::terminal::size().expect("Could not read current terminal size");
+        let hyperlinks = match settings.hyperlinks {
+            HyperlinkSupport::Auto => supports_hyperlinks(),
+            HyperlinkSupport::Always => true,
+            HyperlinkSupport::Never => false,
+        };
 
         Self {
             height,
             width,
             colors,
             title,
+            bell_pending: false,
+            full_redraw_pending: false,
+            synchronized_updates,
+            hyperlinks,
+            shutdown_hooks: settings.shutdown_hooks.clone(),
+            safe_area: settings.safe_area,
+            fixed_canvas: settings.fixed_canvas,
+            headless: false,
+            restored: false,
         }
     }
 }
 
-// Ensure teardown even if we encounter a panic
-impl Drop for CrosstermWindow {
-    fn drop(&mut self) {
-        let mut term = std::io::stdout();
-        queue!(
+/// Switches to the alternate screen, turns on mouse and focus events, sets the title and colors, and
+/// then runs the startup hooks
+fn prepare_terminal(
+    settings: &CrosstermWindowSettings,
+    term: &mut impl Write,
+) -> std::io::Result<()> {
+    queue!(
+        term,
+        crossterm::terminal::EnterAlternateScreen,
+        crossterm::event::EnableMouseCapture,
+        crossterm::event::EnableFocusChange,
+        crossterm::terminal::Clear(crossterm::terminal::ClearType::All),
+    )?;
+    if let Some(title) = &settings.title {
+        term.queue(crossterm::terminal::SetTitle(title))?;
+    }
+    term.queue(crossterm::style::SetColors(settings.colors.to_crossterm()))?;
+
+    for hook in &settings.startup_hooks {
+        hook.run(term)?;
+    }
+
+    term.flush()
+}
+
+/// Guesses whether the terminal supports synchronized output, based on the environment.
+///
+/// Terminals can be asked directly, but crossterm's input parser doesn't understand the reply and
+/// would swallow the keys pressed after it, so this sticks to terminals known to support it.
+fn supports_synchronized_updates() -> bool {
+    let var = |name| std::env::var(name).unwrap_or_default().to_lowercase();
+
+    let term = var("TERM");
+    let term_program = var("TERM_PROGRAM");
+    [
+        "kitty",
+        "foot",
+        "alacritty",
+        "wezterm",
+        "contour",
+        "ghostty",
+    ]
+    .iter()
+    .any(|known| term.contains(known) || term_program.contains(known))
+        || term_program == "iterm.app"
+        || std::env::var_os("WT_SESSION").is_some()
+}
+
+/// Guesses whether the terminal supports OSC 8 hyperlinks, based on the environment, the same way
+/// as for synchronized output.
+fn supports_hyperlinks() -> bool {
+    let var = |name| std::env::var(name).unwrap_or_default().to_lowercase();
+
+    let term = var("TERM");
+    let term_program = var("TERM_PROGRAM");
+    // VTE based terminals, like GNOME Terminal, support them from version 0.50
+    let vte = var("VTE_VERSION")
+        .parse::<u32>()
+        .is_ok_and(|version| version >= 5000);
+    [
+        "kitty",
+        "foot",
+        "alacritty",
+        "wezterm",
+        "contour",
+        "ghostty",
+    ]
+    .iter()
+    .any(|known| term.contains(known) || term_program.contains(known))
+        || ["iterm.app", "vscode"].contains(&term_program.as_str())
+        || std::env::var_os("WT_SESSION").is_some()
+        || vte
+}
+
+/// Puts the terminal's colors back the way they were before the app started. Besides the colors of
+/// the text, OSC sequences can change the cursor color and the terminal's default colors, and some
+/// terminals keep those changes around after the app exits. Terminals which don't support these
+/// sequences ignore them.
+fn reset_terminal_colors(term: &mut impl Write) -> std::io::Result<()> {
+    queue!(
+        term,
+        crossterm::style::SetAttribute(crossterm::style::Attribute::Reset),
+        crossterm::style::ResetColor,
+        // Reset the default foreground, default background and cursor colors
+        crossterm::style::Print("\x1b]110\x1b\\"),
+        crossterm::style::Print("\x1b]111\x1b\\"),
+        crossterm::style::Print("\x1b]112\x1b\\"),
+    )
+}
+
+impl CrosstermWindow {
+    /// Runs the shutdown hooks, and turns off everything the window turned on in the terminal
+    fn restore_terminal(&mut self, term: &mut impl Write) {
+        self.restored = true;
+        for hook in &self.shutdown_hooks {
+            if let Err(error) = hook.run(term) {
+                bevy::log::error!("Could not run shutdown hook: {error}");
+            }
+        }
+        let reset = queue!(
             term,
             crossterm::event::DisableMouseCapture,
             crossterm::event::DisableFocusChange,
             crossterm::cursor::Show,
         )
-        .expect("Could not queue commands");
-        term.flush().expect("Could not reset terminal");
+        .and_then(|_| reset_terminal_colors(term))
+        .and_then(|_| term.flush());
+        if let Err(error) = reset {
+            bevy::log::error!("Could not reset terminal: {error}");
+        }
+    }
+}
+
+// Ensure teardown even if we encounter a panic. After an AppExit the terminal has already been restored,
+// so only raw mode is left to turn off once the app is dropped. Nothing in here may panic: panicking while already unwinding aborts the process, which would leave
+// the terminal in raw mode. Failures are logged and the rest of the teardown carries on
+impl Drop for CrosstermWindow {
+    fn drop(&mut self) {
+        if self.headless {
+            return;
+        }
+
+        if !self.restored {
+            self.restore_terminal(&mut std::io::stdout());
+        }
 
-        crossterm::terminal::disable_raw_mode().expect("Could not disable raw mode");
+        if let Err(error) = crossterm::terminal::disable_raw_mode() {
+            bevy::log::error!("Could not disable raw mode: {error}");
+        }
     }
 }
 
 pub fn crossterm_runner(mut app: App) {
-    let bevy_window = setup_window(&mut app);
+    let bevy_window = setup_window(&mut app.world);
 
     // There should only be one ScheduleRunnerPlugin, but if there isn't, add one
     // (also there might be a better way to do this)
@@ -89,7 +209,8 @@ pub fn crossterm_runner(mut app: App) {
         bevy::app::RunMode::Loop { wait } => {
             // Run the main loop, and delay if we need to
             let mut start_time = std::time::Instant::now();
-            while tick(&mut app, bevy_window).is_ok() {
+            let started = start_time;
+            'running: while tick(&mut app, bevy_window, started).is_ok() {
                 let end_time = std::time::Instant::now();
 
                 if let Some(wait) = wait {
@@ -98,36 +219,79 @@ pub fn crossterm_runner(mut app: App) {
                         let delay = wait - exe_time;
                         // dbg!(delay);
                         std::thread::sleep(delay);
+                    } else if catch_up(&mut app, bevy_window, started, exe_time, wait).is_err() {
+                        break 'running;
                     }
                 }
 
-                start_time = end_time;
+                // Time spent waiting or catching up doesn't count towards the next update
+                start_time = std::time::Instant::now();
             }
 
             // Cleanup and teardown
-            // Most teardown is done by the drop implementation of CrosstermWindow, which will run even if we encounter
-            // a panic (provided we do not run in panic="abort" mode)
+            // Raw mode is turned off by the drop implementation of CrosstermWindow, which also restores the
+            // terminal if we encounter a panic (provided we do not run in panic="abort" mode)
             // We do __NOT__ want to leave the alternate screen after a panic, because that would wipe out the panic
             // message
-            let mut term = std::io::stdout();
-            term.execute(crossterm::terminal::LeaveAlternateScreen)
-                .expect("Could not leave alternate terminal");
+            shut_down(&mut app.world, bevy_window, &mut std::io::stdout())
+                .expect("Could not reset terminal");
         }
     }
 }
 
+/// Restores the terminal once the app has exited normally. The shutdown hooks run while the alternate
+/// screen is still up, so they can undo what the startup hooks did there, and only then is the
+/// alternate screen left behind.
+fn shut_down(world: &mut World, bevy_window: Entity, term: &mut impl Write) -> std::io::Result<()> {
+    let exit_screen = world.resource::<CrosstermWindowSettings>().exit_screen();
+    let frame = world.resource::<RenderState>().previous.clone();
+    let mut window = world.get_mut::<CrosstermWindow>(bevy_window).unwrap();
+    window.restore_terminal(term);
+    leave_screen(exit_screen, &window, &frame, term)
+}
+
+/// Leaves the alternate screen once the app has exited, then does whatever the `ExitScreen` says
+/// with the screen underneath
+fn leave_screen(
+    exit_screen: ExitScreen,
+    window: &CrosstermWindow,
+    frame: &crate::buffer::CellBuffer,
+    term: &mut impl Write,
+) -> std::io::Result<()> {
+    term.execute(crossterm::terminal::LeaveAlternateScreen)?;
+
+    match exit_screen {
+        ExitScreen::RestoreAlternate => {}
+        ExitScreen::ClearScreen => {
+            queue!(
+                term,
+                crossterm::terminal::Clear(crossterm::terminal::ClearType::All),
+                crossterm::cursor::MoveTo(0, 0),
+            )?;
+        }
+        ExitScreen::LeaveFrame => {
+            let blank = Cell::blank(window.colors.with_default(Colors::term_colors()));
+            let mut text = Vec::new();
+            crate::systems::write_frame_inline(&mut text, frame, &blank)?;
+            term.write_all(&text)?;
+        }
+    }
+    term.flush()
+}
+
 /// Setup the crossterm window, so it is available to the rest of the app
-fn setup_window(app: &mut App) -> Entity {
-    app.init_resource::<CrosstermWindowSettings>();
+pub(crate) fn setup_window(world: &mut World) -> Entity {
+    world.init_resource::<CrosstermWindowSettings>();
+    world.init_resource::<CrosstermInputSource>();
 
-    let window_settings = app.world.resource::<CrosstermWindowSettings>();
+    let window_settings = world.resource::<CrosstermWindowSettings>();
     let window = CrosstermWindow::new(window_settings);
 
     // Insert our window entity so that other parts of our app can use them
-    let bevy_window = app.world.spawn(window).insert(PrimaryWindow).id();
+    let bevy_window = world.spawn(window).insert(PrimaryWindow).id();
 
     // Publish to the app that a terminal window has been created
-    app.world.send_event(WindowCreated {
+    world.send_event(WindowCreated {
         window: bevy_window,
     });
 
@@ -135,8 +299,8 @@ fn setup_window(app: &mut App) -> Entity {
 }
 
 /// A single game update
-fn tick(app: &mut App, bevy_window: Entity) -> Result<(), AppExit> {
-    crossterm_events(&mut app.world, bevy_window);
+fn tick(app: &mut App, bevy_window: Entity, started: std::time::Instant) -> Result<(), AppExit> {
+    crossterm_events(&mut app.world, bevy_window, started.elapsed());
 
     // Yield execution to the rest of bevy and it's scheduler
     app.update();
@@ -155,66 +319,410 @@ fn tick(app: &mut App, bevy_window: Entity) -> Result<(), AppExit> {
     Ok(())
 }
 
-/// Check if any events are immediately available and if so, read them and republish
-fn crossterm_events(world: &mut bevy_ecs::world::World, bevy_window: Entity) {
-    while let Ok(available) = crossterm::event::poll(std::time::Duration::from_secs(0)) {
-        if available {
-            match crossterm::event::read().unwrap() {
-                // Republish keyboard events in bevy
-                crossterm::event::Event::Key(key_event) => {
-                    // If the key event is for C-c, submit a AppExit event so the application
-                    // can be killed
-                    use crossterm::event::{KeyCode, KeyModifiers};
-                    if key_event.code == KeyCode::Char('c')
-                        && key_event.modifiers.contains(KeyModifiers::CONTROL)
-                    {
-                        world.send_event(AppExit);
-                    }
+/// After an update which took `took`, longer than the `wait` between updates, runs the updates that
+/// were missed if the app's `FramePacing` asks for them
+fn catch_up(
+    app: &mut App,
+    bevy_window: Entity,
+    started: std::time::Instant,
+    took: std::time::Duration,
+    wait: std::time::Duration,
+) -> Result<(), AppExit> {
+    let pacing = app
+        .world
+        .resource::<CrosstermWindowSettings>()
+        .frame_pacing();
+    for _ in 1..pacing.updates(took, wait) {
+        tick(app, bevy_window, started)?;
+    }
+    Ok(())
+}
 
-                    world.send_event(CrosstermKeyEventWrapper(key_event));
+/// Check if any events are immediately available and if so, read them and republish
+fn crossterm_events(world: &mut World, bevy_window: Entity, elapsed: std::time::Duration) {
+    let settings = world.resource::<CrosstermWindowSettings>();
+    let (capacity, overflow) = (settings.input_capacity().get(), settings.input_overflow());
+    let handle_sigint = settings.handle_sigint();
+
+    // Only key and mouse events are limited, so they're queued apart from the window events. They're
+    // sent as different bevy events anyway, so they don't have to stay in order with each other
+    let mut input = std::collections::VecDeque::new();
+    let mut window_events = Vec::new();
+    let mut dropped = 0;
+    world.resource_scope(|world, mut source: Mut<CrosstermInputSource>| {
+        while let Some(event) = source.0.poll(elapsed) {
+            // If the key event is for C-c, submit a AppExit event so the application can be killed,
+            // unless the app wants it for itself.
+            // This is done straight away, so it still works if the key event is dropped
+            use crossterm::event::{KeyCode, KeyModifiers};
+            if let crossterm::event::Event::Key(key_event) = &event {
+                if handle_sigint
+                    && key_event.code == KeyCode::Char('c')
+                    && key_event.modifiers.contains(KeyModifiers::CONTROL)
+                {
+                    world.send_event(AppExit);
                 }
+            }
 
-                // Republish mouse events in bevy
-                crossterm::event::Event::Mouse(mouse_event) => {
-                    world.send_event(CrosstermMouseEventWrapper(mouse_event));
-                }
+            if !crate::input::is_limited(&event) {
+                window_events.push(event);
+            } else if overflow.push(&mut input, event, capacity) {
+                dropped += 1;
+            }
+        }
+    });
 
-                // Send a bevy window resized event if the terminal is resized, and also change the persisted window state
-                crossterm::event::Event::Resize(width, height) => {
-                    // Update the window resource and publish an event for the window being resized
-                    world.send_event(WindowResized {
-                        window: bevy_window,
-                        width: width as f32,
-                        height: height as f32,
-                    });
+    if dropped > 0 {
+        world.resource_mut::<RenderStats>().input_dropped += dropped;
+    }
+
+    for event in window_events.into_iter().chain(input) {
+        match event {
+            // Republish keyboard events in bevy
+            crossterm::event::Event::Key(key_event) => {
+                world.send_event(CrosstermKeyEventWrapper(key_event));
+            }
 
-                    let mut window_component =
-                        world.get_mut::<CrosstermWindow>(bevy_window).unwrap();
+            // Republish mouse events in bevy
+            crossterm::event::Event::Mouse(mouse_event) => {
+                world.send_event(CrosstermMouseEventWrapper(mouse_event));
+            }
 
+            // Send a bevy window resized event if the terminal is resized, and also change the persisted window state
+            crossterm::event::Event::Resize(width, height) => {
+                // Update the window resource and publish an event for the window being resized
+                world.send_event(WindowResized {
+                    window: bevy_window,
+                    width: width as f32,
+                    height: height as f32,
+                });
+
+                // Only write the new size if it's different, so `Changed<CrosstermWindow>` doesn't
+                // fire for terminals which send a resize without the size changing
+                let mut window_component = world.get_mut::<CrosstermWindow>(bevy_window).unwrap();
+                if window_component.width != width || window_component.height != height {
                     window_component.height = height;
                     window_component.width = width;
                 }
+            }
 
-                // Send a bevy window focused event
-                crossterm::event::Event::FocusGained => {
-                    world.send_event(bevy::window::WindowFocused {
-                        window: bevy_window,
-                        focused: true,
-                    });
-                }
-                crossterm::event::Event::FocusLost => {
-                    world.send_event(bevy::window::WindowFocused {
-                        window: bevy_window,
-                        focused: false,
-                    });
+            // Send a bevy window focused event
+            crossterm::event::Event::FocusGained => {
+                world.send_event(bevy::window::WindowFocused {
+                    window: bevy_window,
+                    focused: true,
+                });
+            }
+            crossterm::event::Event::FocusLost => {
+                world.send_event(bevy::window::WindowFocused {
+                    window: bevy_window,
+                    focused: false,
+                });
+            }
+
+            // Ignore bracketed paste. It's not well supported on windows.
+            // If it's ever required it should be easy to add a wrapper for it.
+            crossterm::event::Event::Paste(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::*;
+    use crate::input::ScriptedInput;
+    use crate::testing;
+
+    #[test]
+    fn exiting_resets_the_terminal_colors() {
+        let mut settings = CrosstermWindowSettings::default();
+        settings.on_shutdown(|term| term.write_all(b"bye"));
+        let mut window = testing::headless_window(&settings, 10, 4);
+
+        let mut term = Vec::new();
+        window.restore_terminal(&mut term);
+        let written = String::from_utf8(term).unwrap();
+        // Shutdown hooks go first, so they can't undo the reset
+        assert!(written.starts_with("bye"), "{written:?}");
+        for reset in [
+            "\x1b[?25h",
+            "\x1b[0m",
+            "\x1b]110\x1b\\",
+            "\x1b]111\x1b\\",
+            "\x1b]112\x1b\\",
+        ] {
+            assert!(written.contains(reset), "{reset:?} isn't in {written:?}");
+        }
+    }
+
+    #[test]
+    fn scripted_input_arrives_on_the_right_frames() {
+        use bevy::ecs::event::ManualEventReader;
+        use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+        use std::time::Duration;
+
+        let key = |c| Event::Key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+        let ms = Duration::from_millis;
+        let (mut app, _) = testing::app(10, 4);
+        app.insert_resource(CrosstermInputSource::new(ScriptedInput::new(vec![
+            (ms(0), key('a')),
+            (ms(100), key('b')),
+            (ms(100), key('c')),
+            (ms(300), key('d')),
+        ])));
+        let window = app
+            .world
+            .query_filtered::<Entity, With<PrimaryWindow>>()
+            .single(&app.world);
+
+        let mut reader = ManualEventReader::<CrosstermKeyEventWrapper>::default();
+        let mut frames = Vec::new();
+        for elapsed in [0, 50, 100, 200, 300, 400] {
+            crossterm_events(&mut app.world, window, ms(elapsed));
+            app.update();
+            let events = app.world.resource::<Events<CrosstermKeyEventWrapper>>();
+            let keys: Vec<_> = reader.read(events).map(|key| key.0.code).collect();
+            frames.push(keys);
+        }
+
+        let chars = |chars: &str| chars.chars().map(KeyCode::Char).collect::<Vec<_>>();
+        let expected = ["a", "", "bc", "", "d", ""].map(chars);
+        assert_eq!(frames, expected);
+    }
+
+    #[test]
+    fn floods_of_input_follow_the_overflow_policy() {
+        use crate::input::InputOverflow;
+        use bevy::ecs::event::ManualEventReader;
+        use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+        use std::time::Duration;
+
+        let key = |c| Event::Key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+        for (overflow, kept) in [
+            (InputOverflow::DropOldest, "cdd"),
+            (InputOverflow::DropNewest, "abc"),
+            // The second d only replaces the first, but c still pushes out the oldest key
+            (InputOverflow::Coalesce, "bcd"),
+        ] {
+            let (mut app, _) = testing::app_with(10, 4, |settings| {
+                settings
+                    .set_input_capacity(std::num::NonZeroUsize::new(3).unwrap())
+                    .set_input_overflow(overflow);
+            });
+            let events = vec![
+                key('a'),
+                key('b'),
+                Event::Resize(12, 5),
+                key('c'),
+                key('d'),
+                key('d'),
+            ];
+            let events = events.into_iter().map(|event| (Duration::ZERO, event));
+            app.insert_resource(CrosstermInputSource::new(ScriptedInput::new(
+                events.collect(),
+            )));
+            let window = app
+                .world
+                .query_filtered::<Entity, With<PrimaryWindow>>()
+                .single(&app.world);
+
+            let mut reader = ManualEventReader::<CrosstermKeyEventWrapper>::default();
+            crossterm_events(&mut app.world, window, Duration::ZERO);
+            app.update();
+            let events = app.world.resource::<Events<CrosstermKeyEventWrapper>>();
+            let keys: String = reader
+                .read(events)
+                .map(|key| match key.0.code {
+                    KeyCode::Char(c) => c,
+                    _ => unreachable!(),
+                })
+                .collect();
+            assert_eq!(keys, kept, "{overflow:?}");
+            assert_eq!(app.world.resource::<RenderStats>().input_dropped, 2);
+            // Resizes don't count towards the capacity, and are never dropped
+            let window = app.world.get::<CrosstermWindow>(window).unwrap();
+            assert_eq!((window.width, window.height), (12, 5), "{overflow:?}");
+        }
+    }
+
+    #[test]
+    fn late_updates_are_caught_up_or_dropped() {
+        use crate::FramePacing;
+        use std::time::Duration;
+
+        #[derive(Resource, Default)]
+        struct Updates(u32);
+
+        let ms = Duration::from_millis;
+        for (pacing, updates) in [
+            (FramePacing::DropFrames, 1),
+            // Two updates were missed while the slow one ran
+            (FramePacing::CatchUp { max_updates: 5 }, 3),
+            (FramePacing::CatchUp { max_updates: 1 }, 2),
+        ] {
+            let (mut app, _) = testing::app_with(10, 4, |settings| {
+                settings.set_frame_pacing(pacing);
+            });
+            app.init_resource::<Updates>()
+                .add_systems(Update, |mut updates: ResMut<Updates>| updates.0 += 1);
+            app.insert_resource(CrosstermInputSource::new(ScriptedInput::new(Vec::new())));
+            let window = app
+                .world
+                .query_filtered::<Entity, With<PrimaryWindow>>()
+                .single(&app.world);
+
+            let started = std::time::Instant::now();
+            tick(&mut app, window, started).unwrap();
+            catch_up(&mut app, window, started, ms(35), ms(10)).unwrap();
+            assert_eq!(app.world.resource::<Updates>().0, updates, "{pacing:?}");
+        }
+    }
+
+    #[test]
+    fn ctrl_c_exits_unless_the_app_handles_it() {
+        use bevy::ecs::event::ManualEventReader;
+        use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+
+        let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        for handle_sigint in [true, false] {
+            let (mut app, _) = testing::app_with(10, 4, |settings| {
+                settings.set_handle_sigint(handle_sigint);
+            });
+            let script = vec![(std::time::Duration::ZERO, Event::Key(ctrl_c))];
+            app.insert_resource(CrosstermInputSource::new(ScriptedInput::new(script)));
+            let window = app
+                .world
+                .query_filtered::<Entity, With<PrimaryWindow>>()
+                .single(&app.world);
+
+            let mut reader = ManualEventReader::<CrosstermKeyEventWrapper>::default();
+            let exited = tick(&mut app, window, std::time::Instant::now()).is_err();
+            assert_eq!(exited, handle_sigint);
+            let events = app.world.resource::<Events<CrosstermKeyEventWrapper>>();
+            let keys: Vec<_> = reader.read(events).map(|key| key.0).collect();
+            assert_eq!(keys, [ctrl_c], "handle_sigint: {handle_sigint}");
+        }
+    }
+
+    #[test]
+    fn window_only_changes_on_real_resizes() {
+        use crossterm::event::Event;
+        use std::time::Duration;
+
+        #[derive(Resource, Default)]
+        struct Changes(Vec<(u16, u16)>);
+
+        let (mut app, _) = testing::app(10, 4);
+        app.init_resource::<Changes>().add_systems(
+            Update,
+            |windows: Query<&CrosstermWindow, Changed<CrosstermWindow>>,
+             mut changes: ResMut<Changes>| {
+                for window in &windows {
+                    changes.0.push((window.width, window.height));
                 }
+            },
+        );
+        let window = app
+            .world
+            .query_filtered::<Entity, With<PrimaryWindow>>()
+            .single(&app.world);
+        // The window was just added, which counts as a change
+        app.update();
+        app.world.resource_mut::<Changes>().0.clear();
+
+        let resizes = [None, Some((10, 4)), Some((12, 5)), Some((12, 5)), None];
+        let mut changed = Vec::new();
+        for resize in resizes {
+            let events = resize.map(|(width, height)| Event::Resize(width, height));
+            let script = events.into_iter().map(|event| (Duration::ZERO, event));
+            app.insert_resource(CrosstermInputSource::new(ScriptedInput::new(
+                script.collect(),
+            )));
+            crossterm_events(&mut app.world, window, Duration::ZERO);
+            app.update();
+            changed.push(std::mem::take(&mut app.world.resource_mut::<Changes>().0));
+        }
+        assert_eq!(changed, [vec![], vec![], vec![(12, 5)], vec![], vec![]]);
+    }
 
-                // Ignore bracketed paste. It's not well supported on windows.
-                // If it's ever required it should be easy to add a wrapper for it.
-                crossterm::event::Event::Paste(_) => {}
+    #[test]
+    fn hooks_run_around_the_frames() {
+        let (mut app, output) = testing::app_with(10, 3, |settings| {
+            settings
+                .on_startup(|term| term.write_all(b"<start>"))
+                .on_shutdown(|term| term.write_all(b"<stop>"))
+                .set_exit_screen(ExitScreen::LeaveFrame);
+        });
+        let window = app
+            .world
+            .query_filtered::<Entity, With<PrimaryWindow>>()
+            .single(&app.world);
+
+        // Like the runner: set up the terminal, draw frames until the app exits, then shut it down
+        app.insert_resource(CrosstermInputSource::new(ScriptedInput::new(Vec::new())));
+        output.take();
+        prepare_terminal(app.world.resource(), &mut output.clone()).unwrap();
+        testing::spawn_sprite(&mut app, "frame", 0, 0);
+        let started = std::time::Instant::now();
+        assert!(tick(&mut app, window, started).is_ok());
+        app.world.send_event(AppExit);
+        assert!(tick(&mut app, window, started).is_err());
+        shut_down(&mut app.world, window, &mut output.clone()).unwrap();
+
+        let written = output.take();
+        let start = written.find("<start>").unwrap();
+        let (frames, teardown) = written.split_once("<stop>").unwrap();
+        assert!(frames[start..].contains("frame"), "{written:?}");
+        // The shutdown hook runs in the alternate screen, before the terminal is reset and the last
+        // frame is left on the main screen
+        let (reset, main_screen) = teardown.split_once("\x1b[?1049l").unwrap();
+        assert!(reset.ends_with("\x1b]112\x1b\\"), "{written:?}");
+        assert!(!reset.contains('H'), "{written:?}");
+        assert!(main_screen.contains("frame"), "{written:?}");
+        assert!(!main_screen.contains("<stop>"), "{written:?}");
+
+        // The window has been restored, so dropping it mustn't run the hooks again
+        let mut window = app.world.query::<&CrosstermWindow>();
+        assert!(window.single(&app.world).restored);
+    }
+
+    #[test]
+    fn each_exit_screen_leaves_the_terminal_as_asked() {
+        let (mut app, _) = testing::app(6, 3);
+        testing::spawn_sprite(&mut app, "hi", 0, 0);
+        testing::step(&mut app, 0.0);
+        let mut window = app.world.query::<&CrosstermWindow>();
+        let window = window.single(&app.world);
+        let frame = &app.world.resource::<RenderState>().previous;
+
+        let teardown = |exit_screen| {
+            let mut term = Vec::new();
+            leave_screen(exit_screen, window, frame, &mut term).unwrap();
+            String::from_utf8(term).unwrap()
+        };
+        let strip_styles = |text: &str| {
+            let mut plain = String::new();
+            let mut rest = text;
+            while let Some(start) = rest.find("\x1b[") {
+                plain.push_str(&rest[..start]);
+                let end = rest[start + 2..]
+                    .find(|c: char| c.is_ascii_alphabetic())
+                    .unwrap();
+                rest = &rest[start + 2 + end + 1..];
             }
-        } else {
-            break;
-        }
+            plain + rest
+        };
+
+        assert_eq!(teardown(ExitScreen::RestoreAlternate), "\x1b[?1049l");
+        assert_eq!(
+            teardown(ExitScreen::ClearScreen),
+            "\x1b[?1049l\x1b[2J\x1b[1;1H"
+        );
+        // The last frame is printed onto the main screen, without the blank rows under it
+        let left = teardown(ExitScreen::LeaveFrame);
+        let frame = left.strip_prefix("\x1b[?1049l").unwrap();
+        assert_eq!(strip_styles(frame), "hi    \r\n");
     }
 }
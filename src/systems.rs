@@ -1,586 +1,2700 @@
-use std::{convert::TryInto, io::Write};
+use std::io::Write;
 
+use crate::buffer::{Cell, CellBuffer};
 use crate::components::{self, Style};
-use crate::components::{
-    Colors, Position, PreviousEntityDetails, PreviousWindowColors, Sprite, StyleMap,
+use crate::components::{grapheme_width, Colors, Position, RenderState, Sprite, StyleMap};
+use crate::{
+    CoordinateSpace, CrosstermRenderError, CrosstermRng, CrosstermWindow, CrosstermWindowSettings,
+    CrosstermWriter, Cursor, CursorMode, DebugOverlay, DiffStrategy, FirstFrameRendered,
+    RenderStats,
 };
-use crate::{CrosstermWindow, Cursor};
 
 use bevy::prelude::*;
 use bevy::window::WindowResized;
-use bevy_asset::{AssetEvent, Assets, Handle};
+use bevy_asset::{Asset, AssetEvent, AssetId, AssetServer, Assets, Handle, LoadState};
 use crossterm::{queue, QueueableCommand};
+use unicode_width::UnicodeWidthStr;
 
-/// Records the initial position/size for every new entity
-pub(crate) fn add_previous_position(
-    mut entities_without_assets: Local<bevy::utils::HashSet<Entity>>,
-    mut previous_details: ResMut<PreviousEntityDetails>,
-    frames: Res<Assets<Sprite>>,
-    entities: Query<(Entity, &Position, &Handle<Sprite>), (Added<Position>, Added<Handle<Sprite>>)>,
-    all: Query<(&Position, &Handle<Sprite>)>,
+/// Whether the time driven systems should run
+pub(crate) fn not_paused(paused: Res<crate::CrosstermPaused>) -> bool {
+    !paused.is_paused()
+}
+
+/// Moves every entity with a velocity, carrying over any partial cells to the next frame
+pub(crate) fn apply_velocity(
+    time: Res<Time>,
+    mut entities: Query<(&mut Position, &mut components::Velocity)>,
 ) {
-    for (entity, pos, sprite) in entities.iter() {
-        if let Some(sprite) = frames.get(sprite) {
-            let prev_pos = components::PreviousPosition {
-                x: pos.x,
-                y: pos.y,
-                z: pos.z,
-            };
-            let prev_size = components::PreviousSize {
-                width: sprite.width() as u16,
-                height: sprite.graphemes().len() as u16,
-            };
-            previous_details.0.insert(entity, (prev_pos, prev_size));
-        } else {
-            // The asset hasn't loaded yet, so let's make a record of it for later
-            entities_without_assets.insert(entity);
+    let delta = time.delta_seconds();
+    for (mut pos, mut velocity) in &mut entities {
+        velocity.remainder_x += velocity.x * delta;
+        velocity.remainder_y += velocity.y * delta;
+
+        // Truncating rounds towards zero, so negative velocities behave the same as positive ones
+        let step_x = velocity.remainder_x.trunc();
+        let step_y = velocity.remainder_y.trunc();
+        velocity.remainder_x -= step_x;
+        velocity.remainder_y -= step_y;
+
+        // Only touch the position if it actually moved, otherwise it would be redrawn every frame
+        if step_x != 0.0 || step_y != 0.0 {
+            pos.x += step_x as i32;
+            pos.y += step_y as i32;
         }
     }
+}
 
-    // Bevy loads assets asynchronously so if we couldn't find an asset, we add it to this list and
-    // try again until it loads
-    let mut entities_to_remove = Vec::new();
-    for entity in &entities_without_assets {
-        let data = all.get(*entity);
-        if data.is_err() {
-            continue;
-        }
-        let (pos, sprite) = data.unwrap();
+/// Applies changes to the window's colors in `CrosstermWindowSettings` to the window
+pub(crate) fn sync_window_settings(
+    settings: Res<CrosstermWindowSettings>,
+    mut window: Query<&mut CrosstermWindow>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
 
-        if let Some(sprite) = frames.get(sprite) {
-            let prev_pos = components::PreviousPosition {
-                x: pos.x,
-                y: pos.y,
-                z: pos.z,
-            };
-            let prev_size = components::PreviousSize {
-                width: sprite.width() as u16,
-                height: sprite.graphemes().len() as u16,
-            };
-            previous_details.0.insert(*entity, (prev_pos, prev_size));
+    let mut window = window.single_mut();
+    if window.colors != settings.colors() {
+        // The renderer notices the new colors and redraws the whole window
+        window.colors = settings.colors();
+    }
+    if window.safe_area != settings.safe_area() {
+        window.safe_area = settings.safe_area();
+    }
+    if window.fixed_canvas != settings.fixed_canvas() {
+        window.fixed_canvas = settings.fixed_canvas();
+    }
+}
 
-            // We need to remove this entity now, but can't since it's container is borrowed.
-            entities_to_remove.push(*entity);
-        }
+/// Moves the cursor to wherever the `CursorMode` says it should be
+pub(crate) fn apply_cursor_mode(
+    mode: Res<CursorMode>,
+    mut cursor: ResMut<Cursor>,
+    window: Query<&CrosstermWindow>,
+    space: Option<Res<CoordinateSpace>>,
+    carets: Query<(&Position, Option<&components::Caret>)>,
+    hierarchy: Hierarchy,
+) {
+    let (x, y, hidden) = match *mode {
+        CursorMode::Hidden => (cursor.x, cursor.y, true),
+        CursorMode::Free { x, y } => (x, y, false),
+        CursorMode::AtCaret(entity) => match carets.get(entity) {
+            Ok((pos, caret)) => {
+                let caret = caret.copied().unwrap_or_default();
+                let space = space.as_deref().copied().unwrap_or_default();
+                let pos = global_position(entity, pos, &hierarchy);
+                let (x, y) = space.to_screen(pos.x, pos.y, window.single());
+                // The caret is an offset within the sprite, which always goes down the screen
+                (x + caret.x, y + caret.y, false)
+            }
+            // The entity is gone, so there's nowhere to show the cursor
+            Err(_) => (cursor.x, cursor.y, true),
+        },
+    };
+
+    // Only touch the cursor if it actually changes, otherwise it would be redrawn every frame
+    if cursor.x != x || cursor.y != y || cursor.hidden != hidden {
+        cursor.x = x;
+        cursor.y = y;
+        cursor.hidden = hidden;
     }
+}
 
-    for entity in &entities_to_remove {
-        entities_without_assets.remove(entity);
+/// Rounds a coordinate to the nearest multiple of `grid`
+fn snap_axis(pos: i32, grid: u16) -> i32 {
+    let grid = std::cmp::max(grid, 1) as i32;
+    let remainder = pos.rem_euclid(grid);
+    if remainder * 2 >= grid {
+        pos - remainder + grid
+    } else {
+        pos - remainder
     }
 }
 
-/// Records the current position for every known entity
-pub(crate) fn update_previous_position(
-    mut previous_details: ResMut<PreviousEntityDetails>,
-    frames: Res<Assets<Sprite>>,
-    mut positions: Query<(Entity, &Position, &Handle<Sprite>, &components::Visible)>,
-) {
-    for (entity, new_pos, sprite, _) in &mut positions {
-        if let Some(sprite) = frames.get(sprite) {
-            let prev_pos = components::PreviousPosition {
-                x: new_pos.x,
-                y: new_pos.y,
-                z: new_pos.z,
-            };
-            let prev_size = components::PreviousSize {
-                width: sprite.width() as u16,
-                height: sprite.graphemes().len() as u16,
-            };
+/// The position an entity is drawn at, on the nearest grid point if it snaps to a grid. The stored
+/// `Position` is left alone, so movement smaller than a grid cell still adds up
+fn snapped(pos: &Position, snap: Option<&components::GridSnap>) -> Position {
+    match snap {
+        Some(snap) => Position {
+            x: snap_axis(pos.x, snap.cell_w),
+            y: snap_axis(pos.y, snap.cell_h),
+            z: pos.z,
+        },
+        None => *pos,
+    }
+}
 
-            if let Some(value) = previous_details.0.get_mut(&entity) {
-                *value = (prev_pos, prev_size);
+/// Clamps a single axis so that at least `visible` cells of a sprite of `length` stay on a window of `window_length`
+fn clamp_axis(pos: i32, length: usize, window_length: i32, visible: Option<u16>) -> i32 {
+    let length = length as i32;
+    match visible {
+        None => {
+            if length > window_length {
+                0
+            } else {
+                pos.clamp(0, window_length - length)
             }
         }
+        Some(visible) => {
+            let visible = (visible as i32).min(length).min(window_length);
+            pos.clamp(visible - length, window_length - visible)
+        }
     }
 }
 
-fn changed_assets<T: bevy_asset::Asset>(
-    asset_events: &Res<Events<AssetEvent<T>>>,
-    assets: &Res<Assets<T>>,
-) -> (
-    bevy::utils::HashSet<AssetId<T>>,
-    bevy::utils::HashSet<AssetId<T>>,
+/// Moves entities which are clamped to the window back inside of it
+pub(crate) fn clamp_to_window(
+    window: Query<&CrosstermWindow>,
+    space: Option<Res<CoordinateSpace>>,
+    sprites: Res<Assets<Sprite>>,
+    entities: Query<(Entity, &Handle<Sprite>, &components::ClampToWindow)>,
+    mut positions: ParamSet<(Hierarchy, Query<&mut Position>)>,
 ) {
-    let mut created = bevy::utils::HashSet::default();
-    let mut changed = bevy::utils::HashSet::default();
-
-    for evt in asset_events.get_reader().read(asset_events) {
-        match evt {
-            AssetEvent::LoadedWithDependencies { id } => {
-                for cmp in assets.iter() {
-                    if &cmp.0 == id {
-                        created.insert(*id);
-                        break;
+    let window = window.single();
+    let space = space.as_deref().copied().unwrap_or_default();
+    let hierarchy = positions.p0();
+    let mut moves = Vec::new();
+    for (entity, sprite, clamp) in &entities {
+        let (Some(sprite), Ok((Some(pos), ..))) = (sprites.get(sprite), hierarchy.get(entity))
+        else {
+            continue;
+        };
+
+        let (columns, rows) = match clamp {
+            components::ClampToWindow::Fully => (None, None),
+            components::ClampToWindow::Partially { columns, rows } => (Some(*columns), Some(*rows)),
+        };
+        // Sprites are clamped to the part of the window outside the safe area
+        // Where the sprite is drawn is what's clamped, and the entity's own position moves by however
+        // far that had to go, so children are kept inside the window too
+        let area = window.drawable_area();
+        let global = global_position(entity, pos, &hierarchy);
+        let (x, y) = space.to_screen(global.x, global.y, window);
+        let x = area.min.x + clamp_axis(x - area.min.x, sprite.width(), area.width(), columns);
+        let y = area.min.y + clamp_axis(y - area.min.y, sprite.height(), area.height(), rows);
+        let (x, y) = space.from_screen(x, y, window);
+        let (x, y) = (pos.x + x - global.x, pos.y + y - global.y);
+
+        // Only write to the position if it actually changes, otherwise it would be redrawn every frame
+        if pos.x != x || pos.y != y {
+            moves.push((entity, x, y));
+        }
+    }
+
+    let mut positions = positions.p1();
+    for (entity, x, y) in moves {
+        if let Ok(mut pos) = positions.get_mut(entity) {
+            pos.x = x;
+            pos.y = y;
+        }
+    }
+}
+
+/// Blends the sprites of every running transition, and finishes any that have run their course
+pub(crate) fn update_transitions(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut rng: ResMut<CrosstermRng>,
+    mut sprites: ResMut<Assets<Sprite>>,
+    mut finished: EventWriter<components::TransitionFinished>,
+    mut transitions: Query<(Entity, &mut components::Transition, &mut Handle<Sprite>)>,
+) {
+    for (entity, mut transition, mut sprite_handle) in &mut transitions {
+        transition.timer.tick(time.delta());
+
+        if transition.timer.finished() {
+            *sprite_handle = transition.target.clone();
+            commands.entity(entity).remove::<components::Transition>();
+            finished.send(components::TransitionFinished { entity });
+            continue;
+        }
+
+        let source = sprites.get(&transition.source);
+        let target = sprites.get(&transition.target);
+        if source.is_none() || target.is_none() {
+            // The sprites haven't loaded yet, try again next frame
+            continue;
+        }
+        let (source, target) = (source.unwrap(), target.unwrap());
+
+        // The blended sprite must be big enough to contain both sprites
+        let width = std::cmp::max(source.width(), target.width());
+        let height = std::cmp::max(source.height(), target.height());
+
+        if transition.reveal_order.len() != width * height {
+            transition.reveal_order = (0..width * height).collect();
+            if transition.mask == components::DissolveMask::Random {
+                rng.shuffle(&mut transition.reveal_order);
+            }
+            transition.revealed = usize::MAX;
+        }
+
+        // Only rebuild the blended sprite if more cells were revealed, otherwise it would be redrawn every frame
+        let revealed = (transition.progress() * transition.reveal_order.len() as f32) as usize;
+        let needs_handle = transition.blended.as_ref() != Some(&*sprite_handle);
+        if revealed == transition.revealed && !needs_handle {
+            continue;
+        }
+        transition.revealed = revealed;
+
+        let mut is_revealed = vec![false; width * height];
+        for idx in &transition.reveal_order[..revealed] {
+            is_revealed[*idx] = true;
+        }
+
+        let mut data = String::new();
+        for y in 0..height {
+            if y > 0 {
+                data.push('\n');
+            }
+            let mut x = 0;
+            while x < width {
+                let from = if is_revealed[y * width + x] {
+                    target
+                } else {
+                    source
+                };
+                // A wide grapheme covers the next column too, so that column comes from the same sprite
+                match from.grapheme_at(x, y) {
+                    Some(grapheme) => {
+                        data.push_str(grapheme);
+                        x += grapheme_width(grapheme);
+                    }
+                    None => {
+                        data.push(' ');
+                        x += 1;
                     }
                 }
             }
-            AssetEvent::Modified { id } => {
-                for cmp in assets.iter() {
-                    if &cmp.0 == id {
-                        changed.insert(*id);
-                        break;
-                    }
+        }
+
+        match &transition.blended {
+            Some(blended) => {
+                if let Some(blended) = sprites.get_mut(blended) {
+                    blended.update(data);
                 }
             }
-            _ => {}
+            None => transition.blended = Some(sprites.add(Sprite::new(data))),
+        }
+
+        if needs_handle {
+            *sprite_handle = transition.blended.clone().unwrap();
         }
     }
+}
+
+/// Turns the visible part of each streaming sprite into a sprite, whenever it scrolls
+pub(crate) fn update_streaming_sprites(
+    mut sprites: ResMut<Assets<Sprite>>,
+    mut streams: Query<(&mut components::StreamingSprite, &mut Handle<Sprite>)>,
+) {
+    for (mut stream, mut sprite_handle) in &mut streams {
+        let view = Some((stream.scroll(), stream.visible_lines(), stream.tab_width()));
+        let needs_handle = stream.rendered.as_ref() != Some(&*sprite_handle);
+        if stream.shown == view && !needs_handle {
+            continue;
+        }
+
+        let data = stream.visible_text();
+        // The scroll position may have been moved back from past the end
+        stream.shown = Some((stream.scroll(), stream.visible_lines(), stream.tab_width()));
+
+        match &stream.rendered {
+            Some(rendered) => {
+                if let Some(rendered) = sprites.get_mut(rendered) {
+                    rendered.update(data);
+                }
+            }
+            None => stream.rendered = Some(sprites.add(Sprite::new(data))),
+        }
 
-    (created, changed)
+        if needs_handle {
+            *sprite_handle = stream.rendered.clone().unwrap();
+        }
+    }
 }
 
-/// Calculates which entities need to be redrawn
-pub(crate) fn calculate_entities_to_redraw(
-    mut prev_colors: ResMut<PreviousWindowColors>,
-    mut entities: ResMut<components::EntitiesToRedraw>,
-    previous_details: Res<PreviousEntityDetails>,
-    window: Query<&CrosstermWindow>,
-    resize_events: Res<Events<WindowResized>>,
-    sprites: Res<Assets<Sprite>>,
-    stylemaps: Res<Assets<StyleMap>>,
-    sprite_asset_events: Res<Events<AssetEvent<Sprite>>>,
-    stylemap_asset_events: Res<Events<AssetEvent<StyleMap>>>,
-    all: Query<(
+pub(crate) fn update_fades(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut rng: ResMut<CrosstermRng>,
+    mut sprites: ResMut<Assets<Sprite>>,
+    mut stylemaps: ResMut<Assets<StyleMap>>,
+    mut finished: EventWriter<components::FadeFinished>,
+    mut fades: Query<(
         Entity,
-        &Handle<StyleMap>,
-        &Handle<Sprite>,
-        &Position,
-        &components::Visible,
+        &mut components::FadeOut,
+        &mut Handle<Sprite>,
+        &mut Handle<StyleMap>,
     )>,
-    mut removed: RemovedComponents<Handle<Sprite>>,
-    changed: Query<
-        Entity,
-        Or<(
-            Changed<Position>,
-            Changed<Handle<StyleMap>>,
-            Changed<components::Visible>,
-            Changed<Handle<Sprite>>,
-        )>,
-    >,
-    added: Query<
-        Entity,
-        (
-            Or<(
-                Added<Position>,
-                Added<Handle<StyleMap>>,
-                Added<components::Visible>,
-                Added<Handle<Sprite>>,
-            )>,
-            With<Position>,
-            With<Handle<StyleMap>>,
-            With<components::Visible>,
-            With<Handle<Sprite>>,
-        ),
-    >,
 ) {
-    let window = window.single();
-    entities.full_redraw = false;
-    entities.to_draw.clear();
-    entities.to_clear.clear();
-
-    let mut draw_set = bevy::utils::HashSet::default();
-
-    // If a resize happened the whole screen is invalidated
-    if !resize_events.get_reader().is_empty(&resize_events) || window.colors != prev_colors.0 {
-        // We need a full redraw, so flag a full update and bail early
-        // No need to do fancy update calculations
-        entities.full_redraw = true;
-        prev_colors.0 = window.colors;
-        // Mark all entities as needed to redraw
-        for (entity, _, _, pos, _) in all.iter() {
-            entities
-                .to_draw
-                .push(components::EntityDepth { entity, z: pos.z });
-        }
-        entities.to_draw.sort_by_key(|item| item.z);
-        return;
-    }
+    for (entity, mut fade, mut sprite_handle, mut stylemap_handle) in &mut fades {
+        if fade.source.is_none() {
+            fade.source = Some((sprite_handle.clone(), stylemap_handle.clone()));
+        }
+        fade.timer.tick(time.delta());
+
+        let (source_sprite, source_stylemap) = fade.source.clone().unwrap();
+        let source = match sprites.get(&source_sprite) {
+            // The sprite hasn't loaded yet, try again next frame
+            None => continue,
+            Some(source) => source,
+        };
+        let (width, height) = (source.width(), source.height());
+
+        if fade.fade_order.len() != width * height {
+            fade.fade_order = (0..width * height).collect();
+            if fade.mask == components::DissolveMask::Random {
+                rng.shuffle(&mut fade.fade_order);
+            }
+            fade.revealed = usize::MAX;
+        }
+
+        // Only rebuild the faded sprite if more cells faded, otherwise it would be redrawn every frame
+        let revealed = if fade.timer.finished() {
+            fade.fade_order.len()
+        } else {
+            (fade.progress() * fade.fade_order.len() as f32) as usize
+        };
+        let needs_handle = !fade.faded.as_ref().is_some_and(|(sprite, stylemap)| {
+            *sprite == *sprite_handle && *stylemap == *stylemap_handle
+        });
+        if revealed != fade.revealed || needs_handle {
+            fade.revealed = revealed;
 
-    // Now check to see which entities actually changed since it's not a full update
+            let mut is_faded = vec![false; width * height];
+            for idx in &fade.fade_order[..revealed] {
+                is_faded[*idx] = true;
+            }
 
-    // Collect all the changed assets
-    let (created_sprite_assets, changed_sprite_assets) =
-        changed_assets(&sprite_asset_events, &sprites);
-    let (created_stylemap_assets, changed_stylemap_assets) =
-        changed_assets(&stylemap_asset_events, &stylemaps);
+            // Missing stylemaps, like the default one, are treated as having no styles
+            let empty_stylemap = StyleMap::default();
+            let source_stylemap = stylemaps.get(&source_stylemap).unwrap_or(&empty_stylemap);
+            let faded_style = match fade.color {
+                Some(color) => Style::with_bg(color),
+                None => Style::default(),
+            };
 
-    // Collect all the entities that changed this update, either because their asset did,
-    // or their components did
-    for (entity, style_hnd, sprite_hnd, _, _) in all.iter() {
-        if changed_sprite_assets.contains(&sprite_hnd.id())
-            || changed_stylemap_assets.contains(&style_hnd.id())
-        {
-            entities.to_clear.insert(entity);
-            draw_set.insert(entity);
+            let mut data = String::new();
+            let mut map = Vec::with_capacity(height);
+            for y in 0..height {
+                if y > 0 {
+                    data.push('\n');
+                }
+                let mut row = Vec::with_capacity(width);
+                let mut x = 0;
+                while x < width {
+                    let (grapheme, columns) = match source.grapheme_at(x, y) {
+                        Some(grapheme) => (grapheme, grapheme_width(grapheme)),
+                        None => (" ", 1),
+                    };
+                    // A wide grapheme fades as soon as either of its halves does
+                    let columns = std::cmp::min(columns, width - x);
+                    if (x..x + columns).any(|x| is_faded[y * width + x]) {
+                        data.push_str(&" ".repeat(columns));
+                        row.extend(std::iter::repeat_n(faded_style, columns));
+                    } else {
+                        data.push_str(grapheme);
+                        row.extend((x..x + columns).map(|x| source_stylemap.style_for(x, y)));
+                    }
+                    x += columns;
+                }
+                map.push(row);
+            }
+            let stylemap = StyleMap::new(source_stylemap.style, map);
+
+            match &fade.faded {
+                Some((faded_sprite, faded_stylemap)) => {
+                    if let Some(faded_sprite) = sprites.get_mut(faded_sprite) {
+                        faded_sprite.update(data);
+                    }
+                    if let Some(faded_stylemap) = stylemaps.get_mut(faded_stylemap) {
+                        *faded_stylemap = stylemap;
+                    }
+                }
+                None => {
+                    fade.faded = Some((sprites.add(Sprite::new(data)), stylemaps.add(stylemap)));
+                }
+            }
+
+            if needs_handle {
+                let (faded_sprite, faded_stylemap) = fade.faded.clone().unwrap();
+                *sprite_handle = faded_sprite;
+                *stylemap_handle = faded_stylemap;
+            }
         }
 
-        if created_sprite_assets.contains(&sprite_hnd.id())
-            || created_stylemap_assets.contains(&style_hnd.id())
-        {
-            draw_set.insert(entity);
+        if fade.timer.finished() {
+            if fade.despawn {
+                commands.entity(entity).despawn_recursive();
+            } else {
+                commands.entity(entity).remove::<components::FadeOut>();
+            }
+            finished.send(components::FadeFinished { entity });
         }
     }
+}
 
-    for entity in changed.iter() {
-        entities.to_clear.insert(entity);
-        draw_set.insert(entity);
+/// Shows and hides soft blinking entities
+pub(crate) fn update_soft_blinks(
+    time: Res<Time>,
+    mut blinks: Query<(&components::SoftBlink, &mut components::Visible)>,
+) {
+    for (blink, mut visible) in &mut blinks {
+        let is_visible = blink.is_visible_at(time.elapsed());
+        // Only write to the visibility if it actually changes, otherwise it would be redrawn every frame
+        if visible.is_visible != is_visible {
+            visible.is_visible = is_visible;
+        }
     }
+}
 
-    draw_set.extend(added.iter());
+pub(crate) fn update_typewriters(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut keys: EventReader<crate::CrosstermKeyEventWrapper>,
+    mut sprites: ResMut<Assets<Sprite>>,
+    mut done: EventWriter<components::TypewriterDone>,
+    mut typewriters: Query<(Entity, &mut components::Typewriter, &mut Handle<Sprite>)>,
+) {
+    let pressed: Vec<crossterm::event::KeyCode> = keys
+        .read()
+        .filter(|event| event.0.kind != crossterm::event::KeyEventKind::Release)
+        .map(|event| event.0.code)
+        .collect();
+
+    for (entity, mut typewriter, mut sprite_handle) in &mut typewriters {
+        typewriter.elapsed += time.delta_seconds();
+        if typewriter
+            .skip_key
+            .is_some_and(|key| pressed.contains(&key))
+        {
+            typewriter.skip();
+        }
 
-    // Find all entities that either became invisible, or changed their size or moved. (cleared is good enough for now)
-    // Figure out what their previous bounding box is and query all current positions to see what sprites are under it
-    // Add the collided entities to draw_set
-    let mut collided_entities = Vec::new();
-    let mut bboxes = Vec::new();
-    for (entity, _, sprite, pos, _) in all.iter() {
-        let sprite_data = sprites.get(sprite);
-        if sprite_data.is_none() {
+        let target = match sprites.get(&typewriter.target) {
+            // The sprite hasn't loaded yet, try again next frame
+            None => continue,
+            Some(target) => target,
+        };
+
+        let total: usize = target.graphemes().iter().map(Vec::len).sum();
+        let typed = typewriter.typed_chars();
+        if typed >= total {
+            *sprite_handle = typewriter.target.clone();
+            commands.entity(entity).remove::<components::Typewriter>();
+            done.send(components::TypewriterDone { entity });
             continue;
         }
-        let sprite = sprite_data.unwrap();
-        let bb = broccoli::bbox(
-            broccoli::rect(
-                pos.x,
-                pos.x + sprite.width() as i32,
-                pos.y,
-                pos.y + sprite.data().len() as i32,
-            ),
-            entity,
-        );
-        bboxes.push(bb);
-    }
 
-    let broccoli = broccoli::new(&mut bboxes);
-    for ent in changed.iter() {
-        let prev_data = previous_details.0.get(&ent);
-        if prev_data.is_none() {
+        // Only rebuild the typed sprite if more was typed, otherwise it would be redrawn every frame
+        let needs_handle = typewriter.typed.as_ref() != Some(&*sprite_handle);
+        if typed == typewriter.revealed && !needs_handle {
             continue;
         }
-        let (prev_pos, prev_size) = prev_data.unwrap();
-        let blank_bb = broccoli::rect(
-            prev_pos.x,
-            prev_pos.x + prev_size.width as i32,
-            prev_pos.y,
-            prev_pos.y + prev_size.height as i32,
-        );
-        // dbg!("checking for collision", ent, prev_pos);
-        broccoli.for_all_intersect_rect(&blank_bb, |bb| {
-            if ent == bb.inner {
-                return;
+        typewriter.revealed = typed;
+
+        // Graphemes which haven't been typed yet are replaced with spaces, so the sprite doesn't
+        // change shape as it's typed
+        let mut data = String::new();
+        let mut remaining = typed;
+        for y in 0..target.height() {
+            if y > 0 {
+                data.push('\n');
             }
-            // dbg!("Found Entity: ", bb.inner);
-            if !draw_set.contains(&bb.inner) {
-                draw_set.insert(bb.inner);
-                collided_entities.push(bb.inner);
+            for (_, grapheme) in target.glyphs(y) {
+                if remaining > 0 {
+                    data.push_str(grapheme);
+                    remaining -= 1;
+                } else {
+                    data.push_str(&" ".repeat(grapheme_width(grapheme)));
+                }
             }
-        });
-    }
+        }
 
-    let mut cur_index = 0;
-    while cur_index < collided_entities.len() {
-        let ent = collided_entities[cur_index];
-        cur_index += 1;
+        match &typewriter.typed {
+            Some(typed) => {
+                if let Some(typed) = sprites.get_mut(typed) {
+                    typed.update(data);
+                }
+            }
+            None => typewriter.typed = Some(sprites.add(Sprite::new(data))),
+        }
 
-        let prev_data = previous_details.0.get(&ent);
-        if prev_data.is_none() {
-            continue;
+        if needs_handle {
+            *sprite_handle = typewriter.typed.clone().unwrap();
         }
-        let (prev_pos, prev_size) = prev_data.unwrap();
-        let blank_bb = broccoli::rect(
-            prev_pos.x,
-            prev_pos.x + prev_size.width as i32,
-            prev_pos.y,
-            prev_pos.y + prev_size.height as i32,
-        );
-        // dbg!("checking for collision", ent, prev_pos);
-        broccoli.for_all_intersect_rect(&blank_bb, |bb| {
-            if ent == bb.inner {
-                return;
-            }
-            // dbg!("Found Entity: ", bb.inner);
-            if !draw_set.contains(&bb.inner) {
-                draw_set.insert(bb.inner);
-                collided_entities.push(bb.inner);
-            }
-        });
     }
+}
+
+/// Crops the frames of every animated sprite once its sheet is available, and moves each one on to
+/// the frame it should be showing
+pub(crate) fn update_animated_sprites(
+    time: Res<Time>,
+    mut sprite_events: EventReader<AssetEvent<Sprite>>,
+    mut stylemap_events: EventReader<AssetEvent<StyleMap>>,
+    mut sprites: ResMut<Assets<Sprite>>,
+    mut stylemaps: ResMut<Assets<StyleMap>>,
+    mut animations: Query<(
+        &mut components::AnimatedSprite,
+        &mut Handle<Sprite>,
+        &mut Handle<StyleMap>,
+    )>,
+) {
+    let modified_sprites: bevy::utils::HashSet<AssetId<Sprite>> = sprite_events
+        .read()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect();
+    let modified_stylemaps: bevy::utils::HashSet<AssetId<StyleMap>> = stylemap_events
+        .read()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect();
+
+    for (mut animation, mut sprite_handle, mut stylemap_handle) in &mut animations {
+        if animation.frame_rects.is_empty() {
+            continue;
+        }
 
-    entities.to_clear.extend(removed.read());
+        // Crop the frames again if the sheet was hot reloaded, or if the frames were changed
+        let stale = modified_sprites.contains(&animation.sheet.id())
+            || animation
+                .stylemap
+                .as_ref()
+                .is_some_and(|stylemap| modified_stylemaps.contains(&stylemap.id()))
+            || animation.frames.len() != animation.frame_rects.len();
+        if stale {
+            let sheet = match sprites.get(&animation.sheet) {
+                // The sheet hasn't loaded yet, try again next frame
+                None => continue,
+                Some(sheet) => sheet,
+            };
+            let sheet_stylemap = match &animation.stylemap {
+                Some(handle) => match stylemaps.get(handle) {
+                    None => continue,
+                    Some(stylemap) => Some(stylemap),
+                },
+                None => None,
+            };
 
-    for ent_to_draw in &draw_set {
-        let (entity, _, _, pos, _) = all.get(*ent_to_draw).unwrap();
-        entities
-            .to_draw
-            .push(components::EntityDepth { entity, z: pos.z });
+            let cropped: Vec<(Sprite, Option<StyleMap>)> = animation
+                .frame_rects
+                .iter()
+                .map(|rect| {
+                    (
+                        sheet.crop(*rect),
+                        sheet_stylemap.map(|stylemap| stylemap.crop(*rect)),
+                    )
+                })
+                .collect();
+            let frames = cropped
+                .into_iter()
+                .map(|(sprite, stylemap)| {
+                    (
+                        sprites.add(sprite),
+                        stylemap.map(|stylemap| stylemaps.add(stylemap)),
+                    )
+                })
+                .collect();
+            animation.frames = frames;
+        }
+
+        animation.timer.tick(time.delta());
+        let advanced = animation.timer.times_finished_this_tick() as usize;
+        let frame = (animation.frame + advanced) % animation.frames.len();
+        animation.frame = frame;
+
+        let (sprite, stylemap) = &animation.frames[frame];
+        if *sprite != *sprite_handle {
+            *sprite_handle = sprite.clone();
+        }
+        if let Some(stylemap) = stylemap {
+            if *stylemap != *stylemap_handle {
+                *stylemap_handle = stylemap.clone();
+            }
+        }
     }
-    entities.to_draw.sort_by_key(|item| item.z);
 }
 
-/// Helper function for `draw_entity` which determines whether the style on the terminal should be
-/// changed
-fn change_style_if_needed(
-    term: &mut std::io::StdoutLock,
-    previous_style: &mut Style,
-    current_style: &Style,
-) -> Result<(), Box<dyn std::error::Error>> {
-    if current_style.attributes != previous_style.attributes {
-        term.queue(crossterm::style::SetAttributes(current_style.attributes))?;
-        previous_style.attributes = current_style.attributes;
-    }
-    if current_style.colors != previous_style.colors {
-        term.queue(crossterm::style::SetColors(
-            current_style.colors.to_crossterm(),
-        ))?;
-        previous_style.colors = current_style.colors;
+/// How many frames after a sprite or stylemap is hot reloaded a mismatch between them is put down
+/// to the other one not having been reloaded yet
+const RELOAD_GRACE_FRAMES: u64 = 20;
+
+/// Entities which lost a component that changes how, or whether, they're drawn
+#[derive(bevy::ecs::system::SystemParam)]
+pub(crate) struct RemovedRenderComponents<'w, 's> {
+    sprites: RemovedComponents<'w, 's, Handle<Sprite>>,
+    stylemaps: RemovedComponents<'w, 's, Handle<StyleMap>>,
+    positions: RemovedComponents<'w, 's, Position>,
+    visibles: RemovedComponents<'w, 's, components::Visible>,
+    priorities: RemovedComponents<'w, 's, components::RenderPriority>,
+    overflows: RemovedComponents<'w, 's, components::Overflow>,
+    parents: RemovedComponents<'w, 's, Parent>,
+    links: RemovedComponents<'w, 's, components::Hyperlinks>,
+    snaps: RemovedComponents<'w, 's, components::GridSnap>,
+}
+
+impl RemovedRenderComponents<'_, '_> {
+    fn read(&mut self) -> Vec<Entity> {
+        self.sprites
+            .read()
+            .chain(self.stylemaps.read())
+            .chain(self.positions.read())
+            .chain(self.visibles.read())
+            .chain(self.priorities.read())
+            .chain(self.overflows.read())
+            .chain(self.parents.read())
+            .chain(self.links.read())
+            .chain(self.snaps.read())
+            .collect()
     }
-    Ok(())
 }
 
-fn draw_entity(
-    entity: Entity,
-    term: &mut std::io::StdoutLock,
-    window: &CrosstermWindow,
-    sprites: &Res<Assets<Sprite>>,
-    stylemaps: &Res<Assets<StyleMap>>,
-    all: &Query<(
-        Entity,
-        &Position,
-        &Handle<StyleMap>,
-        &components::Visible,
-        &Handle<Sprite>,
+/// Entities with a component that changes how, or whether, they're drawn that changed
+type ChangedRenderComponents<'w, 's> = Query<
+    'w,
+    's,
+    (Has<components::Static>, Has<Children>),
+    Or<(
+        Changed<Position>,
+        Changed<Handle<StyleMap>>,
+        Changed<components::Visible>,
+        Changed<Handle<Sprite>>,
+        Added<components::Static>,
+        Changed<components::RenderPriority>,
+        Changed<components::Overflow>,
+        Changed<Parent>,
+        Changed<components::Hyperlinks>,
+        Changed<components::GridSnap>,
     )>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let entity_data = all.get(entity);
-    if entity_data.is_err() {
-        return Ok(());
+>;
+
+/// Figures out what needs to be redrawn this frame
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn calculate_redraw(
+    mut state: ResMut<RenderState>,
+    window: Query<&CrosstermWindow>,
+    mut resize_events: EventReader<WindowResized>,
+    mut sprite_events: EventReader<AssetEvent<Sprite>>,
+    mut stylemap_events: EventReader<AssetEvent<StyleMap>>,
+    all: Query<(&Handle<Sprite>, &Handle<StyleMap>, Has<components::Static>)>,
+    changed: ChangedRenderComponents,
+    mut removed: RemovedRenderComponents,
+    mut removed_statics: RemovedComponents<components::Static>,
+    settings: Res<CrosstermWindowSettings>,
+    space: Option<Res<CoordinateSpace>>,
+    overlay: Option<Res<DebugOverlay>>,
+) {
+    let window = window.single();
+
+    // The overlay is drawn over the frame, so the frame has to be drawn again to take it off
+    let overlay = overlay
+        .as_deref()
+        .copied()
+        .filter(|overlay| overlay.enabled);
+    if state.debug_overlay != overlay {
+        state.debug_overlay = overlay;
+        state.redraw = true;
     }
-    let (_, pos, style, draw, sprite) = entity_data.unwrap();
 
-    // If the entity isn't visible, skip it
-    if !draw.is_visible {
-        return Ok(());
+    // Every sprite moves when the coordinate space does
+    let space = space.as_deref().copied();
+    if state.coordinate_space != space {
+        state.coordinate_space = space;
+        state.redraw = true;
+        state.rebuild_background = true;
     }
 
-    let sprite = sprites.get(sprite);
-    if sprite.is_none() {
-        // The sprite asset hasn't loaded yet, this isn't a problem
-        return Ok(());
+    // The center of the window moves with the safe area and the canvas, and the space around them
+    // has to be cleared
+    let window_area = (window.safe_area(), window.fixed_canvas());
+    if state.window_area != Some(window_area) {
+        state.window_area = Some(window_area);
+        state.redraw = true;
+        state.rebuild_background = true;
     }
-    let sprite = sprite.unwrap();
 
-    // If the entity's not on the screen, skip it
-    if pos.y >= window.height.into()
-        || pos.y + sprite.height() as i32 <= 0
-        || pos.x >= window.width.into()
-        || pos.x + sprite.width() as i32 <= 0
+    // Every sprite which doesn't have its own overflow uses the default one
+    if state.overflow != Some(settings.overflow()) {
+        state.overflow = Some(settings.overflow());
+        state.redraw = true;
+        state.rebuild_background = true;
+    }
+
+    // If a resize happened or the window changed colors, the whole screen is invalidated
+    let resized = resize_events.read().count() > 0;
+    if resized
+        || state.colors != Some(window.colors)
+        || state.previous.width() != window.width()
+        || state.previous.height() != window.height()
     {
-        return Ok(());
+        state.colors = Some(window.colors);
+        // The window colors are baked into the laid out sprites
+        state.rasters.clear();
+        state.full_redraw = true;
+        state.redraw = true;
+        state.rebuild_background = true;
     }
 
-    let stylemap = stylemaps.get(style);
-    if stylemap.is_none() {
-        // The stylemap asset hasn't loaded yet, this isn't a problem
-        return Ok(());
+    // Any entity which changed needs the frame to be recomposed, and if it's static the cached
+    // background needs to be rebuilt as well
+    for (is_static, has_children) in changed.iter() {
+        state.redraw = true;
+        // Children move with their parent, and any of them could be static
+        state.rebuild_background |=
+            is_static || (has_children && !state.static_entities.is_empty());
     }
-    let stylemap = stylemap.unwrap();
-    let sprite_colors = stylemap.style.colors.with_default(window.colors);
 
-    queue!(
-        term,
-        crossterm::style::SetAttribute(crossterm::style::Attribute::Reset),
-        crossterm::style::SetAttributes(stylemap.style.attributes),
-        crossterm::style::SetColors(sprite_colors.to_crossterm())
-    )?;
+    // Entities that were despawned, or lost a component they need to be drawn, have to be removed from
+    // the screen. Losing a priority, overflow or parent can change how the sprite is drawn too
+    for entity in &removed.read() {
+        state.redraw = true;
+        state.rebuild_background |= state.static_entities.contains(entity);
+    }
+    if removed_statics.read().count() > 0 {
+        // These sprites moved from the background to the foreground
+        state.redraw = true;
+        state.rebuild_background = true;
+    }
 
-    let mut previous_style = stylemap.style;
+    // Reloads which are old enough that a mismatch is no longer excused. Redraw so that any
+    // entities which were held back are drawn, or reported
+    state.frame += 1;
+    let frame = state.frame;
+    let reloads = state.reloads.len();
+    state
+        .reloads
+        .retain(|_, reloaded| frame - *reloaded < RELOAD_GRACE_FRAMES);
+    if state.reloads.len() != reloads {
+        state.redraw = true;
+        state.rebuild_background = true;
+    }
 
-    for (line_num, line) in sprite.graphemes().iter().enumerate() {
-        let line_offset: i32 = line_num.try_into()?;
+    // Collect all the assets which were loaded or changed, and redraw the entities using them. Events
+    // arrive the update after the asset was added, by when it may already have been laid out, so only
+    // assets which changed or went away have to be laid out again
+    let mut changed_sprites = bevy::utils::HashSet::default();
+    let mut stale_sprites = bevy::utils::HashSet::default();
+    for event in sprite_events.read() {
+        track_reloads(&mut state, event, frame);
+        if let AssetEvent::Modified { id } | AssetEvent::Removed { id } = event {
+            stale_sprites.insert(*id);
+        }
+        let (AssetEvent::Added { id }
+        | AssetEvent::Modified { id }
+        | AssetEvent::LoadedWithDependencies { id }
+        | AssetEvent::Removed { id }) = event;
+        changed_sprites.insert(*id);
+    }
+    let mut changed_stylemaps = bevy::utils::HashSet::default();
+    let mut stale_stylemaps = bevy::utils::HashSet::default();
+    for event in stylemap_events.read() {
+        track_reloads(&mut state, event, frame);
+        if let AssetEvent::Modified { id } | AssetEvent::Removed { id } = event {
+            stale_stylemaps.insert(*id);
+        }
+        let (AssetEvent::Added { id }
+        | AssetEvent::Modified { id }
+        | AssetEvent::LoadedWithDependencies { id }
+        | AssetEvent::Removed { id }) = event;
+        changed_stylemaps.insert(*id);
+    }
+    if !changed_sprites.is_empty() || !changed_stylemaps.is_empty() {
+        state.rasters.retain(|(sprite, stylemap), _| {
+            !stale_sprites.contains(sprite) && !stale_stylemaps.contains(stylemap)
+        });
+        for (sprite, stylemap, is_static) in all.iter() {
+            if changed_sprites.contains(&sprite.id()) || changed_stylemaps.contains(&stylemap.id())
+            {
+                state.redraw = true;
+                state.rebuild_background |= is_static;
+            }
+        }
+    }
+}
+
+/// Keeps track of which assets the asset server has loaded, so that loading one again is known to be
+/// a hot reload. Changes made with `Assets::get_mut` aren't reloads, since they can't be half done.
+fn track_reloads<A: Asset>(state: &mut RenderState, event: &AssetEvent<A>, frame: u64) {
+    match event {
+        AssetEvent::LoadedWithDependencies { id } => {
+            if !state.loaded.insert(id.untyped()) {
+                state.reloads.insert(id.untyped(), frame);
+            }
+        }
+        AssetEvent::Removed { id } => {
+            state.loaded.remove(&id.untyped());
+        }
+        AssetEvent::Added { .. } | AssetEvent::Modified { .. } => {}
+    }
+}
+
+/// Draws a single sprite into the part of the buffer inside `area`
+fn draw_entity(
+    buffer: &mut CellBuffer,
+    area: IRect,
+    pos: &Position,
+    raster: &Raster,
+    links: Option<&components::Hyperlinks>,
+    visible: &components::Visible,
+    overflow: components::Overflow,
+) {
+    // If the entity isn't visible, skip it
+    if !visible.is_visible || area.is_empty() {
+        return;
+    }
+
+    // Sprites are clipped, hidden or wrapped around at the edges of the drawable area, not the
+    // terminal's, so nothing ends up in the safe area or the letterbox
+    let wrap = overflow == components::Overflow::Wrap;
+    if overflow == components::Overflow::Hidden
+        && !(pos.x >= area.min.x
+            && pos.y >= area.min.y
+            && pos.x + raster.width as i32 <= area.max.x
+            && pos.y + raster.height as i32 <= area.max.y)
+    {
+        return;
+    }
+
+    // Only look at the part of the sprite which is actually inside the area, unless it wraps around
+    let (x_start, x_end) = if wrap {
+        (0, raster.width)
+    } else {
+        let x_start = std::cmp::max(0, area.min.x - pos.x) as usize;
+        let x_end = std::cmp::max(0, area.max.x - pos.x) as usize;
+        (x_start, std::cmp::min(x_end, raster.width))
+    };
+
+    for line_num in 0..raster.height {
+        let mut y = pos.y + line_num as i32;
+        if wrap {
+            y = area.min.y + (y - area.min.y).rem_euclid(area.height());
+        }
 
         // Check to see if this line is on the screen, if not skip it
-        if pos.y + line_offset < 0 {
+        if y < area.min.y {
             continue;
         }
 
         // If this line is off the bottom of the screen, break out since no lines can ever
         // be on the screen ever again
-        if pos.y + line_offset >= window.height.into() {
+        if y >= area.max.y {
             break;
         }
 
-        // Calculate the beginning and end of string sprite, to not render things off screen
-        let start: i32 = std::cmp::max(0, pos.x);
-        let end: i32 = std::cmp::min(window.width as i32, pos.x + line.len() as i32);
+        for idx in x_start..x_end {
+            let mut x = pos.x + idx as i32;
+            if wrap {
+                x = area.min.x + (x - area.min.x).rem_euclid(area.width());
+            }
 
-        let start_idx: usize = (start - pos.x).try_into()?;
-        let end_idx: usize = (end - pos.x).try_into()?;
+            let cell = &raster.cells[line_num * raster.width + idx];
+            let grapheme = if cell.grapheme.is_empty() {
+                // The second half of a wide grapheme was already drawn along with the first half,
+                // unless the first half is off the screen or on the other side of it
+                if idx != x_start && x != area.min.x {
+                    continue;
+                }
+                " "
+            } else if grapheme_width(&cell.grapheme) == 2
+                && (idx + 1 >= x_end || x + 1 >= area.max.x)
+            {
+                // A wide grapheme needs both of its cells, so if the second one is cut off or would
+                // wrap around just draw a space
+                " "
+            } else {
+                &cell.grapheme
+            };
+
+            // If the grapheme is a transparent space with no style, let whatever is underneath show through
+            if visible.is_transparent && grapheme == " " && cell.unstyled {
+                continue;
+            }
 
-        term.queue(crossterm::cursor::MoveTo(
-            start.try_into()?,
-            (pos.y + line_offset).try_into()?,
-        ))?;
+            buffer.put(x, y, grapheme, cell.style);
+            if let Some(link) = links.and_then(|links| links.url_at(idx, line_num)) {
+                let end = if grapheme_width(grapheme) == 2 {
+                    x + 1
+                } else {
+                    x
+                };
+                for x in x..=end {
+                    if let Some(target) = buffer.get_mut(x, y) {
+                        target.link = Some(link.clone());
+                    }
+                }
+            }
+        }
+    }
+}
 
-        let graphemes = &line[start_idx..end_idx];
-        if !graphemes.is_empty() {
-            // Go through each grapheme one by one to make sure we have the correct style and color
-            // (Cross reference with the stylemap, otherwise default to )
-            for (i, grapheme) in graphemes.iter().enumerate() {
-                let idx = start_idx + i;
+/// The laid out sprites, by the sprite and stylemap they were made from
+pub(crate) type Rasters = bevy::utils::HashMap<(AssetId<Sprite>, AssetId<StyleMap>), Raster>;
 
-                // If the grapheme is a transparent space with no style, skip rendering it
-                if draw.is_transparent
-                    && stylemap.style_at(idx, line_num).is_none()
-                    && sprite.grapheme(grapheme) == " "
-                {
-                    term.queue(crossterm::cursor::MoveRight(1))?;
-                    continue;
+/// A sprite laid out in cells with its stylemap applied, ready to be copied into a buffer
+pub(crate) struct Raster {
+    /// A hash of the sprite and stylemap it was laid out from
+    source: u64,
+    width: usize,
+    height: usize,
+    cells: Vec<RasterCell>,
+}
+
+struct RasterCell {
+    /// Empty for the second half of a wide grapheme
+    grapheme: String,
+    style: Style,
+    /// Whether the stylemap leaves this cell alone, so a transparent space here can be seen through
+    unstyled: bool,
+}
+
+impl Raster {
+    fn new(sprite: &Sprite, stylemap: &StyleMap, window_colors: Colors) -> Raster {
+        let source = content_hash(sprite, stylemap);
+        let (width, height) = (sprite.width(), sprite.height());
+        let mut cells = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let grapheme = match sprite.grapheme_at(x, y) {
+                    Some(grapheme) => grapheme,
+                    None if sprite.is_continuation(x, y) => "",
+                    // Lines don't have to go to the end of the sprite. Pad them out so the sprite is rectangular
+                    None => " ",
+                };
+                cells.push(RasterCell {
+                    grapheme: grapheme.to_string(),
+                    style: stylemap.style_for(x, y).with_default_colors(window_colors),
+                    unstyled: stylemap.style_at(x, y).is_none(),
+                });
+            }
+        }
+        Raster {
+            source,
+            width,
+            height,
+            cells,
+        }
+    }
+}
+
+fn content_hash(sprite: &Sprite, stylemap: &StyleMap) -> u64 {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = bevy::utils::AHasher::default();
+    (sprite, stylemap).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// An entity to draw, with its position on the screen
+type Drawable<'a> = (
+    Entity,
+    Position,
+    Option<&'a components::RenderPriority>,
+    Option<&'a components::Overflow>,
+    &'a Handle<StyleMap>,
+    &'a components::Visible,
+    &'a Handle<Sprite>,
+    Option<&'a components::Hyperlinks>,
+);
+
+/// Draws a set of entities into the buffer, from the lowest z to the highest
+#[allow(clippy::too_many_arguments)]
+fn draw_entities(
+    buffer: &mut CellBuffer,
+    area: IRect,
+    mut entities: Vec<Drawable>,
+    sprites: &Assets<Sprite>,
+    stylemaps: &Assets<StyleMap>,
+    asset_server: &AssetServer,
+    errors: &mut EventWriter<CrosstermRenderError>,
+    stats: &mut RenderStats,
+    overflow: components::Overflow,
+    window_colors: Colors,
+    reloads: &bevy::utils::HashMap<bevy_asset::UntypedAssetId, u64>,
+    rasters: &mut Rasters,
+) {
+    // Entities with the same z and priority are always drawn in the same order
+    entities.sort_by_key(|(entity, pos, priority, ..)| {
+        (pos.z, priority.copied().unwrap_or_default(), *entity)
+    });
+
+    for (entity, pos, _, entity_overflow, stylemap_handle, visible, sprite_handle, links) in
+        entities
+    {
+        let sprite = sprites.get(sprite_handle);
+        let stylemap = stylemaps.get(stylemap_handle);
+        let (sprite, stylemap) = match (sprite, stylemap) {
+            (Some(sprite), Some(stylemap)) => (sprite, stylemap),
+            _ => {
+                // Usually the assets just haven't loaded yet, which isn't a problem. They'll be drawn
+                // once they are. But if they never will be, say so
+                if sprite.is_none() && is_missing(sprite_handle, asset_server) {
+                    errors.send(CrosstermRenderError::MissingSprite { entity });
                 }
+                if stylemap.is_none() && is_missing(stylemap_handle, asset_server) {
+                    errors.send(CrosstermRenderError::MissingStyleMap { entity });
+                }
+                continue;
+            }
+        };
 
-                // Get the style we need to render this grapheme with
-                let grapheme_style = stylemap.style_for(idx, line_num);
-                change_style_if_needed(term, &mut previous_style, &grapheme_style)?;
+        // Empty sprites don't have anything to draw, whatever their stylemap is like
+        if sprite.is_empty() {
+            continue;
+        }
 
-                term.queue(crossterm::style::Print(&sprite.grapheme(grapheme)))?;
+        // A stylemap with styles for lines the sprite doesn't have was probably made for a different
+        // sprite, so rather than draw something that looks wrong, leave the entity out. Longer rows are
+        // fine, since text editors like to trim the spaces at the end of a sprite's lines
+        let clipped;
+        let stylemap = if stylemap.map.len() > sprite.height() {
+            // When the sprite or stylemap was just hot reloaded, the other one is probably about to
+            // be reloaded to match, so until it is, only the styles that fit the sprite are used
+            let reloading = reloads.contains_key(&sprite_handle.id().untyped())
+                || reloads.contains_key(&stylemap_handle.id().untyped());
+            if !reloading {
+                errors.send(CrosstermRenderError::StyleMapMismatch {
+                    entity,
+                    sprite_size: (sprite.width(), sprite.height()),
+                    stylemap_size: (
+                        stylemap.map.iter().map(Vec::len).max().unwrap_or(0),
+                        stylemap.map.len(),
+                    ),
+                });
+                continue;
             }
+            clipped = StyleMap::new(stylemap.style, stylemap.map[..sprite.height()].to_vec());
+            &clipped
+        } else {
+            stylemap
+        };
+
+        if !visible.is_visible {
+            continue;
         }
 
-        // Lines don't have to go to the end of the sprite. Pad them out so the sprite is rectangular
-        if end < window.width as i32 && line.len() < sprite.width() {
-            let unaccounted = sprite.width() - line.len();
-            let blank_length = std::cmp::min(unaccounted, (window.width as i32 - end) as usize);
-            let blank_str = str::repeat(" ", blank_length);
-            for (i, space) in blank_str.chars().enumerate() {
-                let idx = end_idx + i;
+        // Don't bother with sprites that are entirely off the screen. Sprites that wrap around are
+        // always somewhere on it
+        let overflow = entity_overflow.copied().unwrap_or(overflow);
+        if overflow != components::Overflow::Wrap && sprite.bounds(&pos).intersect(area).is_empty()
+        {
+            stats.culled += 1;
+            continue;
+        }
 
-                // If the filler space is transparent and has no style, skip it
-                if draw.is_transparent && stylemap.style_at(idx, line_num).is_none() {
-                    term.queue(crossterm::cursor::MoveRight(1))?;
-                    continue;
+        stats.drawn += 1;
+        // Laying a sprite out is only done again once it or its stylemap changes. Their asset events
+        // only arrive on the next update, so the contents are checked too
+        let raster = rasters
+            .entry((sprite_handle.id(), stylemap_handle.id()))
+            .and_modify(|raster| {
+                if raster.source != content_hash(sprite, stylemap) {
+                    stats.rasterized += 1;
+                    *raster = Raster::new(sprite, stylemap, window_colors);
                 }
+            })
+            .or_insert_with(|| {
+                stats.rasterized += 1;
+                Raster::new(sprite, stylemap, window_colors)
+            });
+        draw_entity(buffer, area, &pos, raster, links, visible, overflow);
+    }
+}
+
+/// Whether an asset that isn't in its `Assets` never will be, because it failed to load or was removed
+fn is_missing<A: Asset>(handle: &Handle<A>, asset_server: &AssetServer) -> bool {
+    // The default handle is a placeholder for an asset that hasn't been picked yet
+    if handle.id() == AssetId::default() {
+        return false;
+    }
+    matches!(
+        asset_server.get_load_state(handle.id()),
+        None | Some(LoadState::Failed)
+    )
+}
+
+/// The positions of entities and their parents, for `global_position`
+pub(crate) type Hierarchy<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Option<&'static Position>,
+        Option<&'static Parent>,
+        Option<&'static components::GridSnap>,
+    ),
+>;
+
+/// Where an entity is drawn. The `Position` of an entity with a `Parent` is relative to its parent's,
+/// all the way up the hierarchy. Parents without a `Position` don't move their children. Each
+/// position in the hierarchy with a `GridSnap` is snapped to its grid.
+pub(crate) fn global_position(entity: Entity, pos: &Position, hierarchy: &Hierarchy) -> Position {
+    let snap = hierarchy.get(entity).ok().and_then(|(.., snap)| snap);
+    let mut global = snapped(pos, snap);
+    let mut current = entity;
+    while let Ok((_, Some(parent), _)) = hierarchy.get(current) {
+        current = parent.get();
+        if let Ok((Some(parent_pos), _, snap)) = hierarchy.get(current) {
+            let parent_pos = snapped(parent_pos, snap);
+            global.x += parent_pos.x;
+            global.y += parent_pos.y;
+            global.z += parent_pos.z;
+        }
+    }
+    global
+}
 
-                // Get the style we need to render this space with
-                let grapheme_style = stylemap.style_for(idx, line_num);
-                change_style_if_needed(term, &mut previous_style, &grapheme_style)?;
+/// Everything that can be drawn, along with whether it's static
+type Drawables<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Position,
+        Option<&'static components::RenderPriority>,
+        Option<&'static components::Overflow>,
+        &'static Handle<StyleMap>,
+        &'static components::Visible,
+        &'static Handle<Sprite>,
+        Option<&'static components::Hyperlinks>,
+        Has<components::Static>,
+    ),
+>;
+
+/// Builds up the next frame in memory
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn compose_frame(
+    mut state: ResMut<RenderState>,
+    mut frame: ResMut<CellBuffer>,
+    window: Query<&CrosstermWindow>,
+    sprites: Res<Assets<Sprite>>,
+    stylemaps: Res<Assets<StyleMap>>,
+    all: Drawables,
+    hierarchy: Hierarchy,
+    space: Option<Res<CoordinateSpace>>,
+    settings: Res<CrosstermWindowSettings>,
+    asset_server: Res<AssetServer>,
+    mut errors: EventWriter<CrosstermRenderError>,
+    mut stats: ResMut<RenderStats>,
+) {
+    if !state.redraw {
+        return;
+    }
+    *stats = RenderStats {
+        input_dropped: stats.input_dropped,
+        ..RenderStats::default()
+    };
 
-                term.queue(crossterm::style::Print(space))?;
+    let window = window.single();
+    let state = &mut *state;
+    let window_colors = window.colors.with_default(Colors::term_colors());
+    let blank = Cell::blank(window_colors);
+    let space = space.as_deref().copied().unwrap_or_default();
+    let screen_position = |entity, pos| {
+        let mut pos = global_position(entity, pos, &hierarchy);
+        (pos.x, pos.y) = space.to_screen(pos.x, pos.y, window);
+        pos
+    };
+
+    // Static sprites are drawn once into their own layer, which is reused until one of them changes
+    if state.rebuild_background {
+        state
+            .background
+            .resize(window.width(), window.height(), &blank);
+        state.static_entities.clear();
+
+        let mut statics = Vec::new();
+        for (entity, pos, priority, overflow, stylemap, visible, sprite, links, is_static) in
+            all.iter()
+        {
+            if is_static {
+                state.static_entities.insert(entity);
+                let pos = screen_position(entity, pos);
+                statics.push((
+                    entity, pos, priority, overflow, stylemap, visible, sprite, links,
+                ));
             }
         }
+        draw_entities(
+            &mut state.background,
+            window.drawable_area(),
+            statics,
+            &sprites,
+            &stylemaps,
+            &asset_server,
+            &mut errors,
+            &mut stats,
+            settings.overflow(),
+            window_colors,
+            &state.reloads,
+            &mut state.rasters,
+        );
+        state.rebuild_background = false;
     }
 
-    Ok(())
+    // Everything else is drawn on top of the static layer every time the frame changes
+    frame.copy_from(&state.background);
+    let dynamics = all
+        .iter()
+        .filter(|(.., is_static)| !is_static)
+        .map(
+            |(entity, pos, priority, overflow, stylemap, visible, sprite, links, _)| {
+                let pos = screen_position(entity, pos);
+                (
+                    entity, pos, priority, overflow, stylemap, visible, sprite, links,
+                )
+            },
+        )
+        .collect();
+    draw_entities(
+        &mut frame,
+        window.drawable_area(),
+        dynamics,
+        &sprites,
+        &stylemaps,
+        &asset_server,
+        &mut errors,
+        &mut stats,
+        settings.overflow(),
+        window_colors,
+        &state.reloads,
+        &mut state.rasters,
+    );
+
+    // Nothing is drawn in the safe area, or around the canvas
+    let letterbox = match window.fixed_canvas() {
+        Some(canvas) => Cell::blank(Colors::bg(canvas.letterbox).with_default(window_colors)),
+        None => blank,
+    };
+    frame.clear_outside(window.drawable_area(), &letterbox);
 }
 
-fn clear_entity(
-    entity: Entity,
-    term: &mut std::io::StdoutLock,
-    window: &CrosstermWindow,
-    previous_details: &PreviousEntityDetails,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let prev_details = previous_details.0.get(&entity);
-    if prev_details.is_none() {
-        // We didn't have a chance to create previous details for this entity yet
-        // Since it's sprite is most likely still loading
-        return Ok(());
+/// Whether a new frame was composed this update, and hasn't been written to the terminal yet
+pub(crate) fn frame_composed(state: Res<RenderState>) -> bool {
+    state.redraw
+}
+
+/// Draws the `DebugOverlay` on top of a newly composed frame
+pub(crate) fn draw_debug_overlay(
+    state: Res<RenderState>,
+    overlay: Res<DebugOverlay>,
+    mut frame: ResMut<CellBuffer>,
+) {
+    if !state.redraw || !overlay.enabled {
+        return;
     }
-    let (prev_pos, prev_size) = prev_details.unwrap();
 
-    for height in 0..prev_size.height {
-        let y = prev_pos.y + height as i32;
+    let (width, height) = (frame.width() as i32, frame.height() as i32);
+    if let Some(spacing) = overlay.grid {
+        let spacing = spacing.get() as i32;
+        let style = Style::with_attrib(crossterm::style::Attribute::Dim);
+        for y in 0..height {
+            for x in 0..width {
+                let glyph = match (x % spacing == 0, y % spacing == 0) {
+                    (true, true) => "┼",
+                    (true, false) => "│",
+                    (false, true) => "─",
+                    (false, false) => continue,
+                };
+                // Grid lines only go where there's nothing else, so they don't hide the sprites
+                if frame.get(x, y).is_some_and(|cell| cell.grapheme == " ") {
+                    frame.put(x, y, glyph, style);
+                }
+            }
+        }
+    }
 
-        if y < 0 {
-            continue;
+    if overlay.rulers {
+        let digit = |n: i32| (n % 10).to_string();
+        let style = |n: i32| {
+            let mut attributes =
+                crossterm::style::Attributes::from(crossterm::style::Attribute::Reverse);
+            if n % 10 == 0 {
+                attributes.set(crossterm::style::Attribute::Bold);
+            }
+            Style::with_attribs(attributes)
+        };
+        for x in 0..width {
+            frame.put(x, 0, &digit(x), style(x));
+        }
+        for y in 1..height {
+            frame.put(0, y, &digit(y), style(y));
         }
+    }
+}
 
-        if prev_pos.y >= window.height.into()
-            || prev_pos.y + prev_size.height as i32 <= 0
-            || prev_pos.x >= window.width.into()
-            || prev_pos.x + prev_size.width as i32 <= 0
-        {
-            break;
+/// Keeps track of the style the terminal is currently printing with, so the escape sequences to change
+/// it are only written when the next cell actually looks different. `None` means it isn't known.
+#[derive(Default)]
+struct TerminalStyle {
+    attributes: Option<crossterm::style::Attributes>,
+    foreground: Option<crossterm::style::Color>,
+    background: Option<crossterm::style::Color>,
+    // None is the terminal's default, which is the text color
+    underline_color: Option<crossterm::style::Color>,
+}
+
+impl TerminalStyle {
+    /// Switches the terminal to the given style, which must have both of its colors set
+    fn apply(&mut self, term: &mut Vec<u8>, style: Style) -> std::io::Result<()> {
+        // Attributes can only be turned off with a reset, which also resets the colors
+        if self.attributes != Some(style.attributes) {
+            term.queue(crossterm::style::SetAttribute(
+                crossterm::style::Attribute::Reset,
+            ))?;
+            // Styles without attributes are often written as just `Reset`, which has already been done
+            let mut attributes = style.attributes;
+            attributes.unset(crossterm::style::Attribute::Reset);
+            if !attributes.is_empty() {
+                term.queue(crossterm::style::SetAttributes(attributes))?;
+            }
+            self.attributes = Some(style.attributes);
+            self.foreground = Some(crossterm::style::Color::Reset);
+            self.background = Some(crossterm::style::Color::Reset);
+            self.underline_color = None;
         }
 
-        let x_start: i32 = std::cmp::max(0, prev_pos.x);
-        let x_end: i32 = std::cmp::min(window.width as i32, prev_pos.x + prev_size.width as i32);
+        if self.foreground != style.colors.foreground {
+            if let Some(color) = style.colors.foreground {
+                term.queue(crossterm::style::SetForegroundColor(color))?;
+            }
+            self.foreground = style.colors.foreground;
+        }
+        if self.background != style.colors.background {
+            if let Some(color) = style.colors.background {
+                term.queue(crossterm::style::SetBackgroundColor(color))?;
+            }
+            self.background = style.colors.background;
+        }
+        // Terminals which don't support underline colors ignore this
+        if self.underline_color != style.underline_color {
+            let color = style
+                .underline_color
+                .unwrap_or(crossterm::style::Color::Reset);
+            term.queue(crossterm::style::SetUnderlineColor(color))?;
+            self.underline_color = style.underline_color;
+        }
 
-        let actual_width = x_end - x_start;
-        let blank_string = " ".repeat(actual_width as usize);
+        Ok(())
+    }
+}
+
+/// Writes out every cell which differs between what's on the screen and the new frame
+fn write_changes(
+    term: &mut Vec<u8>,
+    previous: &CellBuffer,
+    current: &CellBuffer,
+    strategy: DiffStrategy,
+    hyperlinks: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // We don't know what style the terminal was left in, so the first cell always sets it
+    let mut terminal_style = TerminalStyle::default();
+    let mut terminal_cursor = None;
+    // Links stay open while the cursor moves, so every cell that's printed has to be checked
+    let mut terminal_link = None;
+    // Even a full rewrite is skipped if the frame came out the same
+    let rewrite_all = strategy == DiffStrategy::Full && previous != current;
+
+    for y in 0..current.height() {
+        let y = y as i32;
+        let changed = |x| previous.get(x, y) != current.get(x, y);
+        let rewrite_row = match strategy {
+            DiffStrategy::Cell => false,
+            DiffStrategy::Row => (0..current.width() as i32).any(changed),
+            DiffStrategy::Full => rewrite_all,
+        };
+
+        for x in 0..current.width() {
+            let x = x as i32;
+            let cell = current.get(x, y).unwrap();
+            // The two halves of a wide grapheme are written together, so if either half changed
+            // the whole grapheme has to be written again
+            let unchanged = !rewrite_row
+                && !changed(x)
+                && !(current.is_wide(x, y) && changed(x + 1))
+                && !(cell.is_continuation() && changed(x - 1));
+            if unchanged {
+                continue;
+            }
+
+            let grapheme = if cell.is_continuation() {
+                if current.is_wide(x - 1, y) {
+                    // Already covered by the first half
+                    continue;
+                }
+                // Something drew over the first half, so blank out the half that's left
+                " "
+            } else if current.is_wide(x, y)
+                && !current.get(x + 1, y).is_some_and(Cell::is_continuation)
+            {
+                // Something drew over the second half
+                " "
+            } else {
+                &cell.grapheme
+            };
+
+            // Anything can write to the frame, so colors which weren't set use the terminal's
+            let style = cell.style.with_default_colors(Colors::term_colors());
+
+            // Printing moves the cursor along, so consecutive changed cells don't need to move it
+            if terminal_cursor != Some((x, y)) {
+                term.queue(crossterm::cursor::MoveTo(x as u16, y as u16))?;
+            }
 
-        let x = x_start.try_into()?;
-        let y = y.try_into()?;
+            terminal_style.apply(term, style)?;
 
+            // Terminals which don't support hyperlinks are sent plain text, since some of them print
+            // the escape codes
+            let link = cell.link.as_deref().filter(|_| hyperlinks);
+            if terminal_link != link {
+                write_hyperlink(term, link)?;
+                terminal_link = link;
+            }
+
+            term.queue(crossterm::style::Print(grapheme))?;
+            // Terminals don't agree on how far zero width graphemes move the cursor, so don't guess
+            terminal_cursor = match grapheme.width() {
+                0 => None,
+                width => Some((x + width.min(2) as i32, y)),
+            };
+        }
+    }
+
+    // Don't leave a link open for whatever is printed next
+    if terminal_link.is_some() {
+        write_hyperlink(term, None)?;
+    }
+
+    Ok(())
+}
+
+/// Starts an OSC 8 hyperlink to the given URL, which covers everything printed until the next one.
+/// `None` ends the link
+fn write_hyperlink(term: &mut Vec<u8>, url: Option<&str>) -> std::io::Result<()> {
+    write!(term, "\x1b]8;;{}\x07", url.unwrap_or_default())
+}
+
+/// Writes a frame to the terminal wherever the cursor is, one row after another, rather than at the
+/// top of the screen. Rows at the bottom which are entirely `blank` are left out
+pub(crate) fn write_frame_inline(
+    term: &mut Vec<u8>,
+    frame: &CellBuffer,
+    blank: &Cell,
+) -> std::io::Result<()> {
+    let row_is_blank = |y| (0..frame.width() as i32).all(|x| frame.get(x, y) == Some(blank));
+    let height = (0..frame.height() as i32)
+        .rev()
+        .find(|&y| !row_is_blank(y))
+        .map_or(0, |y| y + 1);
+
+    for y in 0..height {
+        let mut terminal_style = TerminalStyle::default();
+        for x in 0..frame.width() as i32 {
+            let cell = frame.get(x, y).unwrap();
+            if cell.is_continuation() {
+                continue;
+            }
+            terminal_style.apply(term, cell.style.with_default_colors(Colors::term_colors()))?;
+            term.queue(crossterm::style::Print(&cell.grapheme))?;
+        }
+        // The terminal is still in raw mode, so a line feed on its own wouldn't go back to the start
+        // of the line
         queue!(
             term,
-            crossterm::style::SetAttribute(crossterm::style::Attribute::Reset,),
-            crossterm::style::SetColors(Colors::term_colors().to_crossterm(),),
-            crossterm::cursor::MoveTo(x, y),
-            crossterm::style::Print(blank_string)
+            crossterm::style::SetAttribute(crossterm::style::Attribute::Reset),
+            crossterm::style::ResetColor,
+            crossterm::style::Print("\r\n"),
         )?;
     }
 
     Ok(())
 }
 
-/// Draw any entity that needs to be drawn
+/// Writes the changes in the new frame to the terminal
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn crossterm_render(
-    changed_entities: Res<components::EntitiesToRedraw>,
-    window: Query<&CrosstermWindow>,
+    mut state: ResMut<RenderState>,
+    frame: Res<CellBuffer>,
+    mut window: Query<&mut CrosstermWindow>,
+    settings: Res<CrosstermWindowSettings>,
     cursor: Res<Cursor>,
-    previous_details: Res<PreviousEntityDetails>,
-    sprites: Res<Assets<Sprite>>,
-    stylemaps: Res<Assets<StyleMap>>,
-    all: Query<(
-        Entity,
-        &Position,
-        &Handle<StyleMap>,
-        &components::Visible,
-        &Handle<Sprite>,
-    )>,
+    mut errors: EventWriter<CrosstermRenderError>,
+    mut first_frame: EventWriter<FirstFrameRendered>,
+    mut writer: ResMut<CrosstermWriter>,
+    mut term: Local<Vec<u8>>,
 ) {
-    let window = window.single();
-    let stdout = std::io::stdout();
-    let mut term = stdout.lock();
+    let mut window = window.single_mut();
+    let state = &mut *state;
+    let term = &mut *term;
+    // The whole frame is queued up in memory first, and only written to the terminal at the end.
+    // Clearing keeps the allocation from the previous frame around
+    term.clear();
+
+    // Clearing the requests isn't a change to the window, so it shouldn't trip `Changed<CrosstermWindow>`
+    if window.full_redraw_pending {
+        window.bypass_change_detection().full_redraw_pending = false;
+        state.redraw = true;
+        state.full_redraw = true;
+    }
+
+    // Something other than the compose step may have drawn into the frame too
+    let drawing = state.redraw || frame.is_changed();
+    if drawing {
+        // If the terminal supports it, have it wait until the frame is finished before showing it
+        if window.synchronized_updates() {
+            term.queue(crossterm::terminal::BeginSynchronizedUpdate)
+                .unwrap();
+        }
 
-    // If we're gonna be drawing stuff, hide the cursor so it doesn't jump all over the place
-    if !changed_entities.to_draw.is_empty() {
+        // If we're gonna be drawing stuff, hide the cursor so it doesn't jump all over the place
         term.queue(crossterm::cursor::Hide).unwrap();
-    }
 
-    // If a resize happened, clear the screen and go from there
-    if changed_entities.full_redraw {
-        queue!(
+        // If a resize happened or a full redraw was requested, clear the screen and go from there
+        if state.full_redraw {
+            let window_colors = window.colors.with_default(Colors::term_colors());
+            queue!(
+                term,
+                crossterm::style::SetAttribute(crossterm::style::Attribute::Reset),
+                crossterm::style::SetColors(window_colors.to_crossterm()),
+                crossterm::terminal::Clear(crossterm::terminal::ClearType::All)
+            )
+            .unwrap();
+
+            // The screen is now entirely blank, so only the cells with something in them need to be drawn
+            let (width, height) = (frame.width(), frame.height());
+            state
+                .previous
+                .resize(width, height, &Cell::blank(window_colors));
+        }
+
+        write_changes(
             term,
-            crossterm::style::SetAttribute(crossterm::style::Attribute::Reset),
-            crossterm::terminal::Clear(crossterm::terminal::ClearType::All)
+            &state.previous,
+            &frame,
+            settings.diff_strategy(),
+            window.hyperlinks(),
         )
         .unwrap();
-    } else {
-        // No need to clear individual entities if we just cleared the whole screen anyways.
-        // Blank out all the previous locations of sprites that changed either their position or their size
-        for entity in &changed_entities.to_clear {
-            clear_entity(*entity, &mut term, window, &previous_details).unwrap();
+
+        if let Some(path) = settings.mirror_text_path() {
+            if let Err(error) = std::fs::write(path, frame.to_text()) {
+                errors.send(CrosstermRenderError::MirrorText(error));
+            }
         }
-    }
 
-    // Redraw all the changed sprites, either because they moved, or because they changed their shape
-    for entity in &changed_entities.to_draw {
-        draw_entity(entity.entity, &mut term, window, &sprites, &stylemaps, &all).unwrap();
+        // What we just drew is now on the screen
+        state.previous.copy_from(&frame);
+        state.redraw = false;
+        state.full_redraw = false;
     }
 
     // Draw the cursor at the right position, if needed
-    if !cursor.hidden
-        && cursor.x >= 0
-        && cursor.x < window.width as i32
-        && cursor.y >= 0
-        && cursor.y < window.height as i32
-    {
-        queue!(
-            term,
-            crossterm::cursor::MoveTo(cursor.x as u16, cursor.y as u16),
-            crossterm::cursor::Show
+    if drawing || cursor.is_changed() {
+        if !cursor.hidden
+            && cursor.x >= 0
+            && cursor.x < window.width as i32
+            && cursor.y >= 0
+            && cursor.y < window.height as i32
+        {
+            queue!(
+                term,
+                crossterm::cursor::MoveTo(cursor.x as u16, cursor.y as u16),
+                crossterm::cursor::Show
+            )
+            .unwrap();
+        } else {
+            term.queue(crossterm::cursor::Hide).unwrap();
+        }
+    }
+
+    if drawing && window.synchronized_updates() {
+        term.queue(crossterm::terminal::EndSynchronizedUpdate)
+            .unwrap();
+    }
+
+    // Ring the bell if someone asked for it since the last frame
+    if window.bell_pending {
+        window.bypass_change_detection().bell_pending = false;
+        if !settings.bell_suppressed() {
+            term.queue(crossterm::style::Print('\x07')).unwrap();
+        }
+    }
+
+    if term.is_empty() {
+        return;
+    }
+
+    let out = &mut writer.0;
+    let written = match settings.write_chunk_size() {
+        None => out.write_all(term),
+        Some(chunk_size) => term.chunks(chunk_size.get()).try_for_each(|chunk| {
+            out.write_all(chunk)?;
+            out.flush()
+        }),
+    }
+    .and_then(|_| out.flush());
+
+    if let Err(error) = written {
+        // There's no telling how much of the frame made it to the screen, so start from scratch next time
+        state.redraw = true;
+        state.full_redraw = true;
+        errors.send(CrosstermRenderError::Io(error));
+    } else if drawing && !state.first_frame_rendered {
+        state.first_frame_rendered = true;
+        first_frame.send(FirstFrameRendered);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn velocity_moves_whole_cells_at_its_rate() {
+        let (mut app, _) = testing::app(20, 10);
+        let entity = testing::spawn_sprite(&mut app, "@", 5, 5);
+        app.world
+            .entity_mut(entity)
+            .insert(components::Velocity::new(3.0, -2.0));
+
+        // 0.75 cells right and 0.5 cells up each step. Only whole cells are applied, and what's left
+        // over carries on to the next step
+        let mut positions = Vec::new();
+        for _ in 0..4 {
+            testing::step(&mut app, 0.25);
+            positions.push(testing::position(&app, entity));
+        }
+        assert_eq!(positions, [(5, 5), (6, 4), (7, 4), (8, 3)]);
+    }
+
+    #[test]
+    fn transition_ends_on_the_target_sprite() {
+        let (mut app, _) = testing::app(10, 3);
+        let entity = testing::spawn_sprite(&mut app, "aaaa", 0, 0);
+        let mut sprites = app.world.resource_mut::<Assets<Sprite>>();
+        let source = sprites.add(Sprite::new("aaaa"));
+        let target = sprites.add(Sprite::new("bbbb"));
+        app.world
+            .entity_mut(entity)
+            .insert(components::Transition::new(
+                source,
+                target.clone(),
+                std::time::Duration::from_secs(1),
+                components::DissolveMask::Ordered,
+            ));
+
+        // Halfway through, half of the cells have been replaced
+        testing::step(&mut app, 0.25);
+        testing::step(&mut app, 0.25);
+        let halfway = &testing::screen(&app)[0];
+        assert_eq!(halfway.matches('b').count(), 2, "{halfway:?}");
+
+        testing::step(&mut app, 0.25);
+        testing::step(&mut app, 0.25);
+        app.update();
+        assert_eq!(testing::screen(&app)[0], "bbbb");
+        let entity_ref = app.world.entity(entity);
+        assert_eq!(entity_ref.get::<Handle<Sprite>>(), Some(&target));
+        assert!(!entity_ref.contains::<components::Transition>());
+        let finished = app
+            .world
+            .resource::<Events<components::TransitionFinished>>();
+        let mut reader = finished.get_reader();
+        let finished: Vec<_> = reader.read(finished).map(|event| event.entity).collect();
+        assert_eq!(finished, [entity]);
+    }
+
+    #[test]
+    fn typewriters_reveal_a_grapheme_at_a_time() {
+        use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+        let (mut app, _) = testing::app(10, 2);
+        let entity = testing::spawn_sprite(&mut app, "", 0, 0);
+        let target = app
+            .world
+            .resource_mut::<Assets<Sprite>>()
+            .add(Sprite::new("Hello\nthere"));
+        app.world
+            .entity_mut(entity)
+            .insert(components::Typewriter::new(target.clone(), 4.0).with_skip_key(KeyCode::Enter));
+        let typed = |app: &App| {
+            let screen = testing::screen(app).join("\n");
+            screen.chars().filter(|c| c.is_alphabetic()).count()
+        };
+
+        // 4 graphemes a second, and a new line doesn't count as one. The typed sprite is drawn the
+        // update after it changes.
+        testing::step(&mut app, 0.25);
+        testing::step(&mut app, 0.0);
+        assert_eq!(typed(&app), 1);
+        for _ in 0..5 {
+            testing::step(&mut app, 0.25);
+        }
+        testing::step(&mut app, 0.0);
+        assert_eq!(testing::screen(&app), ["Hello", "t"]);
+        assert_eq!(typed(&app), 6);
+
+        let enter = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+        app.world.send_event(crate::CrosstermKeyEventWrapper(enter));
+        app.update();
+        app.update();
+        assert_eq!(testing::screen(&app), ["Hello", "there"]);
+        assert_eq!(app.world.get::<Handle<Sprite>>(entity), Some(&target));
+        let done = app.world.resource::<Events<components::TypewriterDone>>();
+        let mut reader = done.get_reader();
+        let done: Vec<_> = reader.read(done).map(|event| event.entity).collect();
+        assert_eq!(done, [entity]);
+    }
+
+    #[test]
+    fn clamp_to_window_keeps_sprites_on_screen() {
+        let (mut app, _) = testing::app(20, 10);
+        let fully = testing::spawn_sprite(&mut app, "###\n###", 0, 0);
+        let partially = testing::spawn_sprite(&mut app, "###\n###", 0, 0);
+        let huge = testing::spawn_sprite(&mut app, &"#".repeat(30), 5, 5);
+        app.world
+            .entity_mut(fully)
+            .insert(components::ClampToWindow::Fully);
+        app.world
+            .entity_mut(partially)
+            .insert(components::ClampToWindow::Partially {
+                columns: 1,
+                rows: 1,
+            });
+        app.world
+            .entity_mut(huge)
+            .insert(components::ClampToWindow::Fully);
+        app.update();
+
+        for entity in [fully, partially] {
+            let mut pos = app.world.get_mut::<Position>(entity).unwrap();
+            (pos.x, pos.y) = (25, -4);
+        }
+        app.update();
+
+        assert_eq!(testing::position(&app, fully), (17, 0));
+        assert_eq!(testing::position(&app, partially), (19, -1));
+        // Sprites too big for the window go in the corner
+        assert_eq!(testing::position(&app, huge), (0, 5));
+
+        let mut window = app.world.query::<&CrosstermWindow>();
+        let window = window.single(&app.world);
+        let sprite = Sprite::new("###\n###");
+        assert!(window.contains(&Position::with_xy(17, 8), &sprite));
+        assert!(!window.contains(&Position::with_xy(18, 8), &sprite));
+        assert!(!window.contains(&Position::with_xy(0, -1), &sprite));
+    }
+
+    #[test]
+    fn default_colors_fill_in_unstyled_and_empty_cells() {
+        use components::Color;
+
+        let (mut app, output) = testing::app_with(6, 2, |settings| {
+            settings.default_colors(Color::Yellow, Color::Black);
+        });
+        let red = Style::with_colors(Colors::fg(Color::Red));
+        let stylemap = StyleMap::new(Style::default(), vec![vec![red]]);
+        testing::spawn_styled(&mut app, "ab", stylemap, 0, 0);
+        app.update();
+
+        let colors = |app: &App, x, y| {
+            let frame = app.world.resource::<CellBuffer>();
+            frame.get(x, y).unwrap().style.colors
+        };
+        // The styled cell keeps its own foreground but takes the default background
+        assert_eq!(colors(&app, 0, 0), Colors::new(Color::Red, Color::Black));
+        assert_eq!(colors(&app, 1, 0), Colors::new(Color::Yellow, Color::Black));
+        assert_eq!(colors(&app, 4, 1), Colors::new(Color::Yellow, Color::Black));
+
+        // New defaults clear the screen and draw everything again with them
+        output.take();
+        app.world
+            .resource_mut::<CrosstermWindowSettings>()
+            .default_colors(Color::White, Color::Blue);
+        app.update();
+        let written = output.take();
+        assert!(written.contains("\x1b[2J"), "{written:?}");
+        assert_eq!(colors(&app, 1, 0), Colors::new(Color::White, Color::Blue));
+        assert_eq!(colors(&app, 4, 1), Colors::new(Color::White, Color::Blue));
+    }
+
+    #[test]
+    fn each_frame_is_written_in_one_call() {
+        let (mut app, output) = testing::app(40, 10);
+        for row in 0..5 {
+            testing::spawn_sprite(&mut app, "a sprite\nwith two lines", row * 3, row * 2);
+        }
+        app.update();
+        let mut writes = output.writes();
+        let mut writes_this_frame = |app: &mut App| {
+            app.update();
+            let total = output.writes();
+            total - std::mem::replace(&mut writes, total)
+        };
+        writes_this_frame(&mut app);
+
+        for row in 0..5 {
+            testing::spawn_sprite(&mut app, "more", 30, row);
+        }
+        assert_eq!(writes_this_frame(&mut app), 1);
+        // The new assets' events only arrive after the frame is drawn, so the next frame is checked
+        // again, and still written in one go
+        assert!(writes_this_frame(&mut app) <= 1);
+        // Nothing changed, so nothing is written
+        assert_eq!(writes_this_frame(&mut app), 0);
+
+        // With a chunk size, the frame is split into chunks of at most that many bytes
+        app.world
+            .resource_mut::<CrosstermWindowSettings>()
+            .set_write_chunk_size(std::num::NonZeroUsize::new(16));
+        output.take();
+        let before = output.writes();
+        testing::spawn_sprite(&mut app, "even more sprites", 0, 9);
+        app.update();
+        let written = output.take().len();
+        assert_eq!(output.writes() - before, written.div_ceil(16));
+    }
+
+    #[test]
+    fn static_sprites_are_drawn_once_into_the_background() {
+        let (mut app, _) = testing::app(20, 5);
+        let background = testing::spawn_sprite(&mut app, &"=".repeat(20), 0, 4);
+        app.world.entity_mut(background).insert(components::Static);
+        let player = testing::spawn_sprite(&mut app, "@", 0, 3);
+        app.update();
+        assert_eq!(app.world.resource::<RenderStats>().drawn, 2);
+        // The events for the new assets arrive a frame late, and count as a change to them
+        app.update();
+
+        // Only the moving sprite is drawn again, on top of the background that was kept
+        for x in 1..10 {
+            app.world.get_mut::<Position>(player).unwrap().x = x;
+            app.update();
+            assert_eq!(app.world.resource::<RenderStats>().drawn, 1);
+            assert_eq!(testing::screen(&app)[4], "=".repeat(20));
+        }
+        assert_eq!(testing::screen(&app)[3], "         @");
+
+        // Changing the static sprite draws the background again
+        app.world.get_mut::<Position>(background).unwrap().x = 1;
+        app.update();
+        assert_eq!(app.world.resource::<RenderStats>().drawn, 2);
+        assert_eq!(testing::screen(&app)[4], format!(" {}", "=".repeat(19)));
+    }
+
+    #[test]
+    fn cursor_follows_its_mode() {
+        let (mut app, _) = testing::app(20, 10);
+        let cursor = |app: &App| {
+            let cursor = app.world.resource::<Cursor>();
+            (cursor.x, cursor.y, cursor.hidden)
+        };
+
+        app.insert_resource(CursorMode::Free { x: 3, y: 4 });
+        app.update();
+        assert_eq!(cursor(&app), (3, 4, false));
+
+        // The caret is an offset from where the field is drawn, parents included
+        let field = testing::spawn_sprite(&mut app, "name: ", 2, 1);
+        app.world
+            .entity_mut(field)
+            .insert(components::Caret { x: 6, y: 0 });
+        let panel = testing::spawn_sprite(&mut app, "", 5, 5);
+        app.world.entity_mut(panel).add_child(field);
+        app.insert_resource(CursorMode::AtCaret(field));
+        app.update();
+        assert_eq!(cursor(&app), (13, 6, false));
+
+        // Hiding leaves the cursor where it was
+        app.insert_resource(CursorMode::Hidden);
+        app.update();
+        assert_eq!(cursor(&app), (13, 6, true));
+
+        app.insert_resource(CursorMode::AtCaret(field));
+        app.update();
+        app.world.entity_mut(field).despawn_recursive();
+        app.update();
+        assert_eq!(cursor(&app), (13, 6, true));
+    }
+
+    #[test]
+    fn grid_snap_moves_where_sprites_are_drawn() {
+        let (mut app, _) = testing::app(20, 10);
+        let entity = testing::spawn_sprite(&mut app, "@", 5, 3);
+        app.world
+            .entity_mut(entity)
+            .insert(components::GridSnap::new(4, 4));
+        app.update();
+
+        let screen = testing::screen(&app);
+        assert_eq!(screen[4], "    @", "{screen:#?}");
+        assert_eq!(testing::position(&app, entity), (5, 3));
+
+        // A slow velocity still builds up in the position until it reaches the next tile
+        app.world
+            .entity_mut(entity)
+            .insert(components::Velocity::new(4.0, 0.0));
+        testing::step(&mut app, 0.25);
+        testing::step(&mut app, 0.25);
+        assert_eq!(testing::position(&app, entity), (7, 3));
+        assert_eq!(testing::screen(&app)[4], "        @");
+    }
+
+    #[test]
+    fn synchronized_updates_bracket_the_frame() {
+        let (mut app, output) = testing::app(10, 3);
+        testing::spawn_sprite(&mut app, "plain", 0, 0);
+        app.update();
+        let written = output.take();
+        assert!(!written.contains("\x1b[?2026"), "{written:?}");
+        assert!(written.starts_with("\x1b[?25l"), "{written:?}");
+
+        let mut window = app.world.query::<&mut CrosstermWindow>();
+        window.single_mut(&mut app.world).synchronized_updates = true;
+        testing::spawn_sprite(&mut app, "synced", 0, 1);
+        app.update();
+        let written = output.take();
+        assert!(written.starts_with("\x1b[?2026h"), "{written:?}");
+        assert!(written.ends_with("\x1b[?2026l"), "{written:?}");
+        assert!(written.contains("synced"), "{written:?}");
+    }
+
+    #[test]
+    fn fades_leave_every_cell_in_the_target_color() {
+        use crossterm::style::Color;
+
+        let (mut app, _) = testing::app(4, 2);
+        let entity = testing::spawn_sprite(&mut app, "ab\ncd", 1, 0);
+        app.world
+            .entity_mut(entity)
+            .insert(components::FadeOut::new(
+                Color::Red,
+                std::time::Duration::from_secs(1),
+                components::DissolveMask::Random,
+            ));
+        let faded = |app: &App| {
+            let frame = app.world.resource::<CellBuffer>();
+            let mut cells = Vec::new();
+            for y in 0..2 {
+                for x in 0..4 {
+                    let cell = frame.get(x, y).unwrap();
+                    if cell.style.colors.background == Some(Color::Red) {
+                        assert_eq!(cell.grapheme, " ");
+                        cells.push((x, y));
+                    }
+                }
+            }
+            cells
+        };
+
+        testing::step(&mut app, 0.25);
+        testing::step(&mut app, 0.25);
+        testing::step(&mut app, 0.0);
+        assert_eq!(faded(&app).len(), 2);
+
+        testing::step(&mut app, 0.25);
+        testing::step(&mut app, 0.25);
+        testing::step(&mut app, 0.0);
+        assert_eq!(faded(&app), [(1, 0), (2, 0), (1, 1), (2, 1)]);
+        assert!(!app.world.entity(entity).contains::<components::FadeOut>());
+        let finished = app.world.resource::<Events<components::FadeFinished>>();
+        let mut reader = finished.get_reader();
+        let finished: Vec<_> = reader.read(finished).map(|event| event.entity).collect();
+        assert_eq!(finished, [entity]);
+    }
+
+    #[test]
+    fn seeded_dissolves_reveal_cells_in_the_same_order() {
+        let reveal = |seed| {
+            let (mut app, _) = testing::app(20, 3);
+            app.insert_resource(CrosstermRng::with_seed(seed));
+            let entity = testing::spawn_sprite(&mut app, "................", 0, 0);
+            let mut sprites = app.world.resource_mut::<Assets<Sprite>>();
+            let source = sprites.add(Sprite::new("................"));
+            let target = sprites.add(Sprite::new("################"));
+            app.world
+                .entity_mut(entity)
+                .insert(components::Transition::new(
+                    source,
+                    target,
+                    std::time::Duration::from_secs(2),
+                    components::DissolveMask::Random,
+                ));
+
+            let mut frames = Vec::new();
+            for _ in 0..8 {
+                testing::step(&mut app, 0.25);
+                frames.push(testing::screen(&app)[0].clone());
+            }
+            frames
+        };
+
+        let frames = reveal(7);
+        assert_eq!(frames, reveal(7));
+        // Halfway through, only some of the cells have been revealed
+        let halfway = &frames[3];
+        assert!(
+            halfway.contains('#') && halfway.contains('.'),
+            "{halfway:?}"
+        );
+        assert_ne!(frames, reveal(8));
+    }
+
+    #[test]
+    fn render_priority_breaks_ties_within_a_z() {
+        let (mut app, _) = testing::app(10, 3);
+        let tooltip = testing::spawn_sprite(&mut app, "tip", 0, 0);
+        let sibling = testing::spawn_sprite(&mut app, "sibling", 0, 0);
+        app.update();
+        // Without a priority, the entity spawned last is drawn on top
+        assert_eq!(testing::screen(&app)[0], "sibling");
+
+        app.world
+            .entity_mut(tooltip)
+            .insert(components::RenderPriority(1));
+        app.update();
+        assert_eq!(testing::screen(&app)[0], "tipling");
+
+        // z still comes first
+        app.world.get_mut::<Position>(sibling).unwrap().z = 1;
+        app.update();
+        assert_eq!(testing::screen(&app)[0], "sibling");
+    }
+
+    #[test]
+    fn overflow_decides_what_happens_at_each_edge() {
+        use components::Overflow;
+
+        let empty = ["", "", "", ""];
+        let sideways = ["", "b    a", "d    c", ""];
+        let vertical = ["  cd", "", "", "  ab"];
+        let cases = [
+            ((-1, 1), Overflow::Clip, ["", "b", "d", ""]),
+            ((-1, 1), Overflow::Wrap, sideways),
+            ((-1, 1), Overflow::Hidden, empty),
+            ((5, 1), Overflow::Clip, ["", "     a", "     c", ""]),
+            ((5, 1), Overflow::Wrap, sideways),
+            ((5, 1), Overflow::Hidden, empty),
+            ((2, -1), Overflow::Clip, ["  cd", "", "", ""]),
+            ((2, -1), Overflow::Wrap, vertical),
+            ((2, -1), Overflow::Hidden, empty),
+            ((2, 3), Overflow::Clip, ["", "", "", "  ab"]),
+            ((2, 3), Overflow::Wrap, vertical),
+            ((2, 3), Overflow::Hidden, empty),
+        ];
+
+        for ((x, y), overflow, expected) in cases {
+            let (mut app, _) = testing::app(6, 4);
+            let entity = testing::spawn_sprite(&mut app, "ab\ncd", x, y);
+            app.world.entity_mut(entity).insert(overflow);
+            app.update();
+            assert_eq!(
+                testing::screen(&app),
+                expected,
+                "{overflow:?} at ({x}, {y})"
+            );
+
+            // The same policy can be set for every sprite at once
+            let (mut app, _) = testing::app_with(6, 4, |settings| {
+                settings.set_overflow(overflow);
+            });
+            testing::spawn_sprite(&mut app, "ab\ncd", x, y);
+            app.update();
+            assert_eq!(
+                testing::screen(&app),
+                expected,
+                "{overflow:?} at ({x}, {y})"
+            );
+        }
+    }
+
+    #[test]
+    fn styles_are_only_written_when_they_change() {
+        use crossterm::style::{Attribute, Color};
+
+        let blank = Cell::blank(Colors::term_colors());
+        let mut previous = CellBuffer::default();
+        previous.resize(10, 2, &blank);
+        let mut current = previous.clone();
+
+        let colors = Colors::new(Color::Red, Color::Blue);
+        let bold = Style::new(colors, Attribute::Bold.into());
+        current.put_str(0, 0, "abcdefgh", bold);
+        let mut term = Vec::new();
+        write_changes(&mut term, &previous, &current, DiffStrategy::Cell, false).unwrap();
+        let written = String::from_utf8(term).unwrap();
+        let count = |written: &str, sequence| written.matches(sequence).count();
+        assert!(written.contains("abcdefgh"), "{written:?}");
+        for sequence in ["\x1b[0m", "\x1b[1m", "\x1b[38;5;9m", "\x1b[48;5;12m"] {
+            assert_eq!(count(&written, sequence), 1, "{sequence:?} in {written:?}");
+        }
+
+        // The next frame sets the style again from scratch. Moving the cursor doesn't change it, but
+        // turning off an attribute resets the colors too
+        current.put_str(2, 1, "ij", bold);
+        current.put_str(5, 1, "k", Style::with_colors(colors));
+        let mut term = Vec::new();
+        write_changes(&mut term, &previous, &current, DiffStrategy::Cell, false).unwrap();
+        let written = String::from_utf8(term).unwrap();
+        assert_eq!(count(&written, "\x1b[1m"), 1, "{written:?}");
+        for sequence in ["\x1b[0m", "\x1b[38;5;9m", "\x1b[48;5;12m"] {
+            assert_eq!(count(&written, sequence), 2, "{sequence:?} in {written:?}");
+        }
+    }
+
+    #[test]
+    fn diff_strategies_choose_how_much_is_rewritten() {
+        // Only the text that was printed, without any escape sequences
+        fn printed(written: &str) -> String {
+            let mut text = String::new();
+            let mut chars = written.chars();
+            while let Some(c) = chars.next() {
+                if c == '\x1b' {
+                    chars.next();
+                    chars.find(|c| ('@'..='~').contains(c));
+                } else {
+                    text.push(c);
+                }
+            }
+            text
+        }
+
+        for (strategy, expected) in [
+            (DiffStrategy::Cell, "X"),
+            (DiffStrategy::Row, "aXcd"),
+            (DiffStrategy::Full, "aXcdefgh"),
+        ] {
+            let (mut app, output) =
+                testing::app_with(4, 2, |settings| _ = settings.set_diff_strategy(strategy));
+            testing::spawn_sprite(&mut app, "abcd\nefgh", 0, 0);
+            app.update();
+            app.update();
+
+            output.take();
+            testing::spawn_sprite(&mut app, "X", 1, 0);
+            app.update();
+            app.update();
+            assert_eq!(printed(&output.take()), expected, "{strategy:?}");
+        }
+    }
+
+    #[test]
+    fn hyperlinks_bracket_exactly_their_cells() {
+        let links = components::Hyperlinks::new()
+            .with_link(URect::new(4, 0, 8, 1), "https://docs.rs")
+            .with_link(URect::new(0, 1, 2, 2), "https://a.io");
+        for supported in [true, false] {
+            let (mut app, output) = testing::app(14, 2);
+            let mut window = app.world.query::<&mut CrosstermWindow>();
+            window.single_mut(&mut app.world).hyperlinks = supported;
+            let entity = testing::spawn_sprite(&mut app, "see docs here\nok", 0, 0);
+            app.world.entity_mut(entity).insert(links.clone());
+            output.take();
+            app.update();
+
+            // Only the links and the text, without the cursor moves and styles in between
+            let mut written = output.take();
+            while let Some(start) = written.find("\x1b[") {
+                let len = written[start + 2..]
+                    .find(|c| ('@'..='~').contains(&c))
+                    .unwrap();
+                written.replace_range(start..start + len + 3, "");
+            }
+            if supported {
+                let expected = "see\x1b]8;;https://docs.rs\x07docs\x1b]8;;\x07here\
+                                \x1b]8;;https://a.io\x07ok\x1b]8;;\x07";
+                assert_eq!(written, expected);
+            } else {
+                assert_eq!(written, "seedocshereok");
+            }
+        }
+    }
+
+    #[test]
+    fn first_frame_rendered_is_only_sent_once() {
+        let (mut app, output) = testing::app(10, 2);
+        let mut reader = app
+            .world
+            .resource::<Events<FirstFrameRendered>>()
+            .get_reader();
+        let mut sent = |app: &App| reader.read(app.world.resource()).count();
+
+        // The first frame is drawn in the first update, even though there's nothing in it
+        assert_eq!(sent(&app), 1);
+        assert!(output.writes() > 0);
+        testing::spawn_sprite(&mut app, "more", 0, 0);
+        for _ in 0..3 {
+            app.update();
+            assert_eq!(sent(&app), 0);
+        }
+        assert!(output.take().contains("more"));
+    }
+
+    #[test]
+    fn covering_half_a_wide_grapheme_blanks_the_other_half() {
+        let (mut app, output) = testing::app(8, 1);
+        testing::spawn_sprite(&mut app, "漢  字", 0, 0);
+        let second_half = testing::spawn_sprite(&mut app, "x", 1, 0);
+        let first_half = testing::spawn_sprite(&mut app, "y", 4, 0);
+        for entity in [second_half, first_half] {
+            app.world.get_mut::<Position>(entity).unwrap().z = 1;
+        }
+        app.update();
+
+        assert_eq!(testing::screen(&app), [" x  y"]);
+        let frame = app.world.resource::<CellBuffer>();
+        for x in 0..8 {
+            let cell = frame.get(x, 0).unwrap();
+            assert!(!frame.is_wide(x, 0) && !cell.is_continuation(), "{x}");
+        }
+        let written = output.take();
+        assert!(
+            !written.contains('漢') && !written.contains('字'),
+            "{written:?}"
+        );
+
+        // Once it's uncovered, the whole grapheme is printed again from its first half
+        app.world.despawn(second_half);
+        output.take();
+        app.update();
+        assert_eq!(testing::screen(&app), ["漢  y"]);
+        let written = output.take();
+        assert!(written.contains("\x1b[1;1H\x1b[0m漢"), "{written:?}");
+    }
+
+    #[test]
+    fn solid_stylemaps_cover_sprites_of_any_size() {
+        use crossterm::style::Color;
+
+        let (mut app, _) = testing::app(10, 4);
+        let solid = || StyleMap::solid(Color::Yellow, Color::DarkBlue);
+        let small = testing::spawn_styled(&mut app, "ab", solid(), 0, 0);
+        testing::spawn_styled(&mut app, "wide\nsprite\nrows", solid(), 4, 1);
+        app.update();
+        let colored = |app: &App| {
+            let frame = app.world.resource::<CellBuffer>();
+            let colors = Colors::new(Color::Yellow, Color::DarkBlue);
+            (0..4)
+                .map(|y| {
+                    (0..10)
+                        .map(|x| match frame.get(x, y).unwrap().style.colors == colors {
+                            true => '#',
+                            false => '.',
+                        })
+                        .collect::<String>()
+                })
+                .collect::<Vec<_>>()
+        };
+        // Short lines are padded out, and the padding is colored too
+        assert_eq!(
+            colored(&app),
+            ["##........", "....######", "....######", "....######"]
+        );
+
+        // Growing the sprite doesn't need a new stylemap
+        let handle = app.world.get::<Handle<Sprite>>(small).unwrap().clone();
+        let mut sprites = app.world.resource_mut::<Assets<Sprite>>();
+        sprites.get_mut(&handle).unwrap().update("abc\nd");
+        app.update();
+        assert_eq!(
+            colored(&app),
+            ["###.......", "###.######", "....######", "....######"]
+        );
+        let errors = app.world.resource::<Events<CrosstermRenderError>>();
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn debug_overlay_draws_rulers_and_a_grid_on_top() {
+        use crossterm::style::Attribute;
+
+        let (mut app, _) = testing::app(12, 3);
+        testing::spawn_sprite(&mut app, "ab", 3, 2);
+        app.insert_resource(DebugOverlay {
+            grid: std::num::NonZeroU16::new(4),
+            ..default()
+        });
+        app.update();
+
+        // Grid lines don't cover the sprite
+        assert_eq!(
+            testing::screen(&app),
+            ["012345678901", "1   │   │", "2  ab   │"]
+        );
+        let frame = app.world.resource::<CellBuffer>();
+        let attributes = |x| frame.get(x, 0).unwrap().style.attributes;
+        assert!(attributes(0).has(Attribute::Bold) && attributes(10).has(Attribute::Bold));
+        assert!(!attributes(5).has(Attribute::Bold) && attributes(5).has(Attribute::Reverse));
+
+        app.world.resource_mut::<DebugOverlay>().toggle();
+        app.update();
+        assert_eq!(testing::screen(&app), ["", "", "   ab"]);
+    }
+
+    #[test]
+    fn mismatched_stylemaps_skip_only_their_entity() {
+        let (mut app, _) = testing::app(10, 4);
+        let style = Style::default();
+        let too_tall = StyleMap::new(style, vec![vec![style; 3]; 3]);
+        let broken = testing::spawn_styled(&mut app, "bad", too_tall, 0, 1);
+        testing::spawn_sprite(&mut app, "good", 0, 0);
+        app.update();
+
+        assert_eq!(testing::screen(&app), ["good", "", "", ""]);
+        let errors = app.world.resource::<Events<CrosstermRenderError>>();
+        let mut reader = errors.get_reader();
+        let errors: Vec<_> = reader.read(errors).collect();
+        assert!(
+            matches!(
+                errors[..],
+                [CrosstermRenderError::StyleMapMismatch {
+                    entity,
+                    sprite_size: (3, 1),
+                    stylemap_size: (3, 3),
+                }] if *entity == broken
+            ),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn half_reloaded_entities_are_clipped_until_the_other_half_arrives() {
+        // The files are only there for this test, even if it fails
+        struct Cleanup(std::path::PathBuf);
+        impl Drop for Cleanup {
+            fn drop(&mut self) {
+                for extension in ["txt", "stylemap"] {
+                    let _ = std::fs::remove_file(self.0.with_extension(extension));
+                }
+            }
+        }
+
+        let name = format!("fixtures/reload_{}", std::process::id());
+        let (sprite_path, stylemap_path) = (format!("{name}.txt"), format!("{name}.stylemap"));
+        let assets = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+        let _cleanup = Cleanup(assets.join(&name));
+        let write =
+            |path: &str, contents: String| std::fs::write(assets.join(path), contents).unwrap();
+        let blue = Style::with_bg(components::Color::Blue);
+        let stylemap = |width, height| {
+            let stylemap = StyleMap::new(Style::default(), vec![vec![blue; width]; height]);
+            ron::to_string(&stylemap).unwrap()
+        };
+        let background = |app: &App, x| {
+            let frame = app.world.resource::<CellBuffer>();
+            frame.get(x, 0).unwrap().style.colors.background
+        };
+        // Loading happens on another thread, so keep updating until it's done. The entity mustn't
+        // disappear in the meantime
+        let wait_for = |app: &mut App, done: &dyn Fn(&App) -> bool| {
+            for _ in 0..200 {
+                app.update();
+                if done(app) {
+                    return;
+                }
+                assert_ne!(testing::screen(app)[0], "");
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            panic!("the reload didn't arrive");
+        };
+
+        write(&sprite_path, "ab\ncd".to_string());
+        write(&stylemap_path, stylemap(2, 2));
+        let (mut app, _) = testing::app(10, 2);
+        let server = app.world.resource::<AssetServer>();
+        let bundle = components::SpriteBundle {
+            sprite: server.load(sprite_path.clone()),
+            stylemap: server.load(stylemap_path.clone()),
+            ..default()
+        };
+        let entity = app.world.spawn(bundle).id();
+        for _ in 0..200 {
+            app.update();
+            if testing::screen(&app) == ["ab", "cd"] {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(testing::screen(&app), ["ab", "cd"]);
+        let mut reader = app
+            .world
+            .resource::<Events<CrosstermRenderError>>()
+            .get_reader();
+
+        // The sprite is reloaded with one row first, so its stylemap is a row too tall for a while
+        write(&sprite_path, "xyz".to_string());
+        app.world
+            .resource::<AssetServer>()
+            .reload(sprite_path.clone());
+        wait_for(&mut app, &|app| testing::screen(app)[0] == "xyz");
+        assert_eq!(testing::screen(&app), ["xyz", ""]);
+        assert_eq!(background(&app, 1), Some(components::Color::Blue));
+        assert_ne!(background(&app, 2), Some(components::Color::Blue));
+
+        write(&stylemap_path, stylemap(3, 1));
+        app.world
+            .resource::<AssetServer>()
+            .reload(stylemap_path.clone());
+        wait_for(&mut app, &|app| {
+            background(app, 2) == Some(components::Color::Blue)
+        });
+        assert_eq!(testing::screen(&app), ["xyz", ""]);
+        let errors = app.world.resource::<Events<CrosstermRenderError>>();
+        assert_eq!(reader.read(errors).count(), 0);
+
+        // If the other half never comes, it's reported once the grace period is over
+        write(&stylemap_path, stylemap(3, 2));
+        app.world
+            .resource::<AssetServer>()
+            .reload(stylemap_path.clone());
+        let stylemap = app.world.get::<Handle<StyleMap>>(entity).unwrap().clone();
+        wait_for(&mut app, &|app| {
+            let stylemaps = app.world.resource::<Assets<StyleMap>>();
+            stylemaps.get(&stylemap).unwrap().map.len() == 2
+        });
+        let mut errors = Vec::new();
+        for frame in 1..RELOAD_GRACE_FRAMES + 2 {
+            app.update();
+            let events = app.world.resource::<Events<CrosstermRenderError>>();
+            errors.extend(
+                reader
+                    .read(events)
+                    .map(|error| (frame, format!("{error:?}"))),
+            );
+        }
+        assert!(
+            matches!(&errors[..], [(frame, error)]
+                if *frame >= RELOAD_GRACE_FRAMES - 1 && error.starts_with("StyleMapMismatch")),
+            "{errors:?}"
+        );
+
+        // Changing an asset at runtime isn't a reload, so a mismatch is reported straight away
+        let style = Style::default();
+        let two_rows = StyleMap::new(style, vec![vec![style; 2]; 2]);
+        let generated = testing::spawn_styled(&mut app, "ab\ncd", two_rows, 0, 1);
+        app.update();
+        let sprite = app.world.get::<Handle<Sprite>>(generated).unwrap().clone();
+        let mut sprites = app.world.resource_mut::<Assets<Sprite>>();
+        *sprites.get_mut(&sprite).unwrap() = Sprite::new("xy");
+        app.update();
+        let events = app.world.resource::<Events<CrosstermRenderError>>();
+        assert!(reader.read(events).any(|error| matches!(error,
+            CrosstermRenderError::StyleMapMismatch { entity, .. } if *entity == generated)));
+    }
+
+    #[test]
+    fn mirror_file_holds_the_latest_frame() {
+        let path =
+            std::env::temp_dir().join(format!("bevy_crossterm_mirror_{}", std::process::id()));
+        let mirror = path.clone();
+        let (mut app, _) = testing::app_with(6, 2, |settings| {
+            settings.mirror_text_to(mirror);
+        });
+        let entity = testing::spawn_sprite(&mut app, "hi", 1, 0);
+        app.update();
+        let read = || std::fs::read_to_string(&path).unwrap();
+        assert_eq!(read(), " hi   \n      \n");
+        assert_eq!(read(), app.world.resource::<CellBuffer>().to_text());
+
+        app.world.get_mut::<Position>(entity).unwrap().y = 1;
+        app.update();
+        assert_eq!(read(), "      \n hi   \n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn children_are_drawn_relative_to_their_parents() {
+        let (mut app, _) = testing::app(12, 6);
+        let dialog = testing::spawn_sprite(&mut app, "[", 1, 1);
+        let label = testing::spawn_sprite(&mut app, "a", 2, 0);
+        let button = testing::spawn_sprite(&mut app, "b", 1, 1);
+        app.world.entity_mut(dialog).add_child(label);
+        app.world.entity_mut(label).add_child(button);
+        app.update();
+        assert_eq!(testing::screen(&app)[1..3], [" [ a", "    b"]);
+
+        // Moving the parent moves everything under it, and children still move on their own
+        app.world.get_mut::<Position>(dialog).unwrap().x = 5;
+        app.update();
+        assert_eq!(testing::screen(&app)[1..3], ["     [ a", "        b"]);
+        app.world.get_mut::<Position>(button).unwrap().x = 2;
+        app.update();
+        assert_eq!(testing::screen(&app)[2], "         b");
+
+        // Clamping keeps where the child is drawn on screen, by moving it relative to its parent
+        app.world
+            .entity_mut(button)
+            .insert(components::ClampToWindow::Fully);
+        app.world.get_mut::<Position>(button).unwrap().x = 10;
+        app.update();
+        assert_eq!(testing::position(&app, button), (4, 1));
+        assert_eq!(testing::screen(&app)[2], "           b");
+    }
+
+    #[test]
+    fn sprites_off_the_screen_are_culled() {
+        let (mut app, _) = testing::app_with(10, 4, |settings| {
+            settings.set_safe_area(0, 1, 0, 0);
+        });
+        testing::spawn_sprite(&mut app, "in", 2, 1);
+        testing::spawn_sprite(&mut app, "right", 10, 0);
+        testing::spawn_sprite(&mut app, "below the safe area", 0, 3);
+        // Wide glyphs count as both of their cells, so only the first of these is entirely off the left
+        testing::spawn_sprite(&mut app, "漢", -2, 0);
+        testing::spawn_sprite(&mut app, "字", -1, 2);
+        app.update();
+
+        let stats = app.world.resource::<RenderStats>();
+        assert_eq!((stats.drawn, stats.culled, stats.rasterized), (2, 3, 2));
+        assert_eq!(testing::screen(&app)[1], "  in");
+    }
+
+    #[test]
+    fn moving_sprites_are_only_laid_out_once() {
+        let (mut app, _) = testing::app(20, 3);
+        let entity = testing::spawn_sprite(&mut app, "漢字 and text", 0, 0);
+        let mut rasterized = Vec::new();
+        for x in 0..8 {
+            app.world.get_mut::<Position>(entity).unwrap().x = x;
+            app.update();
+            rasterized.push(app.world.resource::<RenderStats>().rasterized);
+        }
+        assert_eq!(rasterized, [1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(testing::screen(&app)[0], "       漢字 and text");
+
+        // Until the sprite is changed
+        let handle = app.world.get::<Handle<Sprite>>(entity).unwrap().clone();
+        let mut sprites = app.world.resource_mut::<Assets<Sprite>>();
+        sprites.get_mut(&handle).unwrap().update("changed");
+        app.update();
+        app.update();
+        assert_eq!(app.world.resource::<RenderStats>().rasterized, 1);
+        assert_eq!(testing::screen(&app)[0], "       changed");
+    }
+
+    #[test]
+    fn pausing_freezes_animations_where_they_are() {
+        let (mut app, _) = testing::app(10, 3);
+        let entity = testing::spawn_sprite(&mut app, "", 0, 0);
+        let sheet = app
+            .world
+            .resource_mut::<Assets<Sprite>>()
+            .add(Sprite::new("abcd"));
+        let frames = (0..4).map(|x| URect::new(x, 0, x + 1, 1)).collect();
+        let animation = components::AnimatedSprite::from_sheet(
+            sheet,
+            frames,
+            std::time::Duration::from_secs_f32(0.25),
+        );
+        app.world
+            .entity_mut(entity)
+            .insert((animation, components::Velocity::new(4.0, 0.0)));
+        let state = |app: &App| {
+            let animation = app.world.get::<components::AnimatedSprite>(entity).unwrap();
+            (animation.frame(), testing::position(app, entity).0)
+        };
+
+        testing::step(&mut app, 0.25);
+        assert_eq!(state(&app), (1, 1));
+
+        app.world.resource_mut::<crate::CrosstermPaused>().toggle();
+        for _ in 0..4 {
+            testing::step(&mut app, 0.25);
+        }
+        assert_eq!(state(&app), (1, 1));
+        assert_eq!(testing::screen(&app)[0], " b");
+
+        // Time spent paused doesn't count once it's unpaused
+        app.world.resource_mut::<crate::CrosstermPaused>().toggle();
+        testing::step(&mut app, 0.25);
+        assert_eq!(state(&app), (2, 2));
+    }
+
+    #[test]
+    fn blink_phases_keep_sprites_out_of_step() {
+        let (mut app, _) = testing::app(4, 1);
+        let second = std::time::Duration::from_secs(1);
+        let a = testing::spawn_sprite(&mut app, "a", 0, 0);
+        let b = testing::spawn_sprite(&mut app, "b", 2, 0);
+        app.world
+            .entity_mut(a)
+            .insert(components::SoftBlink::new(second));
+        app.world
+            .entity_mut(b)
+            .insert(components::SoftBlink::new(second).with_phase_offset(second / 2));
+
+        let mut frames = Vec::new();
+        for _ in 0..4 {
+            testing::step(&mut app, 0.25);
+            frames.push(testing::screen(&app)[0].clone());
+        }
+        assert_eq!(frames, ["a", "  b", "  b", "a"]);
+    }
+
+    #[test]
+    fn sprite_sheet_animations_cycle_through_their_frames() {
+        use crossterm::style::Color;
+
+        let (mut app, _) = testing::app(6, 3);
+        let entity = testing::spawn_sprite(&mut app, "", 0, 0);
+        let sheet = app
+            .world
+            .resource_mut::<Assets<Sprite>>()
+            .add(Sprite::new("ab12\ncd34"));
+        let red = Style::with_fg(Color::Red);
+        let green = Style::with_fg(Color::Green);
+        let row = vec![red, red, green, green];
+        let sheet_styles = StyleMap::new(Style::default(), vec![row.clone(), row]);
+        let sheet_styles = app
+            .world
+            .resource_mut::<Assets<StyleMap>>()
+            .add(sheet_styles);
+        let frames = vec![URect::new(0, 0, 2, 2), URect::new(2, 0, 4, 2)];
+        let animation = components::AnimatedSprite::from_sheet(
+            sheet,
+            frames,
+            std::time::Duration::from_secs_f32(0.25),
+        );
+        app.world
+            .entity_mut(entity)
+            .insert(animation.with_stylemap(sheet_styles));
+
+        let frame = |app: &App| {
+            let buffer = app.world.resource::<CellBuffer>();
+            let foreground = buffer.get(0, 0).unwrap().style.colors.foreground;
+            (testing::screen(app)[..2].to_vec(), foreground)
+        };
+        let first = (vec!["ab".to_string(), "cd".to_string()], Some(Color::Red));
+        let second = (vec!["12".to_string(), "34".to_string()], Some(Color::Green));
+        app.update();
+        assert_eq!(frame(&app), first);
+        testing::step(&mut app, 0.25);
+        assert_eq!(frame(&app), second);
+        testing::step(&mut app, 0.25);
+        assert_eq!(frame(&app), first);
+    }
+
+    #[test]
+    fn underline_colors_are_written_with_sgr_58() {
+        let stylemap: StyleMap = ron::from_str(
+            r#"(
+                default_style: (attributes: 0, colors: (foreground: None, background: None)),
+                map: [[(attributes: 0, colors: (foreground: None, background: None), ul: Some("rgb_(255,0,0)"))]],
+            )"#,
         )
         .unwrap();
+        let (mut app, output) = testing::app(6, 2);
+        testing::spawn_styled(&mut app, "ab", stylemap, 0, 0);
+        app.update();
+
+        let written = output.take();
+        assert!(written.contains("\x1b[58;2;255;0;0ma"), "{written:?}");
+        // Cells without an underline color go back to the terminal's
+        assert!(written.contains("\x1b[59mb"), "{written:?}");
     }
-
-    term.flush().unwrap();
 }
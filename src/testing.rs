@@ -0,0 +1,161 @@
+//! Helpers for running the plugin in tests, without a terminal
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy::window::PrimaryWindow;
+
+use crate::buffer::CellBuffer;
+use crate::components::{Position, Sprite, SpriteBundle, StyleMap};
+use crate::{CrosstermPlugin, CrosstermWindow, CrosstermWindowSettings, CrosstermWriter};
+
+/// Everything written to the terminal by a test app, shared with the app's `CrosstermWriter`
+#[derive(Clone, Default)]
+pub(crate) struct Output {
+    bytes: Arc<Mutex<Vec<u8>>>,
+    writes: Arc<Mutex<usize>>,
+}
+
+impl Output {
+    /// Everything written since the last call, lossily decoded
+    pub(crate) fn take(&self) -> String {
+        let bytes = std::mem::take(&mut *self.bytes.lock().unwrap());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    /// How many times `write` has been called, in total
+    pub(crate) fn writes(&self) -> usize {
+        *self.writes.lock().unwrap()
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.bytes.lock().unwrap().extend_from_slice(buf);
+        *self.writes.lock().unwrap() += 1;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An app with the plugin and a `width` by `height` window that isn't backed by a terminal. Frames are
+/// written to the returned `Output`, and time only moves forward in `step`. Settings inserted with
+/// `settings` are applied before the window is created.
+pub(crate) fn app_with(
+    width: u16,
+    height: u16,
+    settings: impl FnOnce(&mut CrosstermWindowSettings),
+) -> (App, Output) {
+    let mut window_settings = CrosstermWindowSettings::default();
+    settings(&mut window_settings);
+
+    let output = Output::default();
+    let mut app = App::new();
+    app.insert_resource(window_settings)
+        .insert_resource(CrosstermWriter::new(output.clone()))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::ZERO))
+        .add_plugins((
+            MinimalPlugins,
+            AssetPlugin {
+                watch_for_changes_override: Some(false),
+                ..default()
+            },
+            bevy::window::WindowPlugin {
+                primary_window: None,
+                // There's no bevy `Window`, so it would otherwise exit straight away
+                exit_condition: bevy::window::ExitCondition::DontExit,
+                ..default()
+            },
+            CrosstermPlugin,
+        ));
+
+    let window = headless_window(
+        app.world.resource::<CrosstermWindowSettings>(),
+        width,
+        height,
+    );
+    app.world.spawn((window, PrimaryWindow));
+
+    // The first update only starts the clock, so every `step` after it moves time forward
+    app.update();
+    (app, output)
+}
+
+pub(crate) fn app(width: u16, height: u16) -> (App, Output) {
+    app_with(width, height, |_| {})
+}
+
+/// A window as the runner would make it, without touching the terminal
+pub(crate) fn headless_window(
+    settings: &CrosstermWindowSettings,
+    width: u16,
+    height: u16,
+) -> CrosstermWindow {
+    CrosstermWindow {
+        height,
+        width,
+        colors: settings.colors,
+        title: settings.title.clone(),
+        bell_pending: false,
+        full_redraw_pending: false,
+        synchronized_updates: false,
+        hyperlinks: false,
+        shutdown_hooks: settings.shutdown_hooks.clone(),
+        safe_area: settings.safe_area,
+        fixed_canvas: settings.fixed_canvas,
+        headless: true,
+        restored: false,
+    }
+}
+
+/// Runs one update, `seconds` after the last one
+pub(crate) fn step(app: &mut App, seconds: f32) {
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(
+        seconds,
+    )));
+    app.update();
+}
+
+/// Spawns an entity with a sprite made from `data`, and an empty stylemap
+pub(crate) fn spawn_sprite(app: &mut App, data: &str, x: i32, y: i32) -> Entity {
+    spawn_styled(app, data, StyleMap::default(), x, y)
+}
+
+pub(crate) fn spawn_styled(
+    app: &mut App,
+    data: &str,
+    stylemap: StyleMap,
+    x: i32,
+    y: i32,
+) -> Entity {
+    let sprite = app
+        .world
+        .resource_mut::<Assets<Sprite>>()
+        .add(Sprite::new(data));
+    let stylemap = app.world.resource_mut::<Assets<StyleMap>>().add(stylemap);
+    app.world
+        .spawn(SpriteBundle {
+            sprite,
+            stylemap,
+            position: Position::with_xy(x, y),
+            ..default()
+        })
+        .id()
+}
+
+/// The rows of the last frame that was composed, without the blanks at the end of each
+pub(crate) fn screen(app: &App) -> Vec<String> {
+    let text = app.world.resource::<CellBuffer>().to_text();
+    text.lines().map(|row| row.trim_end().to_string()).collect()
+}
+
+pub(crate) fn position(app: &App, entity: Entity) -> (i32, i32) {
+    let pos = app.world.get::<Position>(entity).unwrap();
+    (pos.x, pos.y)
+}
@@ -0,0 +1,787 @@
+//! Ready made components for common things like meters and menus, which draw themselves into their
+//! entity's sprite and stylemap whenever they change.
+
+use bevy::prelude::*;
+use bevy_asset::{AssetEvent, AssetId, Assets, Handle};
+use crossterm::event::{KeyCode, KeyEventKind};
+
+use crate::components::{BorderStyle, Colors, Position, Sprite, Style, StyleMap};
+use crate::input::{ConsumableInput, InputFocus};
+use crate::{CoordinateSpace, CrosstermWindow};
+
+/// Which way a widget is laid out
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug)]
+pub enum Orientation {
+    #[default]
+    Horizontal,
+    Vertical,
+}
+
+/// A bar that fills up as `current` goes from 0 to `max`, like a loading bar or a health meter.
+///
+/// The entity's `Handle<Sprite>` and `Handle<StyleMap>` are replaced with ones the bar draws itself
+/// into, so spawn it with a default `SpriteBundle`. Horizontal bars fill from left to right, vertical
+/// bars fill from the bottom up.
+#[derive(Debug, Component)]
+pub struct ProgressBar {
+    pub current: f32,
+    pub max: f32,
+    /// How many cells long the bar is
+    pub length: u16,
+    pub filled: char,
+    pub empty: char,
+    /// Colors for the filled part. Colors left unset use the window's colors
+    pub filled_colors: Colors,
+    /// Colors for the empty part. Colors left unset use the window's colors
+    pub empty_colors: Colors,
+    pub orientation: Orientation,
+    /// Shows the percentage in the middle of the bar. Vertical bars are only one cell wide, so
+    /// they never show it.
+    pub show_label: bool,
+    sprite: Option<Handle<Sprite>>,
+    stylemap: Option<Handle<StyleMap>>,
+}
+
+impl ProgressBar {
+    pub fn new(current: f32, max: f32, length: u16) -> ProgressBar {
+        ProgressBar {
+            current,
+            max,
+            length,
+            filled: '█',
+            empty: '░',
+            filled_colors: Colors::default(),
+            empty_colors: Colors::default(),
+            orientation: Orientation::Horizontal,
+            show_label: false,
+            sprite: None,
+            stylemap: None,
+        }
+    }
+
+    pub fn with_chars(mut self, filled: char, empty: char) -> ProgressBar {
+        self.filled = filled;
+        self.empty = empty;
+        self
+    }
+
+    pub fn with_colors(mut self, filled: Colors, empty: Colors) -> ProgressBar {
+        self.filled_colors = filled;
+        self.empty_colors = empty;
+        self
+    }
+
+    pub fn with_orientation(mut self, orientation: Orientation) -> ProgressBar {
+        self.orientation = orientation;
+        self
+    }
+
+    pub fn with_label(mut self) -> ProgressBar {
+        self.show_label = true;
+        self
+    }
+
+    /// How full the bar is, from 0.0 to 1.0
+    pub fn progress(&self) -> f32 {
+        if self.max > 0.0 {
+            (self.current / self.max).clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+
+    /// How many cells of the bar are filled
+    pub fn filled_cells(&self) -> usize {
+        (self.progress() * self.length as f32).floor() as usize
+    }
+
+    /// The bar's cells from start to end, along with whether each one is filled
+    fn cells(&self) -> Vec<(char, bool)> {
+        let length = self.length as usize;
+        let filled = self.filled_cells();
+        let mut cells: Vec<(char, bool)> = (0..length)
+            .map(|idx| {
+                if idx < filled {
+                    (self.filled, true)
+                } else {
+                    (self.empty, false)
+                }
+            })
+            .collect();
+
+        if self.show_label && self.orientation == Orientation::Horizontal {
+            let label = format!("{}%", (self.progress() * 100.0).round() as u32);
+            let label_len = label.chars().count();
+            if label_len <= length {
+                let start = (length - label_len) / 2;
+                for (idx, c) in label.chars().enumerate() {
+                    cells[start + idx].0 = c;
+                }
+            }
+        }
+
+        cells
+    }
+
+    fn style(&self, filled: bool) -> Style {
+        if filled {
+            Style::with_colors(self.filled_colors)
+        } else {
+            Style::with_colors(self.empty_colors)
+        }
+    }
+
+    /// Draws the bar into a sprite and a stylemap which covers it
+    fn render(&self) -> (String, StyleMap) {
+        let cells = self.cells();
+        match self.orientation {
+            Orientation::Horizontal => {
+                let data = cells.iter().map(|(c, _)| c).collect();
+                let row = cells
+                    .iter()
+                    .map(|(_, filled)| self.style(*filled))
+                    .collect();
+                (data, StyleMap::new(Style::default(), vec![row]))
+            }
+            Orientation::Vertical => {
+                let mut data = String::new();
+                let mut map = Vec::new();
+                for (idx, (c, filled)) in cells.iter().rev().enumerate() {
+                    if idx > 0 {
+                        data.push('\n');
+                    }
+                    data.push(*c);
+                    map.push(vec![self.style(*filled)]);
+                }
+                (data, StyleMap::new(Style::default(), map))
+            }
+        }
+    }
+}
+
+/// Progress bars which need to be drawn again
+type ChangedBar = Changed<ProgressBar>;
+
+/// Redraws every progress bar that changed
+pub(crate) fn update_progress_bars(
+    mut sprites: ResMut<Assets<Sprite>>,
+    mut stylemaps: ResMut<Assets<StyleMap>>,
+    mut bars: Query<(&mut ProgressBar, &mut Handle<Sprite>, &mut Handle<StyleMap>), ChangedBar>,
+) {
+    for (mut bar, mut sprite_handle, mut stylemap_handle) in &mut bars {
+        let (data, stylemap) = bar.render();
+
+        // Keeping track of the assets isn't a change to the bar, so it shouldn't cause another redraw
+        let bar = bar.bypass_change_detection();
+        match bar
+            .sprite
+            .as_ref()
+            .and_then(|handle| sprites.get_mut(handle))
+        {
+            Some(sprite) => sprite.update(data),
+            None => bar.sprite = Some(sprites.add(Sprite::new(data))),
+        }
+        match bar
+            .stylemap
+            .as_ref()
+            .and_then(|handle| stylemaps.get_mut(handle))
+        {
+            Some(existing) => *existing = stylemap,
+            None => bar.stylemap = Some(stylemaps.add(stylemap)),
+        }
+
+        if bar.sprite.as_ref() != Some(&*sprite_handle) {
+            *sprite_handle = bar.sprite.clone().unwrap();
+        }
+        if bar.stylemap.as_ref() != Some(&*stylemap_handle) {
+            *stylemap_handle = bar.stylemap.clone().unwrap();
+        }
+    }
+}
+
+/// Marks an entity as one of the entries of a menu. The entry whose `index` matches the `Selection`
+/// is highlighted.
+#[derive(Debug, Default, Component)]
+pub struct Selectable {
+    pub index: usize,
+    // The entity's own stylemap, which is put back when it stops being selected
+    original: Option<Handle<StyleMap>>,
+    // A copy of the entity's own stylemap with the highlight laid over it, kept for the next time
+    // the entry is selected
+    highlighted: Option<Handle<StyleMap>>,
+}
+
+impl Selectable {
+    pub fn new(index: usize) -> Selectable {
+        Selectable {
+            index,
+            original: None,
+            highlighted: None,
+        }
+    }
+}
+
+/// Which entry of a menu is selected. Insert this resource to have the selected `Selectable` entity
+/// drawn with the `highlight` style laid over its own stylemap, until another entry is selected.
+/// The colors the highlight sets replace the entry's, and its attributes are added to the entry's,
+/// so reverse video on a red entry turns it into red highlighting.
+#[derive(Debug, Resource)]
+pub struct Selection {
+    selected: usize,
+    pub highlight: Style,
+    // The number of entries there were the last time the highlight was updated
+    len: usize,
+}
+
+impl Default for Selection {
+    /// Highlights the selected entry in reverse video
+    fn default() -> Self {
+        Selection::new(Style::with_attrib(crossterm::style::Attribute::Reverse))
+    }
+}
+
+impl Selection {
+    pub fn new(highlight: Style) -> Selection {
+        Selection {
+            selected: 0,
+            highlight,
+            len: 0,
+        }
+    }
+
+    /// The index of the selected entry
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn select(&mut self, index: usize) -> &mut Self {
+        self.selected = index;
+        self
+    }
+
+    /// Selects the next entry, going back to the first one after the last
+    pub fn select_next(&mut self) -> &mut Self {
+        self.selected = match self.len {
+            0 => 0,
+            len => (self.selected + 1) % len,
+        };
+        self
+    }
+
+    /// Selects the previous entry, going round to the last one before the first
+    pub fn select_previous(&mut self) -> &mut Self {
+        self.selected = match (self.selected, self.len) {
+            (_, 0) => 0,
+            (0, len) => len - 1,
+            (selected, len) => std::cmp::min(selected, len) - 1,
+        };
+        self
+    }
+}
+
+/// Highlights the selected menu entry, and puts back the stylemaps of entries which aren't selected
+/// anymore
+pub(crate) fn update_selection(
+    mut selection: ResMut<Selection>,
+    mut stylemaps: ResMut<Assets<StyleMap>>,
+    mut stylemap_events: EventReader<AssetEvent<StyleMap>>,
+    mut entries: Query<(&mut Selectable, &mut Handle<StyleMap>)>,
+) {
+    // The highlighted copy of an entry's stylemap has to be made again when the stylemap changes
+    let changed: bevy::utils::HashSet<AssetId<StyleMap>> = stylemap_events
+        .read()
+        .map(|event| {
+            let (AssetEvent::Added { id }
+            | AssetEvent::Modified { id }
+            | AssetEvent::Removed { id }
+            | AssetEvent::LoadedWithDependencies { id }) = event;
+            *id
+        })
+        .collect();
+
+    // Counting the entries isn't a change to the selection
+    let len = entries.iter().count();
+    if selection.len != len {
+        selection.bypass_change_detection().len = len;
+    }
+
+    for (mut entry, mut stylemap_handle) in &mut entries {
+        let is_highlighted = entry.highlighted.as_ref() == Some(&*stylemap_handle);
+        if entry.index == selection.selected() {
+            if !is_highlighted {
+                entry.original = Some(stylemap_handle.clone());
+            }
+
+            let original = entry.original.clone().unwrap_or_default();
+            if !is_highlighted || selection.is_changed() || changed.contains(&original.id()) {
+                let stylemap = highlight_stylemap(stylemaps.get(&original), selection.highlight);
+                match entry
+                    .highlighted
+                    .as_ref()
+                    .and_then(|handle| stylemaps.get_mut(handle))
+                {
+                    Some(existing) => *existing = stylemap,
+                    None => entry.highlighted = Some(stylemaps.add(stylemap)),
+                }
+            }
+
+            if !is_highlighted {
+                *stylemap_handle = entry.highlighted.clone().unwrap();
+            }
+        } else if is_highlighted {
+            *stylemap_handle = entry.original.take().unwrap_or_default();
+        }
+    }
+}
+
+/// A copy of a stylemap with `highlight` laid over every cell. A stylemap which hasn't loaded yet is
+/// treated as an empty one.
+fn highlight_stylemap(stylemap: Option<&StyleMap>, highlight: Style) -> StyleMap {
+    let over = |style: &Style| {
+        let mut attributes = style.attributes;
+        attributes.extend(highlight.attributes);
+        Style {
+            colors: highlight.colors.with_default(style.colors),
+            attributes,
+            underline_color: highlight.underline_color.or(style.underline_color),
+        }
+    };
+
+    match stylemap {
+        Some(stylemap) => StyleMap::new(
+            over(&stylemap.style),
+            stylemap
+                .map
+                .iter()
+                .map(|row| row.iter().map(over).collect())
+                .collect(),
+        ),
+        None => StyleMap::new(highlight, vec![]),
+    }
+}
+
+/// A yes or no question in a bordered box, kept in the middle of the window.
+///
+/// Like `ProgressBar`, the dialog draws itself into its entity's sprite and stylemap, so spawn it
+/// with a default `SpriteBundle`, and a `Position` with a high enough z to be drawn over the game.
+/// The dialog takes `InputFocus` when it's spawned and consumes every key event while it has focus,
+/// so systems which only read `ConsumableInput` don't see the keys meant for it. Left, right and tab
+/// move between the options, enter picks the selected one and escape answers no. Once answered, a
+/// `ConfirmResult` is sent and the dialog's entity is despawned.
+#[derive(Debug, Component)]
+pub struct ConfirmDialog {
+    /// The question, which can be several lines long
+    pub message: String,
+    pub yes_label: String,
+    pub no_label: String,
+    pub border: BorderStyle,
+    /// The style the selected option is drawn with, reverse video by default like `Selection`
+    pub highlight: Style,
+    yes_selected: bool,
+    sprite: Option<Handle<Sprite>>,
+    stylemap: Option<Handle<StyleMap>>,
+}
+
+impl ConfirmDialog {
+    /// A dialog with "Yes" and "No" options, with "No" selected
+    pub fn new(message: impl Into<String>) -> ConfirmDialog {
+        ConfirmDialog {
+            message: message.into(),
+            yes_label: "Yes".to_string(),
+            no_label: "No".to_string(),
+            border: BorderStyle::default(),
+            highlight: Selection::default().highlight,
+            yes_selected: false,
+            sprite: None,
+            stylemap: None,
+        }
+    }
+
+    pub fn with_labels(mut self, yes: impl Into<String>, no: impl Into<String>) -> ConfirmDialog {
+        self.yes_label = yes.into();
+        self.no_label = no.into();
+        self
+    }
+
+    pub fn with_border(mut self, border: BorderStyle) -> ConfirmDialog {
+        self.border = border;
+        self
+    }
+
+    pub fn with_highlight(mut self, highlight: Style) -> ConfirmDialog {
+        self.highlight = highlight;
+        self
+    }
+
+    /// Selects "Yes" instead of "No" when the dialog opens
+    pub fn yes_by_default(mut self) -> ConfirmDialog {
+        self.yes_selected = true;
+        self
+    }
+
+    /// Whether the "Yes" option is the selected one
+    pub fn is_yes_selected(&self) -> bool {
+        self.yes_selected
+    }
+
+    pub fn select(&mut self, yes: bool) -> &mut Self {
+        self.yes_selected = yes;
+        self
+    }
+
+    /// Draws the dialog into a sprite and a stylemap which highlights the selected option
+    fn render(&self) -> (Sprite, StyleMap) {
+        let message = Sprite::new(self.message.as_str());
+        let yes = format!(" {} ", self.yes_label);
+        let no = format!(" {} ", self.no_label);
+        let (yes_width, no_width) = (
+            Sprite::new(yes.as_str()).width(),
+            Sprite::new(no.as_str()).width(),
+        );
+        const GAP: usize = 4;
+
+        // Everything inside the border gets a column of space on either side
+        let buttons_width = yes_width + GAP + no_width;
+        let inner_width = std::cmp::max(message.width(), buttons_width) + 2;
+        let buttons_start = (inner_width - buttons_width) / 2;
+
+        let mut data = String::new();
+        for line in self.message.lines() {
+            data.push(' ');
+            data.push_str(line);
+            data.push('\n');
+        }
+        data.push('\n');
+        data.push_str(&" ".repeat(buttons_start));
+        data.push_str(&yes);
+        data.push_str(&" ".repeat(GAP));
+        data.push_str(&no);
+        data.push_str(&" ".repeat(inner_width - buttons_start - buttons_width));
+        let sprite = Sprite::new(data).bordered(self.border);
+
+        // The buttons are on the last row inside the border
+        let buttons_row = sprite.height() - 2;
+        let selected = match self.yes_selected {
+            true => 1 + buttons_start..1 + buttons_start + yes_width,
+            false => {
+                let start = 1 + buttons_start + yes_width + GAP;
+                start..start + no_width
+            }
+        };
+        let stylemap = StyleMap::from_fn(sprite.width(), sprite.height(), |x, y| {
+            if y == buttons_row && selected.contains(&x) {
+                self.highlight
+            } else {
+                Style::default()
+            }
+        });
+
+        (sprite, stylemap)
+    }
+}
+
+/// Sent when a `ConfirmDialog` is answered
+#[derive(Event, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ConfirmResult {
+    /// The dialog, which has been despawned by the time this is read
+    pub entity: Entity,
+    /// Whether the answer was yes
+    pub confirmed: bool,
+}
+
+/// Gives new dialogs focus, and answers the focused dialog when enter or escape is pressed
+pub(crate) fn handle_confirm_dialogs(
+    mut commands: Commands,
+    mut focus: ResMut<InputFocus>,
+    mut input: ResMut<ConsumableInput>,
+    mut results: EventWriter<ConfirmResult>,
+    mut removed: RemovedComponents<ConfirmDialog>,
+    mut dialogs: Query<(Entity, &mut ConfirmDialog)>,
+) {
+    for entity in removed.read() {
+        focus.remove(entity);
+    }
+
+    for (entity, mut dialog) in &mut dialogs {
+        if dialog.is_added() {
+            focus.push(entity);
+        }
+
+        for key in focus.consume_keys(entity, &mut input) {
+            if key.kind == KeyEventKind::Release {
+                continue;
+            }
+            let answer = match key.code {
+                KeyCode::Left | KeyCode::Right | KeyCode::Tab | KeyCode::BackTab => {
+                    let yes = !dialog.is_yes_selected();
+                    dialog.select(yes);
+                    None
+                }
+                KeyCode::Enter => Some(dialog.is_yes_selected()),
+                KeyCode::Esc => Some(false),
+                _ => None,
+            };
+
+            if let Some(confirmed) = answer {
+                results.send(ConfirmResult { entity, confirmed });
+                focus.remove(entity);
+                commands.entity(entity).despawn();
+                break;
+            }
+        }
+    }
+}
+
+/// Redraws every dialog that changed, and keeps them all in the middle of the window
+pub(crate) fn update_confirm_dialogs(
+    window: Query<&CrosstermWindow>,
+    space: Option<Res<CoordinateSpace>>,
+    mut sprites: ResMut<Assets<Sprite>>,
+    mut stylemaps: ResMut<Assets<StyleMap>>,
+    mut dialogs: Query<(
+        &mut ConfirmDialog,
+        &mut Handle<Sprite>,
+        &mut Handle<StyleMap>,
+        &mut Position,
+    )>,
+) {
+    let window = window.single();
+    let space = space.as_deref().copied().unwrap_or_default();
+    for (mut dialog, mut sprite_handle, mut stylemap_handle, mut pos) in &mut dialogs {
+        if dialog.is_changed() {
+            let (sprite, stylemap) = dialog.render();
+
+            // Keeping track of the assets isn't a change to the dialog, so it shouldn't cause another redraw
+            let dialog = dialog.bypass_change_detection();
+            match dialog
+                .sprite
+                .as_ref()
+                .and_then(|handle| sprites.get_mut(handle))
+            {
+                Some(existing) => *existing = sprite,
+                None => dialog.sprite = Some(sprites.add(sprite)),
+            }
+            match dialog
+                .stylemap
+                .as_ref()
+                .and_then(|handle| stylemaps.get_mut(handle))
+            {
+                Some(existing) => *existing = stylemap,
+                None => dialog.stylemap = Some(stylemaps.add(stylemap)),
+            }
+
+            if dialog.sprite.as_ref() != Some(&*sprite_handle) {
+                *sprite_handle = dialog.sprite.clone().unwrap();
+            }
+            if dialog.stylemap.as_ref() != Some(&*stylemap_handle) {
+                *stylemap_handle = dialog.stylemap.clone().unwrap();
+            }
+        }
+
+        let Some(sprite) = sprites.get(&*sprite_handle) else {
+            continue;
+        };
+        let x = window.x_center() as i32 - sprite.width() as i32 / 2;
+        let y = window.y_center() as i32 - sprite.height() as i32 / 2;
+        let (x, y) = space.from_screen(x, y, window);
+
+        // Only write to the position if it actually changes, otherwise it would be redrawn every frame
+        if pos.x != x || pos.y != y {
+            pos.x = x;
+            pos.y = y;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{Color, SpriteBundle};
+    use crate::testing;
+
+    fn spawn_bar(app: &mut App, bar: ProgressBar, y: i32) -> Entity {
+        let bundle = SpriteBundle {
+            position: Position::with_xy(0, y),
+            ..default()
+        };
+        app.world.spawn((bundle, bar)).id()
+    }
+
+    #[test]
+    fn half_full_bars_fill_half_their_cells() {
+        let (mut app, _) = testing::app(12, 8);
+        let colors = Colors::new(Color::Green, Color::Black);
+        let bar = || {
+            ProgressBar::new(50.0, 100.0, 10)
+                .with_chars('#', '-')
+                .with_colors(colors, Colors::default())
+        };
+        assert_eq!(bar().filled_cells(), 5);
+        let entity = spawn_bar(&mut app, bar(), 0);
+        spawn_bar(&mut app, bar().with_label(), 1);
+        app.update();
+
+        let screen = testing::screen(&app);
+        assert_eq!(screen[0], "#####-----");
+        assert_eq!(screen[1], "###50%----");
+        let frame = app.world.resource::<crate::buffer::CellBuffer>();
+        assert_eq!(frame.get(4, 0).unwrap().style.colors, colors);
+        assert_ne!(frame.get(5, 0).unwrap().style.colors, colors);
+
+        app.world.get_mut::<ProgressBar>(entity).unwrap().current = 100.0;
+        app.update();
+        assert_eq!(testing::screen(&app)[0], "##########");
+    }
+
+    #[test]
+    fn vertical_bars_fill_from_the_bottom() {
+        let (mut app, _) = testing::app(4, 6);
+        let bar = ProgressBar::new(1.0, 4.0, 4)
+            .with_chars('#', '-')
+            .with_orientation(Orientation::Vertical);
+        spawn_bar(&mut app, bar, 1);
+        app.update();
+        assert_eq!(testing::screen(&app)[1..5], ["-", "-", "-", "#"]);
+    }
+
+    #[test]
+    fn only_the_selected_entry_is_highlighted() {
+        use crossterm::style::Attribute;
+
+        let (mut app, _) = testing::app(10, 4);
+        let red = Colors::new(Color::Red, Color::Black);
+        let entries: Vec<_> = ["new", "load", "quit"]
+            .into_iter()
+            .enumerate()
+            .map(|(idx, text)| {
+                let stylemap = if idx == 1 {
+                    StyleMap::with_colors(red)
+                } else {
+                    StyleMap::default()
+                };
+                let entity = testing::spawn_styled(&mut app, text, stylemap, 0, idx as i32);
+                app.world.entity_mut(entity).insert(Selectable::new(idx));
+                entity
+            })
+            .collect();
+        let originals: Vec<_> = entries
+            .iter()
+            .map(|entity| app.world.get::<Handle<StyleMap>>(*entity).unwrap().clone())
+            .collect();
+        app.init_resource::<Selection>();
+
+        let highlighted = |app: &App| {
+            let frame = app.world.resource::<crate::buffer::CellBuffer>();
+            (0..3)
+                .map(|y| {
+                    frame
+                        .get(0, y)
+                        .unwrap()
+                        .style
+                        .attributes
+                        .has(Attribute::Reverse)
+                })
+                .collect::<Vec<_>>()
+        };
+        app.update();
+        assert_eq!(highlighted(&app), [true, false, false]);
+
+        app.world.resource_mut::<Selection>().select_next();
+        app.update();
+        assert_eq!(highlighted(&app), [false, true, false]);
+        // The highlight goes over the entry's own colors
+        let frame = app.world.resource::<crate::buffer::CellBuffer>();
+        assert_eq!(
+            frame.get(0, 1).unwrap().style.colors.foreground,
+            Some(Color::Red)
+        );
+        // Entries which aren't selected get their own stylemaps back
+        let handle = |entity| app.world.get::<Handle<StyleMap>>(entity).unwrap();
+        assert_eq!(handle(entries[0]), &originals[0]);
+        assert_ne!(handle(entries[1]), &originals[1]);
+
+        app.world
+            .resource_mut::<Selection>()
+            .select_previous()
+            .select_previous();
+        app.update();
+        assert_eq!(highlighted(&app), [false, false, true]);
+    }
+
+    #[test]
+    fn dialogs_take_the_keys_until_they_are_answered() {
+        use bevy::ecs::event::ManualEventReader;
+        use crossterm::event::{KeyEvent, KeyModifiers};
+        use crossterm::style::Attribute;
+
+        #[derive(Resource, Default)]
+        struct GameKeys(Vec<KeyCode>);
+
+        let (mut app, _) = testing::app(21, 7);
+        app.init_resource::<GameKeys>().add_systems(
+            Update,
+            |input: Res<ConsumableInput>, mut seen: ResMut<GameKeys>| {
+                seen.0.extend(input.keys().map(|key| key.code));
+            },
+        );
+        let menu = app.world.spawn_empty().id();
+        app.world.resource_mut::<InputFocus>().push(menu);
+        let press = |app: &mut App, code| {
+            let key = KeyEvent::new(code, KeyModifiers::NONE);
+            app.world.send_event(crate::CrosstermKeyEventWrapper(key));
+            app.update();
+        };
+        let highlighted = |app: &App| {
+            let frame = app.world.resource::<crate::buffer::CellBuffer>();
+            let mut text = String::new();
+            for y in 0..frame.height() as i32 {
+                for x in 0..frame.width() as i32 {
+                    let cell = frame.get(x, y).unwrap();
+                    if cell.style.attributes.has(Attribute::Reverse) {
+                        text.push_str(&cell.grapheme);
+                    }
+                }
+            }
+            text
+        };
+
+        let bundle = SpriteBundle {
+            position: Position::new(0, 0, 10),
+            ..default()
+        };
+        let dialog = app.world.spawn((bundle, ConfirmDialog::new("Sure?"))).id();
+        app.update();
+        app.update();
+        assert!(app.world.resource::<InputFocus>().is_focused(dialog));
+        let screen = testing::screen(&app);
+        assert_eq!(screen[2], "  │ Sure?         │");
+        assert_eq!(highlighted(&app), " No ");
+
+        press(&mut app, KeyCode::Right);
+        press(&mut app, KeyCode::Char('q'));
+        app.update();
+        assert_eq!(highlighted(&app), " Yes ");
+        assert!(app.world.resource::<GameKeys>().0.is_empty());
+
+        let mut results = ManualEventReader::<ConfirmResult>::default();
+        press(&mut app, KeyCode::Enter);
+        let answers: Vec<_> = results
+            .read(app.world.resource::<Events<ConfirmResult>>())
+            .copied()
+            .collect();
+        assert_eq!(
+            answers,
+            [ConfirmResult {
+                entity: dialog,
+                confirmed: true
+            }]
+        );
+        assert!(app.world.get_entity(dialog).is_none());
+        assert!(app.world.resource::<GameKeys>().0.is_empty());
+
+        // Once the dialog is gone, keys go back to whatever had focus before it
+        assert!(app.world.resource::<InputFocus>().is_focused(menu));
+        press(&mut app, KeyCode::Char('q'));
+        assert_eq!(app.world.resource::<GameKeys>().0, [KeyCode::Char('q')]);
+    }
+}